@@ -12,4 +12,22 @@ db.createCollection('knowledge');
 db.knowledge.createIndex({ "category": 1 });
 db.knowledge.createIndex({ "created_at": -1 });
 
+// Full articles; their embedded chunks live in knowledge
+db.createCollection('documents');
+db.documents.createIndex({ "category": 1 });
+db.knowledge.createIndex({ "parent_id": 1, "chunk_index": 1 });
+db.knowledge.createIndex({ "embedding_model": 1 });
+db.documents.createIndex(
+  { "content_hash": 1 },
+  { unique: true, partialFilterExpression: { "content_hash": { "$type": "string" } } }
+);
+
+// Chat sessions expire through a TTL index on expires_at
+db.createCollection('sessions');
+db.sessions.createIndex({ "expires_at": 1 }, { expireAfterSeconds: 0, name: "sessions_ttl" });
+
+// API keys are looked up by their hash
+db.createCollection('api_keys');
+db.api_keys.createIndex({ "key_hash": 1 }, { unique: true });
+
 print('MongoDB initialization complete!');
@@ -1,8 +1,9 @@
 #!/bin/bash
 # Seed the knowledge base with initial documents
-# Usage: ./seed_knowledge.sh
+# Usage: API_KEY=<key with knowledge:write> ./seed_knowledge.sh
 
 API_URL="${API_URL:-http://localhost:3000}"
+API_KEY="${API_KEY:-$ADMIN_API_KEY}"
 SEED_FILE="data/knowledge_seed.json"
 
 echo "🌱 Seeding knowledge base..."
@@ -25,31 +26,34 @@ if ! command -v curl &> /dev/null; then
     exit 1
 fi
 
+if [ -z "$API_KEY" ]; then
+    echo "Warning: API_KEY is not set; ingestion only works with AUTH_ENABLED=false."
+fi
+
 # Check if seed file exists
 if [ ! -f "$SEED_FILE" ]; then
     echo "Error: Seed file not found: $SEED_FILE"
     exit 1
 fi
 
-# Read and ingest each document
-count=0
-jq -c '.[]' "$SEED_FILE" | while read -r doc; do
-    title=$(echo "$doc" | jq -r '.title')
-    
-    response=$(curl -s -X POST "$API_URL/api/ingest" \
-        -H "Content-Type: application/json" \
-        -d "$doc")
-    
-    success=$(echo "$response" | jq -r '.success')
-    
-    if [ "$success" = "true" ]; then
-        echo "✅ Ingested: $title"
-        ((count++))
-    else
-        error=$(echo "$response" | jq -r '.error // "Unknown error"')
-        echo "❌ Failed: $title - $error"
-    fi
-done
+# Ingest every document in one request
+response=$(curl -s -X POST "$API_URL/api/ingest/bulk" \
+    -H "Content-Type: application/json" \
+    -H "X-API-Key: $API_KEY" \
+    --data-binary @"$SEED_FILE")
+
+if ! echo "$response" | jq -e '.results' > /dev/null 2>&1; then
+    error=$(echo "$response" | jq -r '.error // "Unknown error"' 2>/dev/null || echo "$response")
+    echo "❌ Bulk ingest failed: $error"
+    exit 1
+fi
+
+echo "$response" | jq -r '.results[]
+    | if .success and .status == "duplicate" then "⏭️  Already stored: \(.title)"
+      elif .success then "✅ Ingested: \(.title)\(if .warning then " (⚠️  \(.warning))" else "" end)"
+      else "❌ Failed: \(.title // "document \(.index)") - \(.error)" end'
+echo ""
+echo "$response" | jq -r '"Stored \(.succeeded) of \(.total) documents"'
 
 echo ""
 echo "🎉 Knowledge base seeding complete!"
@@ -0,0 +1,194 @@
+//! API key authentication.
+//!
+//! Keys are random tokens handed out once; only their SHA-256 hash is stored in
+//! the `api_keys` collection. Handlers declare the scope they need by taking an
+//! `Authorized<S>` extractor, e.g. `Authorized<KnowledgeWrite>` for ingest.
+
+use crate::{AppState, ErrorResponse};
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::Response;
+use chrono::{DateTime, Utc};
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::marker::PhantomData;
+use std::sync::Arc;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// Header carrying the API key (`Authorization: Bearer <key>` is accepted too)
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Prefix of every generated key, makes leaked keys easy to spot
+const KEY_PREFIX: &str = "cht_";
+
+/// Permission attached to an API key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum Scope {
+    /// Chat, sessions and personal data endpoints
+    #[serde(rename = "chat")]
+    Chat,
+    /// Writing to the knowledge base
+    #[serde(rename = "knowledge:write")]
+    KnowledgeWrite,
+    /// Key management; implies every other scope
+    #[serde(rename = "admin")]
+    Admin,
+}
+
+/// API key record stored in MongoDB
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKeyDocument {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub name: String,
+    /// First characters of the key, for recognising it in listings
+    pub prefix: String,
+    /// Hex-encoded SHA-256 of the full key
+    pub key_hash: String,
+    pub scopes: Vec<Scope>,
+    #[serde(with = "mongodb::bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime<Utc>,
+    #[serde(default, with = "mongodb::bson::serde_helpers::chrono_datetime_as_bson_datetime_optional")]
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl ApiKeyDocument {
+    pub fn allows(&self, scope: Scope) -> bool {
+        self.revoked_at.is_none() && (self.scopes.contains(&scope) || self.scopes.contains(&Scope::Admin))
+    }
+}
+
+/// Hex-encoded SHA-256 of a key
+pub fn hash_key(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+/// Create a new random key and the record to store for it
+pub fn generate_key(name: String, scopes: Vec<Scope>) -> (String, ApiKeyDocument) {
+    let key = format!(
+        "{}{}{}",
+        KEY_PREFIX,
+        Uuid::new_v4().simple(),
+        Uuid::new_v4().simple()
+    );
+    let document = ApiKeyDocument {
+        id: Uuid::new_v4().to_string(),
+        name,
+        prefix: key[..KEY_PREFIX.len() + 8].to_string(),
+        key_hash: hash_key(&key),
+        scopes,
+        created_at: Utc::now(),
+        revoked_at: None,
+    };
+    (key, document)
+}
+
+/// Record for a key supplied through configuration (`ADMIN_API_KEY`)
+pub fn bootstrap_admin_key(key: &str) -> ApiKeyDocument {
+    ApiKeyDocument {
+        id: "bootstrap-admin".to_string(),
+        name: "Bootstrap admin (ADMIN_API_KEY)".to_string(),
+        prefix: key.chars().take(KEY_PREFIX.len() + 8).collect(),
+        key_hash: hash_key(key),
+        scopes: vec![Scope::Admin],
+        created_at: Utc::now(),
+        revoked_at: None,
+    }
+}
+
+/// Raw key sent by the client, if any
+pub fn presented_key(headers: &HeaderMap) -> Option<&str> {
+    if let Some(key) = headers.get(API_KEY_HEADER).and_then(|v| v.to_str().ok()) {
+        return Some(key.trim());
+    }
+    headers
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+/// Key already verified earlier in the request, see `ratelimit`
+#[derive(Clone)]
+pub struct VerifiedKey(pub ApiKeyDocument);
+
+/// Find the unrevoked key record for a raw key
+pub async fn lookup(state: &AppState, key: &str) -> Result<Option<ApiKeyDocument>, String> {
+    state
+        .db
+        .api_keys_collection()
+        .find_one(doc! { "key_hash": hash_key(key), "revoked_at": null })
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Compile-time scope requirement for `Authorized`
+pub trait RequiredScope {
+    const SCOPE: Scope;
+}
+
+/// Requires the `chat` scope
+pub struct Chat;
+/// Requires the `knowledge:write` scope
+pub struct KnowledgeWrite;
+/// Requires the `admin` scope
+pub struct Admin;
+
+impl RequiredScope for Chat {
+    const SCOPE: Scope = Scope::Chat;
+}
+impl RequiredScope for KnowledgeWrite {
+    const SCOPE: Scope = Scope::KnowledgeWrite;
+}
+impl RequiredScope for Admin {
+    const SCOPE: Scope = Scope::Admin;
+}
+
+/// Extractor that only succeeds for a valid, unrevoked key holding scope `S`.
+///
+/// With `AUTH_ENABLED=false` every request is let through and `key` is `None`.
+pub struct Authorized<S: RequiredScope> {
+    #[allow(dead_code)]
+    pub key: Option<ApiKeyDocument>,
+    _scope: PhantomData<S>,
+}
+
+impl<S: RequiredScope> FromRequestParts<Arc<AppState>> for Authorized<S> {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &Arc<AppState>) -> Result<Self, Self::Rejection> {
+        if !state.config.auth_enabled {
+            return Ok(Self {
+                key: None,
+                _scope: PhantomData,
+            });
+        }
+
+        let Some(key) = presented_key(&parts.headers) else {
+            return Err(ErrorResponse::respond(StatusCode::UNAUTHORIZED, "API key required"));
+        };
+
+        let record = match parts.extensions.get::<VerifiedKey>() {
+            Some(VerifiedKey(record)) => record.clone(),
+            None => lookup(state, key)
+                .await
+                .map_err(|e| {
+                    tracing::error!("Failed to look up API key: {}", e);
+                    ErrorResponse::respond(StatusCode::INTERNAL_SERVER_ERROR, "Failed to verify API key")
+                })?
+                .ok_or_else(|| ErrorResponse::respond(StatusCode::UNAUTHORIZED, "Invalid API key"))?,
+        };
+
+        if !record.allows(S::SCOPE) {
+            return Err(ErrorResponse::respond(StatusCode::FORBIDDEN, "API key lacks the required scope"));
+        }
+
+        Ok(Self {
+            key: Some(record),
+            _scope: PhantomData,
+        })
+    }
+}
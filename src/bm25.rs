@@ -0,0 +1,374 @@
+//! BM25 lexical index over knowledge chunks.
+//!
+//! Embeddings are good at paraphrases but weak on exact terms such as "4-7-8" or
+//! specific Indonesian words, so retrieval also ranks chunks by BM25 over their
+//! title and content and fuses both rankings. Text is lowercased, stripped of
+//! Indonesian and English stopwords and run through a light Indonesian stemmer.
+
+use crate::db::{AppDatabase, KnowledgeDocument};
+use crate::rag::RetrievedDocument;
+use futures::stream::TryStreamExt;
+use mongodb::bson::{doc, Document};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+/// Term frequency saturation
+const K1: f64 = 1.2;
+/// Length normalization
+const B: f64 = 0.75;
+/// Title terms count this many times, a title match says more than a passing mention
+const TITLE_WEIGHT: u32 = 2;
+
+const STOPWORDS: &[&str] = &[
+    // Indonesian
+    "yang", "dan", "di", "ke", "dari", "ini", "itu", "untuk", "dengan", "pada", "adalah", "dalam", "tidak", "akan",
+    "juga", "atau", "ada", "bisa", "dapat", "sudah", "saya", "aku", "kamu", "anda", "kita", "kami", "mereka", "dia",
+    "ia", "nya", "lebih", "karena", "jika", "kalau", "saat", "agar", "supaya", "seperti", "oleh", "sebagai", "bagi",
+    "tentang", "hal", "para", "pun", "lah", "kah", "telah", "masih", "harus", "sangat", "hanya", "namun", "tetapi",
+    "tapi", "maka", "bahwa", "setelah", "sebelum", "ketika", "apa", "siapa", "bagaimana", "mengapa", "kenapa",
+    "sih", "dong", "deh", "kok", "ya", "yg", "gak", "ga", "nggak", "udah", "aja", "banget", "jadi", "lagi", "mau",
+    "ingin", "sedang", "pernah", "semua", "setiap", "beberapa", "banyak", "satu", "secara",
+    // English
+    "the", "a", "an", "and", "or", "of", "to", "in", "on", "for", "with", "is", "are", "was", "were", "be", "been",
+    "it", "this", "that", "as", "at", "by", "from", "i", "you", "my", "your", "we", "they", "he", "she", "me", "do",
+    "does", "did", "have", "has", "had", "not", "no", "so", "if", "but", "about", "what", "how", "why", "can",
+];
+
+/// Strip the particle, possessive and derivational suffixes and the common
+/// prefixes of an Indonesian word (a dictionary-free take on Nazief-Adriani).
+///
+/// Stems are only used for matching, so over-stemming is harmless as long as
+/// queries and documents are treated alike; words never drop below four letters.
+pub fn stem(word: &str) -> String {
+    const MIN_STEM: usize = 4;
+    let mut word = word.to_string();
+    let strip_suffix = |word: &mut String, suffixes: &[&str]| {
+        for suffix in suffixes {
+            if word.len() >= suffix.len() + MIN_STEM && word.ends_with(suffix) {
+                word.truncate(word.len() - suffix.len());
+                return;
+            }
+        }
+    };
+
+    strip_suffix(&mut word, &["lah", "kah", "tah", "pun"]);
+    strip_suffix(&mut word, &["nya", "ku", "mu"]);
+
+    // Prefixes, longest first; nasal prefixes swallow the first consonant of the root
+    let prefixes: &[(&str, &str)] = &[
+        ("meng", ""),
+        ("meny", "s"),
+        ("mem", "p"),
+        ("men", "t"),
+        ("me", ""),
+        ("peng", ""),
+        ("peny", "s"),
+        ("pem", "p"),
+        ("pen", "t"),
+        ("per", "r"),
+        ("pe", ""),
+        ("ber", ""),
+        ("be", ""),
+        ("ter", ""),
+        ("di", ""),
+        ("ke", ""),
+        ("se", ""),
+    ];
+    for (prefix, restore) in prefixes {
+        if word.len() >= prefix.len() + MIN_STEM && word.starts_with(prefix) {
+            let rest = &word[prefix.len()..];
+            // A vowel after a nasal prefix means the root's first consonant was dropped;
+            // after "per" it usually means "pe" + a root starting with r (perasaan)
+            let starts_with_vowel = rest.starts_with(['a', 'e', 'i', 'o', 'u']);
+            word = if !restore.is_empty() && starts_with_vowel {
+                format!("{}{}", restore, rest)
+            } else {
+                rest.to_string()
+            };
+            break;
+        }
+    }
+
+    strip_suffix(&mut word, &["kan", "an", "i"]);
+    word
+}
+
+/// Lowercase, split, drop stopwords and stem.
+///
+/// Numeric tokens like "4-7-8" stay whole, reduplicated words ("teman-teman")
+/// collapse to one, other hyphenated words are split.
+pub fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let lowered = text.to_lowercase();
+    for raw in lowered.split(|c: char| !(c.is_alphanumeric() || c == '-')) {
+        let raw = raw.trim_matches('-');
+        if raw.is_empty() {
+            continue;
+        }
+        if raw.contains('-') && raw.chars().all(|c| c.is_ascii_digit() || c == '-') {
+            tokens.push(raw.to_string());
+            continue;
+        }
+        let mut parts: Vec<&str> = raw.split('-').filter(|p| !p.is_empty()).collect();
+        parts.dedup();
+        for part in parts {
+            if STOPWORDS.contains(&part) {
+                continue;
+            }
+            tokens.push(if part.chars().all(char::is_alphabetic) { stem(part) } else { part.to_string() });
+        }
+    }
+    tokens
+}
+
+/// Chunk fields read from MongoDB, without the embedding
+#[derive(Debug, Deserialize)]
+struct ChunkText {
+    #[serde(rename = "_id")]
+    id: String,
+    title: String,
+    category: String,
+    content: String,
+    #[serde(default)]
+    parent_id: Option<String>,
+    #[serde(default)]
+    chunk_index: u32,
+}
+
+struct Entry {
+    document: RetrievedDocument,
+    terms: HashMap<String, u32>,
+    length: u32,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<String, Entry>,
+    /// Number of chunks containing each term
+    document_frequency: HashMap<String, u32>,
+    total_length: u64,
+}
+
+impl Inner {
+    fn insert(&mut self, id: String, document: RetrievedDocument) {
+        self.remove(&id);
+        let mut terms: HashMap<String, u32> = HashMap::new();
+        for token in tokenize(&document.title) {
+            *terms.entry(token).or_default() += TITLE_WEIGHT;
+        }
+        for token in tokenize(&document.content) {
+            *terms.entry(token).or_default() += 1;
+        }
+        let length = terms.values().sum();
+        for term in terms.keys() {
+            *self.document_frequency.entry(term.clone()).or_default() += 1;
+        }
+        self.total_length += u64::from(length);
+        self.entries.insert(id, Entry { document, terms, length });
+    }
+
+    fn remove(&mut self, id: &str) {
+        let Some(entry) = self.entries.remove(id) else {
+            return;
+        };
+        self.total_length -= u64::from(entry.length);
+        for term in entry.terms.keys() {
+            if let Some(count) = self.document_frequency.get_mut(term) {
+                *count -= 1;
+                if *count == 0 {
+                    self.document_frequency.remove(term);
+                }
+            }
+        }
+    }
+}
+
+/// Thread-safe BM25 index shared through `RagService`
+#[derive(Default)]
+pub struct Bm25Index {
+    inner: RwLock<Inner>,
+}
+
+impl Bm25Index {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Rebuild from the `knowledge` collection, returns the number of chunks loaded
+    pub async fn reload(&self, db: &AppDatabase) -> Result<usize, String> {
+        let mut cursor = db
+            .knowledge_collection()
+            .clone_with_type::<Document>()
+            .find(doc! {})
+            .projection(doc! { "embedding": 0 })
+            .await
+            .map_err(|e| format!("Failed to query documents: {}", e))?;
+
+        let mut inner = Inner::default();
+        while let Some(raw) = cursor
+            .try_next()
+            .await
+            .map_err(|e| format!("Failed to read documents: {}", e))?
+        {
+            match mongodb::bson::from_document::<ChunkText>(raw) {
+                Ok(chunk) => {
+                    let (id, document) = Self::prepare(chunk);
+                    inner.insert(id, document);
+                }
+                Err(e) => tracing::warn!("Skipping malformed knowledge document in BM25 index: {}", e),
+            }
+        }
+
+        let loaded = inner.entries.len();
+        *self.inner.write().unwrap_or_else(|e| e.into_inner()) = inner;
+        Ok(loaded)
+    }
+
+    fn prepare(chunk: ChunkText) -> (String, RetrievedDocument) {
+        let document = RetrievedDocument {
+            parent_id: chunk.parent_id.unwrap_or_else(|| chunk.id.clone()),
+            chunk_index: chunk.chunk_index,
+            content: chunk.content,
+            title: chunk.title,
+            category: chunk.category,
+            similarity: 0.0,
+            lexical_score: None,
+            embedding: None,
+        };
+        (chunk.id, document)
+    }
+
+    /// Add a chunk, replacing any previous version with the same id
+    pub fn upsert(&self, document: &KnowledgeDocument) {
+        let (id, document) = Self::prepare(ChunkText {
+            id: document.id.clone(),
+            title: document.title.clone(),
+            category: document.category.clone(),
+            content: document.content.clone(),
+            parent_id: document.parent_id.clone(),
+            chunk_index: document.chunk_index,
+        });
+        self.inner.write().unwrap_or_else(|e| e.into_inner()).insert(id, document);
+    }
+
+    /// Drop a chunk
+    pub fn remove(&self, id: &str) {
+        self.inner.write().unwrap_or_else(|e| e.into_inner()).remove(id);
+    }
+
+    /// Best matching chunks for `query`, best first, with `lexical_score` set.
+    ///
+    /// Chunks outside `categories` are skipped unless `categories` is empty.
+    pub fn search(&self, query: &str, limit: usize, categories: &[String]) -> Vec<RetrievedDocument> {
+        let mut terms = tokenize(query);
+        terms.sort();
+        terms.dedup();
+        if terms.is_empty() {
+            return Vec::new();
+        }
+
+        let inner = self.inner.read().unwrap_or_else(|e| e.into_inner());
+        let count = inner.entries.len() as f64;
+        if count == 0.0 {
+            return Vec::new();
+        }
+        let average_length = inner.total_length as f64 / count;
+
+        let idf: Vec<(&str, f64)> = terms
+            .iter()
+            .filter_map(|term| {
+                let df = f64::from(*inner.document_frequency.get(term)?);
+                Some((term.as_str(), ((count - df + 0.5) / (df + 0.5) + 1.0).ln()))
+            })
+            .collect();
+        if idf.is_empty() {
+            return Vec::new();
+        }
+
+        let mut scored: Vec<(&Entry, f64)> = inner
+            .entries
+            .values()
+            .filter(|entry| categories.is_empty() || categories.contains(&entry.document.category))
+            .filter_map(|entry| {
+                let length_norm = 1.0 - B + B * f64::from(entry.length) / average_length;
+                let score: f64 = idf
+                    .iter()
+                    .filter_map(|(term, idf)| {
+                        let tf = f64::from(*entry.terms.get(*term)?);
+                        Some(idf * tf * (K1 + 1.0) / (tf + K1 * length_norm))
+                    })
+                    .sum();
+                (score > 0.0).then_some((entry, score))
+            })
+            .collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+        scored.truncate(limit);
+        scored
+            .into_iter()
+            .map(|(entry, score)| {
+                let mut document = entry.document.clone();
+                document.lexical_score = Some(score);
+                document
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn chunk(id: &str, title: &str, category: &str, content: &str) -> KnowledgeDocument {
+        KnowledgeDocument {
+            id: id.to_string(),
+            content: content.to_string(),
+            title: title.to_string(),
+            category: category.to_string(),
+            embedding: Vec::new(),
+            created_at: Utc::now(),
+            parent_id: Some(id.to_string()),
+            chunk_index: 0,
+            embedding_model: None,
+            embedding_dim: 0,
+        }
+    }
+
+    fn index() -> Bm25Index {
+        let index = Bm25Index::new();
+        index.upsert(&chunk("napas", "Teknik Pernapasan", "coping-techniques", "Coba bernapas dengan pola 4-7-8 saat cemas."));
+        index.upsert(&chunk("tidur", "Tidur Nyenyak", "wellness", "Jadwal tidur yang teratur membantu pikiran lebih tenang."));
+        index.upsert(&chunk("teman", "Dukungan Teman", "self-help", "Teman-teman bisa menjadi tempat bercerita."));
+        index
+    }
+
+    #[test]
+    fn affixes_are_stripped_to_a_shared_stem() {
+        assert_eq!(stem("pernapasan"), "napas");
+        assert_eq!(stem("bernapas"), "napas");
+        assert_eq!(stem("menenangkan"), "tenang");
+    }
+
+    #[test]
+    fn tokenize_drops_stopwords_and_keeps_numeric_terms() {
+        assert_eq!(tokenize("Aku ingin teknik 4-7-8 untuk teman-teman"), vec!["teknik", "4-7-8", "teman"]);
+    }
+
+    #[test]
+    fn search_ranks_exact_terms_first() {
+        let results = index().search("latihan pernapasan 4-7-8", 3, &[]);
+        assert_eq!(results[0].parent_id, "napas");
+        assert!(results[0].lexical_score.is_some_and(|s| s > 0.0));
+        assert!(results.iter().all(|r| r.parent_id != "tidur"));
+    }
+
+    #[test]
+    fn search_respects_categories_and_removals() {
+        let index = index();
+        assert!(index.search("napas", 3, &["wellness".to_string()]).is_empty());
+        index.remove("napas");
+        assert!(index.search("napas", 3, &[]).is_empty());
+        assert!(index.search("yang dan di", 3, &[]).is_empty());
+    }
+}
@@ -0,0 +1,152 @@
+//! Chat categories.
+//!
+//! A chat category picks the persona prompt, the knowledge categories preferred during
+//! retrieval and the sampling parameters. Clients may send the id or any alias
+//! (`karir`, `career`, ...); anything else is rejected so a typo does not silently turn
+//! into the general persona. `GET /api/categories` lists them for the frontend.
+
+use crate::llm::CompletionParams;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// Category used when a chat has none
+pub const DEFAULT_CATEGORY: &str = "general";
+
+pub struct Category {
+    /// Stable id returned to clients
+    pub id: &'static str,
+    /// Indonesian display name
+    pub name_id: &'static str,
+    /// English display name
+    pub name_en: &'static str,
+    /// Other spellings accepted from clients, lowercase
+    pub aliases: &'static [&'static str],
+    /// Persona template sent after the general prompt, see `prompts`
+    pub prompt: Option<&'static str>,
+    /// Knowledge categories preferred during retrieval; empty means no preference
+    pub knowledge_categories: &'static [&'static str],
+    pub max_tokens: u32,
+    pub temperature: f32,
+}
+
+impl Category {
+    pub fn completion_params(&self) -> CompletionParams {
+        CompletionParams {
+            max_tokens: self.max_tokens,
+            temperature: self.temperature,
+        }
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        self.id == name || self.aliases.contains(&name)
+    }
+}
+
+const CATEGORIES: &[Category] = &[
+    Category {
+        id: DEFAULT_CATEGORY,
+        name_id: "Umum",
+        name_en: "General",
+        aliases: &["umum"],
+        prompt: None,
+        knowledge_categories: &[],
+        max_tokens: 500,
+        temperature: 0.7,
+    },
+    Category {
+        id: "karir",
+        name_id: "Karir",
+        name_en: "Career",
+        aliases: &["career", "karier", "pekerjaan", "work"],
+        prompt: Some("career"),
+        knowledge_categories: &["coping-techniques", "self-help", "wellness"],
+        max_tokens: 500,
+        temperature: 0.7,
+    },
+    Category {
+        id: "asmara",
+        name_id: "Asmara",
+        name_en: "Romance",
+        aliases: &["romance", "love", "cinta"],
+        prompt: Some("romance"),
+        knowledge_categories: &["self-help", "awareness", "coping-techniques"],
+        max_tokens: 500,
+        temperature: 0.7,
+    },
+    Category {
+        id: "keluarga",
+        name_id: "Keluarga",
+        name_en: "Family",
+        aliases: &["family"],
+        prompt: Some("family"),
+        knowledge_categories: &["self-help", "awareness", "resources"],
+        max_tokens: 500,
+        temperature: 0.7,
+    },
+    Category {
+        id: "pengembangan-diri",
+        name_id: "Pengembangan Diri",
+        name_en: "Self Development",
+        aliases: &["pengembangan diri", "self development", "self-development", "growth"],
+        prompt: Some("self_development"),
+        knowledge_categories: &["self-help", "wellness"],
+        max_tokens: 500,
+        temperature: 0.7,
+    },
+];
+
+/// Every category, in display order
+pub fn all() -> &'static [Category] {
+    CATEGORIES
+}
+
+/// The category used when a chat has none
+pub fn default_category() -> &'static Category {
+    &CATEGORIES[0]
+}
+
+/// Look up a category by id or alias; `None` or a blank name means the default category
+pub fn resolve(name: Option<&str>) -> Result<&'static Category, String> {
+    let name = name.map(|n| n.trim().to_lowercase()).filter(|n| !n.is_empty());
+    let name = name.as_deref().unwrap_or(DEFAULT_CATEGORY);
+    CATEGORIES
+        .iter()
+        .find(|c| c.matches(name))
+        .ok_or_else(|| format!("Unknown category '{}', see GET /api/categories", name))
+}
+
+/// Persona templates the registry needs, checked when prompts are loaded
+pub fn prompt_names() -> Vec<&'static str> {
+    CATEGORIES.iter().filter_map(|c| c.prompt).collect()
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CategoryNames {
+    #[schema(example = "Karir")]
+    pub id: String,
+    #[schema(example = "Career")]
+    pub en: String,
+}
+
+/// A category as shown to clients
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CategoryInfo {
+    #[schema(example = "karir")]
+    pub id: String,
+    pub names: CategoryNames,
+    /// Other values accepted in the `category` field
+    pub aliases: Vec<String>,
+}
+
+impl From<&Category> for CategoryInfo {
+    fn from(category: &Category) -> Self {
+        Self {
+            id: category.id.to_string(),
+            names: CategoryNames {
+                id: category.name_id.to_string(),
+                en: category.name_en.to_string(),
+            },
+            aliases: category.aliases.iter().map(|a| a.to_string()).collect(),
+        }
+    }
+}
@@ -0,0 +1,258 @@
+//! Splitting knowledge articles into chunks for embedding.
+//!
+//! Long articles embedded as a single vector match almost nothing well, and
+//! injecting them whole into the prompt wastes the context budget. Text is split
+//! into paragraphs and sentences, then packed into chunks of roughly
+//! `CHUNK_SIZE` characters, each repeating up to `CHUNK_OVERLAP` characters of
+//! the previous one so no thought is cut in half without context.
+
+/// Abbreviations whose trailing period does not end a sentence (lowercase, without the period)
+const ABBREVIATIONS: &[&str] = &[
+    // Indonesian
+    "dll", "dsb", "dst", "dkk", "yth", "tsb", "sdr", "sdri", "bpk", "kel", "kec", "kab", "jl", "jln", "no", "hlm",
+    "tgl", "thn", "dr", "drs", "dra", "ir", "prof", "hj", "spt", "krn", "dgn", "utk", "yg", "sbg", "ttg",
+    // English
+    "etc", "vs", "approx", "fig", "mr", "mrs", "ms",
+];
+
+/// Chunk size settings
+#[derive(Debug, Clone, Copy)]
+pub struct ChunkerConfig {
+    /// Target chunk length in characters
+    pub max_chars: usize,
+    /// Characters of trailing context repeated at the start of the next chunk
+    pub overlap_chars: usize,
+}
+
+impl Default for ChunkerConfig {
+    fn default() -> Self {
+        Self {
+            max_chars: 1000,
+            overlap_chars: 150,
+        }
+    }
+}
+
+impl ChunkerConfig {
+    /// Read `CHUNK_SIZE` and `CHUNK_OVERLAP` (characters)
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let read = |name: &str, default: usize| {
+            std::env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        };
+        let max_chars = read("CHUNK_SIZE", defaults.max_chars).max(100);
+        // Overlap must leave room for new text in every chunk
+        let overlap_chars = read("CHUNK_OVERLAP", defaults.overlap_chars).min(max_chars / 2);
+        Self { max_chars, overlap_chars }
+    }
+}
+
+/// Whether the period at the end of `word` belongs to an abbreviation or a number
+fn is_abbreviation(word: &str) -> bool {
+    let word = word.trim_start_matches(|c: char| !c.is_alphanumeric());
+    let stem = word.trim_end_matches('.').to_lowercase();
+    if stem.is_empty() {
+        return false;
+    }
+    // Single letters are initials ("A. Rahman"), dotted forms are acronyms ("a.n.", "S.Psi.")
+    stem.chars().count() == 1 || stem.contains('.') || ABBREVIATIONS.contains(&stem.as_str())
+}
+
+/// Split a paragraph into sentences on `.`, `!`, `?` and `…` followed by whitespace
+pub fn split_sentences(paragraph: &str) -> Vec<String> {
+    let mut sentences = Vec::new();
+    let mut current = String::new();
+    let words: Vec<&str> = paragraph.split_whitespace().collect();
+
+    for (i, word) in words.iter().enumerate() {
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(word);
+
+        let ends_sentence = word
+            .trim_end_matches(['"', '\'', ')', '”', '’'])
+            .ends_with(['.', '!', '?', '…']);
+        let next_starts_sentence = words
+            .get(i + 1)
+            .and_then(|next| next.chars().find(|c| c.is_alphanumeric()))
+            .is_none_or(|c| c.is_uppercase() || c.is_numeric());
+        let abbreviation = word.ends_with('.') && is_abbreviation(word);
+
+        if ends_sentence && next_starts_sentence && !abbreviation {
+            sentences.push(std::mem::take(&mut current));
+        }
+    }
+    if !current.is_empty() {
+        sentences.push(current);
+    }
+    sentences
+}
+
+/// Break a sentence longer than `max_chars` on word boundaries
+fn split_long(sentence: &str, max_chars: usize) -> Vec<String> {
+    let mut pieces = Vec::new();
+    let mut current = String::new();
+    for word in sentence.split_whitespace() {
+        if !current.is_empty() && current.chars().count() + 1 + word.chars().count() > max_chars {
+            pieces.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(word);
+    }
+    if !current.is_empty() {
+        pieces.push(current);
+    }
+    pieces
+}
+
+/// Trailing sentences of `units` that fit in `budget` characters
+fn overlap_tail(units: &[String], budget: usize) -> Vec<String> {
+    let mut tail = Vec::new();
+    let mut used = 0;
+    for unit in units.iter().rev() {
+        let len = unit.chars().count();
+        if used + len > budget {
+            break;
+        }
+        used += len + 1;
+        tail.insert(0, unit.clone());
+    }
+    tail
+}
+
+/// Split `text` into chunks; short texts come back as a single chunk
+pub fn chunk(text: &str, config: ChunkerConfig) -> Vec<String> {
+    let text = text.trim();
+    if text.chars().count() <= config.max_chars {
+        return if text.is_empty() { Vec::new() } else { vec![text.to_string()] };
+    }
+
+    let mut chunks = Vec::new();
+    // Sentences of the chunk being built; `None` marks a paragraph break
+    let mut current: Vec<Option<String>> = Vec::new();
+    let mut current_len = 0;
+    // Sentences added since the last flush, so an overlap-only chunk is never emitted
+    let mut fresh = 0;
+
+    let render = |units: &[Option<String>]| -> String {
+        let mut out = String::new();
+        for unit in units {
+            match unit {
+                Some(sentence) => {
+                    if !out.is_empty() && !out.ends_with('\n') {
+                        out.push(' ');
+                    }
+                    out.push_str(sentence);
+                }
+                None if !out.is_empty() => out.push_str("\n\n"),
+                None => {}
+            }
+        }
+        out.trim().to_string()
+    };
+
+    for paragraph in text.split("\n\n").map(str::trim).filter(|p| !p.is_empty()) {
+        if !current.is_empty() {
+            current.push(None);
+        }
+        for sentence in split_sentences(paragraph) {
+            for piece in split_long(&sentence, config.max_chars) {
+                let len = piece.chars().count();
+                if fresh > 0 && current_len + len > config.max_chars {
+                    chunks.push(render(&current));
+                    let sentences: Vec<String> = current.iter().flatten().cloned().collect();
+                    let tail = overlap_tail(&sentences, config.overlap_chars);
+                    current_len = tail.iter().map(|s| s.chars().count() + 1).sum();
+                    current = tail.into_iter().map(Some).collect();
+                    fresh = 0;
+                }
+                // The overlap only gets the room the new piece leaves, oldest sentences go first
+                while fresh == 0 && !current.is_empty() && current_len + len > config.max_chars {
+                    if let Some(sentence) = current.remove(0) {
+                        current_len -= sentence.chars().count() + 1;
+                    }
+                }
+                current_len += len + 1;
+                current.push(Some(piece));
+                fresh += 1;
+            }
+        }
+    }
+    if fresh > 0 {
+        chunks.push(render(&current));
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_texts_stay_whole() {
+        assert_eq!(chunk("  Tarik napas perlahan.  ", ChunkerConfig::default()), vec!["Tarik napas perlahan."]);
+        assert!(chunk("   ", ChunkerConfig::default()).is_empty());
+    }
+
+    #[test]
+    fn abbreviations_and_lowercase_continuations_do_not_split_sentences() {
+        assert_eq!(
+            split_sentences("Temui dr. Rahman besok. Dia ramah! Kenapa? karena sabar."),
+            vec!["Temui dr. Rahman besok.", "Dia ramah!", "Kenapa? karena sabar."]
+        );
+    }
+
+    #[test]
+    fn long_texts_are_packed_with_overlap() {
+        let config = ChunkerConfig {
+            max_chars: 200,
+            overlap_chars: 60,
+        };
+        let text: Vec<String> = (1..=30)
+            .map(|i| format!("Kalimat nomor {} membahas cara menenangkan diri.", i))
+            .collect();
+        let chunks = chunk(&text.join(" "), config);
+
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|c| c.chars().count() <= config.max_chars));
+        for pair in chunks.windows(2) {
+            let last_sentence = split_sentences(&pair[0]).pop().unwrap();
+            assert!(pair[1].starts_with(&last_sentence), "{:?} does not overlap {:?}", pair[1], pair[0]);
+        }
+        assert!(chunks.last().unwrap().contains("Kalimat nomor 30 "));
+    }
+
+    #[test]
+    fn overlap_never_pushes_a_chunk_past_the_size() {
+        let config = ChunkerConfig {
+            max_chars: 200,
+            overlap_chars: 60,
+        };
+        let short = "Tarik napas perlahan dan hitung sampai empat.";
+        let long = format!("Rasa cemas {}wajar dirasakan.", "yang datang tiba-tiba memang ".repeat(5));
+        assert!(long.chars().count() > config.max_chars - short.chars().count());
+        let text: Vec<&str> = (0..6).flat_map(|_| [short, long.as_str()]).collect();
+        let chunks = chunk(&text.join(" "), config);
+
+        assert!(chunks.len() > 1);
+        for c in &chunks {
+            assert!(c.chars().count() <= config.max_chars, "{} chars: {:?}", c.chars().count(), c);
+        }
+        // Only repeated context is dropped, never new text
+        assert_eq!(chunks.iter().filter(|c| c.contains(long.as_str())).count(), 6);
+    }
+
+    #[test]
+    fn oversized_sentences_are_broken_on_words() {
+        let sentence = "kata ".repeat(100);
+        let pieces = split_long(sentence.trim(), 50);
+        assert!(pieces.iter().all(|p| p.chars().count() <= 50));
+        assert_eq!(pieces.join(" "), sentence.trim());
+    }
+}
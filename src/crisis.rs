@@ -0,0 +1,346 @@
+//! Hard-coded crisis detection.
+//!
+//! Messages that mention self-harm or suicide never reach the model: the chat
+//! handlers answer with a fixed message and hotline data instead, as required
+//! by the "Crisis Intervention" rule in `docs/docs/ethics.md`.
+
+use crate::language::{self, Language};
+use serde::Serialize;
+use std::sync::LazyLock;
+use utoipa::ToSchema;
+
+/// Indonesian crisis phrases, written in plain form (normalization is applied on load)
+const PHRASES_ID: &[&str] = &[
+    "bunuh diri",
+    "ingin mati",
+    "lebih baik mati",
+    "mending mati",
+    "mati saja",
+    "ingin mengakhiri hidup",
+    "mengakhiri hidup",
+    "akhiri hidup",
+    "tidak ingin hidup",
+    "tidak ingin hidup lagi",
+    "tidak ada gunanya hidup",
+    "capek hidup",
+    "lelah hidup",
+    "ingin menghilang selamanya",
+    "melukai diri",
+    "lukai diri",
+    "menyakiti diri",
+    "sakiti diri",
+    "gantung diri",
+    "potong nadi",
+    "sayat tangan",
+    "menyayat tangan",
+    "minum racun",
+    "loncat dari gedung",
+    "lompat dari gedung",
+    "overdosis",
+];
+
+/// English crisis phrases, written in plain form (normalization is applied on load)
+const PHRASES_EN: &[&str] = &[
+    "suicide",
+    "suicidal",
+    "kill myself",
+    "killing myself",
+    "end my life",
+    "end it all",
+    "take my own life",
+    "want to die",
+    "wish i was dead",
+    "wish i were dead",
+    "better off dead",
+    "do not want to live",
+    "no reason to live",
+    "hurt myself",
+    "harm myself",
+    "cut myself",
+    "cutting myself",
+    "self harm",
+    "overdose",
+];
+
+/// Slang and shorthand mapped to the canonical words used in the phrase lists
+const SLANG: &[(&str, &str)] = &[
+    // Indonesian
+    ("bundir", "bunuh diri"),
+    ("bunuhdiri", "bunuh diri"),
+    ("pengen", "ingin"),
+    ("pengin", "ingin"),
+    ("pingin", "ingin"),
+    ("kepengen", "ingin"),
+    ("pgn", "ingin"),
+    ("pngn", "ingin"),
+    ("mau", "ingin"),
+    ("pgen", "ingin"),
+    ("gak", "tidak"),
+    ("ga", "tidak"),
+    ("gk", "tidak"),
+    ("nggak", "tidak"),
+    ("ngga", "tidak"),
+    ("enggak", "tidak"),
+    ("engga", "tidak"),
+    ("tdk", "tidak"),
+    ("tak", "tidak"),
+    ("aja", "saja"),
+    ("aj", "saja"),
+    ("sj", "saja"),
+    ("mendingan", "mending"),
+    ("mnding", "mending"),
+    ("lbh", "lebih"),
+    ("gw", "aku"),
+    ("gue", "aku"),
+    ("gua", "aku"),
+    ("sy", "aku"),
+    ("saya", "aku"),
+    ("lg", "lagi"),
+    ("cape", "capek"),
+    ("cpk", "capek"),
+    ("ngelukain", "melukai"),
+    ("nglukain", "melukai"),
+    ("nyakitin", "menyakiti"),
+    ("nyayat", "menyayat"),
+    ("od", "overdosis"),
+    // English
+    ("kms", "kill myself"),
+    ("unalive", "kill"),
+    ("im", "i am"),
+    ("dont", "do not"),
+    ("cant", "can not"),
+    ("cannot", "can not"),
+    ("wanna", "want to"),
+    ("selfharm", "self harm"),
+];
+
+/// Words that cancel a phrase when they come right before it
+const NEGATIONS: &[&str] = &["tidak", "bukan", "jangan", "belum", "not", "never", "no"];
+
+/// Words that may stand between a negation and the phrase it negates
+/// ("tidak *pernah* ingin", "not *going to* kill myself"); any other word ends the search,
+/// so in "tidak kuat ingin mati" the negation stays with "kuat"
+const NEGATION_FILLERS: &[&str] = &[
+    "aku", "sama", "sekali", "pernah", "akan", "ingin", "benar", "i", "really", "ever", "going", "to", "want",
+];
+
+/// Most filler words skipped between a negation and a phrase
+const NEGATION_WINDOW: usize = 3;
+
+/// Token inserted at clause boundaries so negations never reach across sentences
+const CLAUSE_BREAK: &str = "|";
+
+/// Crisis hotline shown to the user
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Hotline {
+    #[schema(example = "Into The Light Indonesia")]
+    pub name: String,
+    #[schema(example = "119 ext 8")]
+    pub contact: String,
+}
+
+/// A detected crisis phrase
+#[derive(Debug, Clone)]
+pub struct CrisisMatch {
+    /// Normalized phrase that matched, safe to log (it does not contain user text)
+    pub phrase: String,
+    pub language: Language,
+}
+
+static SLANG_CANONICAL: LazyLock<Vec<(String, &'static str)>> = LazyLock::new(|| {
+    SLANG
+        .iter()
+        .map(|(slang, expansion)| (canonical_word(slang), *expansion))
+        .collect()
+});
+
+static PHRASES: LazyLock<Vec<Vec<String>>> = LazyLock::new(|| {
+    PHRASES_ID
+        .iter()
+        .chain(PHRASES_EN.iter())
+        .map(|phrase| normalize(phrase))
+        .collect()
+});
+
+/// Collapse repeated letters ("matiii" -> "mati") and undo common leetspeak
+fn canonical_word(word: &str) -> String {
+    let has_letter = word.chars().any(|c| c.is_alphabetic());
+    let mut out = String::with_capacity(word.len());
+    let mut last = None;
+    for c in word.chars() {
+        let c = if has_letter {
+            match c {
+                '0' => 'o',
+                '1' => 'i',
+                '3' => 'e',
+                '4' => 'a',
+                '5' => 's',
+                '7' => 't',
+                '@' => 'a',
+                '$' => 's',
+                other => other,
+            }
+        } else {
+            c
+        };
+        if Some(c) != last {
+            out.push(c);
+        }
+        last = Some(c);
+    }
+    out
+}
+
+/// Lowercase, strip punctuation, canonicalize spelling and expand slang into tokens.
+///
+/// Clause punctuation is kept as a `CLAUSE_BREAK` token.
+pub fn normalize(text: &str) -> Vec<String> {
+    let lowered = text.to_lowercase().replace(['\'', '’'], "");
+    let mut tokens = Vec::new();
+    for clause in lowered.split(['.', ',', '!', '?', ';', ':', '\n']) {
+        if !tokens.is_empty() && tokens.last().map(String::as_str) != Some(CLAUSE_BREAK) {
+            tokens.push(CLAUSE_BREAK.to_string());
+        }
+        for raw in clause.split(|c: char| !(c.is_alphanumeric() || c == '@' || c == '$')) {
+            if raw.is_empty() {
+                continue;
+            }
+            let word = canonical_word(raw);
+            let expansion = SLANG_CANONICAL
+                .iter()
+                .find(|(slang, _)| *slang == word)
+                .map(|(_, expansion)| *expansion);
+            match expansion {
+                Some(expansion) => tokens.extend(expansion.split(' ').map(canonical_word)),
+                None => tokens.push(word),
+            }
+        }
+    }
+    tokens
+}
+
+/// True when the phrase starting at `start` is directly negated, fillers aside
+fn is_negated(tokens: &[String], start: usize) -> bool {
+    for (skipped, token) in tokens[..start].iter().rev().enumerate() {
+        let token = token.as_str();
+        if NEGATIONS.contains(&token) {
+            return true;
+        }
+        if !NEGATION_FILLERS.contains(&token) || skipped == NEGATION_WINDOW {
+            return false;
+        }
+    }
+    false
+}
+
+/// Look for a non-negated crisis phrase in `text`
+pub fn detect(text: &str) -> Option<CrisisMatch> {
+    let tokens = normalize(text);
+    for phrase in PHRASES.iter() {
+        if phrase.is_empty() || phrase.len() > tokens.len() {
+            continue;
+        }
+        let hit = (0..=tokens.len() - phrase.len())
+            .filter(|&start| tokens[start..start + phrase.len()] == phrase[..])
+            .any(|start| !is_negated(&tokens, start));
+        if hit {
+            return Some(CrisisMatch {
+                phrase: phrase.join(" "),
+                language: language::detect(text),
+            });
+        }
+    }
+    None
+}
+
+/// Hotlines included with every crisis response
+pub fn hotlines() -> Vec<Hotline> {
+    vec![
+        Hotline {
+            name: "Into The Light Indonesia".to_string(),
+            contact: "119 ext 8".to_string(),
+        },
+        Hotline {
+            name: "Yayasan Pulih".to_string(),
+            contact: "021-788-42580".to_string(),
+        },
+        Hotline {
+            name: "Emergency Services".to_string(),
+            contact: "112".to_string(),
+        },
+    ]
+}
+
+/// Hotlines as one sentence fragment, e.g. "A (1), B (2) or C (3)" with `last_joiner` "or"
+pub fn hotline_list(last_joiner: &str) -> String {
+    let hotlines: Vec<String> = hotlines()
+        .into_iter()
+        .map(|h| format!("{} ({})", h.name, h.contact))
+        .collect();
+    match hotlines.split_last() {
+        Some((last, rest)) if !rest.is_empty() => format!("{} {} {}", rest.join(", "), last_joiner, last),
+        Some((last, _)) => last.clone(),
+        None => String::new(),
+    }
+}
+
+/// Fixed message returned instead of a model reply
+pub fn response_text(language: Language) -> String {
+    match language {
+        Language::Id => format!(
+            "Aku mendengar bahwa kamu sedang melalui sesuatu yang sangat berat, dan aku sangat peduli dengan keselamatanmu. Kamu tidak harus menghadapinya sendirian.\n\nTolong hubungi bantuan sekarang juga: {}. Kamu juga bisa menghubungi orang terdekat yang kamu percaya.\n\nKamu layak mendapatkan dukungan dari orang-orang yang benar-benar bisa membantu.",
+            hotline_list("atau")
+        ),
+        Language::En => format!(
+            "I hear that you're going through something really difficult, and your safety matters. You don't have to face this alone.\n\nPlease reach out for help right now: {}. You can also contact someone you trust.\n\nYou deserve support from people who can truly help.",
+            hotline_list("or")
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negation_of_another_word_does_not_cancel_a_phrase() {
+        let detected = detect("aku gak kuat pengen mati aja").expect("crisis detected");
+        assert_eq!(detected.phrase, "ingin mati");
+        assert_eq!(detected.language, Language::Id);
+    }
+
+    #[test]
+    fn directly_negated_phrases_are_ignored() {
+        assert!(detect("aku tidak ingin mati").is_none());
+        assert!(detect("i don't want to die anymore").is_none());
+        assert!(detect("aku tidak pernah ingin melukai diri").is_none());
+    }
+
+    #[test]
+    fn negation_does_not_cross_clauses() {
+        assert!(detect("tidak, aku ingin mati").is_some());
+    }
+
+    #[test]
+    fn slang_and_spelling_are_normalized() {
+        assert!(detect("pengen bundir").is_some());
+        assert!(detect("i wanna kms").is_some());
+        assert!(detect("rasanya pengen matiii").is_some());
+    }
+
+    #[test]
+    fn ordinary_messages_do_not_match() {
+        assert!(detect("aku capek banget sama kerjaan").is_none());
+        assert!(detect("the deadline is killing me").is_none());
+    }
+
+    #[test]
+    fn response_lists_every_hotline() {
+        for language in [Language::Id, Language::En] {
+            let text = response_text(language);
+            for hotline in hotlines() {
+                assert!(text.contains(&hotline.contact), "{:?} response misses {}", language, hotline.name);
+            }
+        }
+    }
+}
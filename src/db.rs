@@ -1,8 +1,32 @@
-use mongodb::{Client, Collection, Database};
+use futures::stream::TryStreamExt;
+use crate::auth::ApiKeyDocument;
+use mongodb::bson::{doc, Document};
+use mongodb::error::{ErrorKind, WriteFailure};
+use mongodb::options::IndexOptions;
+use mongodb::{Client, Collection, Database, IndexModel, SearchIndexModel, SearchIndexType};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
 
-/// Knowledge document stored in MongoDB with vector embedding
+/// Collections holding per-user data, every document keyed by `user_id`.
+///
+/// Any new collection that stores data about a user must be listed here so it is
+/// covered by `GET /api/me/export` and `DELETE /api/me`.
+pub const USER_DATA_COLLECTIONS: &[&str] = &["sessions"];
+
+/// Kinds of user data the service never keeps, named in exports and deletion receipts so
+/// their absence is explicit: there is no feedback or consent endpoint, and log lines
+/// never carry the anonymous identifier.
+pub const NOT_STORED_USER_DATA: &[&str] = &["feedback", "consent_records", "logs"];
+
+/// Filter matching a session only for the user it belongs to; sessions created without
+/// `X-Anonymous-Id` only match callers that send none
+pub fn session_filter(id: &str, user_id: Option<&str>) -> Document {
+    doc! { "_id": id, "user_id": user_id }
+}
+
+/// Knowledge chunk stored in MongoDB with vector embedding
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct KnowledgeDocument {
     #[serde(rename = "_id")]
@@ -12,17 +36,89 @@ pub struct KnowledgeDocument {
     pub category: String,
     pub embedding: Vec<f64>,
     pub created_at: DateTime<Utc>,
+    /// Article in the `documents` collection this chunk was cut from
+    #[serde(default)]
+    pub parent_id: Option<String>,
+    /// Position of the chunk within its article
+    #[serde(default)]
+    pub chunk_index: u32,
+    /// Model that produced `embedding`
+    #[serde(default)]
+    pub embedding_model: Option<String>,
+    #[serde(default)]
+    pub embedding_dim: u32,
+}
+
+/// Full article as ingested; its chunks live in the `knowledge` collection
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParentDocument {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub title: String,
+    pub category: String,
+    pub content: String,
+    pub chunk_count: u32,
+    #[serde(with = "mongodb::bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime<Utc>,
+    /// `content_hash` of `content`, unique across articles
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_hash: Option<String>,
+}
+
+/// SHA-256 of `content` with case and whitespace normalized, so re-ingesting the same
+/// article with different line breaks or capitalization is still recognized
+pub fn content_hash(content: &str) -> String {
+    let normalized = content.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase();
+    format!("{:x}", Sha256::digest(normalized.as_bytes()))
+}
+
+/// Whether a write failed on a unique index
+pub fn is_duplicate_key(error: &mongodb::error::Error) -> bool {
+    matches!(&*error.kind, ErrorKind::Write(WriteFailure::WriteError(e)) if e.code == 11000)
 }
 
 /// Conversation message for history tracking
 #[derive(Debug, Clone, Serialize, Deserialize)]
-#[allow(dead_code)]
 pub struct ConversationMessage {
     pub role: String,
     pub content: String,
+    #[serde(with = "mongodb::bson::serde_helpers::chrono_datetime_as_bson_datetime")]
     pub timestamp: DateTime<Utc>,
 }
 
+/// Server-side chat session; removed by MongoDB's TTL monitor once `expires_at` passes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionDocument {
+    #[serde(rename = "_id")]
+    pub id: String,
+    /// Anonymous identifier of the owner, when the client sent one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+    #[serde(default)]
+    pub category: Option<String>,
+    /// Redacted transcript, only `user` and `assistant` roles
+    #[serde(default)]
+    pub messages: Vec<ConversationMessage>,
+    #[serde(with = "mongodb::bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "mongodb::bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Deployment-wide setting that has to survive restarts, one document per setting
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SettingDocument {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub value: String,
+    #[serde(with = "mongodb::bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Setting naming the model every stored chunk is embedded with, written by a completed
+/// re-embedding job
+const ACTIVE_EMBEDDING_MODEL: &str = "active_embedding_model";
+
 /// MongoDB database wrapper
 #[derive(Clone)]
 pub struct AppDatabase {
@@ -47,9 +143,339 @@ impl AppDatabase {
         self.db.collection("knowledge")
     }
     
+    /// Get the parent documents collection
+    pub fn documents_collection(&self) -> Collection<ParentDocument> {
+        self.db.collection("documents")
+    }
+    
+    /// Get the chat sessions collection
+    pub fn sessions_collection(&self) -> Collection<SessionDocument> {
+        self.db.collection("sessions")
+    }
+    
+    /// Get the API keys collection
+    pub fn api_keys_collection(&self) -> Collection<ApiKeyDocument> {
+        self.db.collection("api_keys")
+    }
+    
+    /// Get the settings collection
+    pub fn settings_collection(&self) -> Collection<SettingDocument> {
+        self.db.collection("settings")
+    }
+    
+    /// Embedding model recorded by the last completed re-embedding job, if any
+    pub async fn active_embedding_model(&self) -> Result<Option<String>, mongodb::error::Error> {
+        let setting = self
+            .settings_collection()
+            .find_one(doc! { "_id": ACTIVE_EMBEDDING_MODEL })
+            .await?;
+        Ok(setting.map(|s| s.value))
+    }
+    
+    /// Record the model the knowledge base is embedded with, so a restart keeps using it
+    pub async fn set_active_embedding_model(&self, model: &str) -> Result<(), mongodb::error::Error> {
+        let setting = SettingDocument {
+            id: ACTIVE_EMBEDDING_MODEL.to_string(),
+            value: model.to_string(),
+            updated_at: Utc::now(),
+        };
+        self.settings_collection()
+            .replace_one(doc! { "_id": ACTIVE_EMBEDDING_MODEL }, &setting)
+            .upsert(true)
+            .await?;
+        Ok(())
+    }
+    
+    /// Create the indexes the application relies on
+    pub async fn ensure_indexes(&self) -> Result<(), mongodb::error::Error> {
+        // TTL index: documents expire as soon as `expires_at` is in the past
+        let ttl = IndexModel::builder()
+            .keys(doc! { "expires_at": 1 })
+            .options(
+                IndexOptions::builder()
+                    .expire_after(std::time::Duration::from_secs(0))
+                    .name("sessions_ttl".to_string())
+                    .build(),
+            )
+            .build();
+        self.sessions_collection().create_index(ttl).await?;
+        
+        // One article per content; articles from before hashing have no hash and are not covered
+        let content_hash = IndexModel::builder()
+            .keys(doc! { "content_hash": 1 })
+            .options(
+                IndexOptions::builder()
+                    .unique(true)
+                    .partial_filter_expression(doc! { "content_hash": { "$type": "string" } })
+                    .build(),
+            )
+            .build();
+        self.documents_collection().create_index(content_hash).await?;
+        
+        // Knowledge listing filters by category and shows the newest first
+        self.documents_collection()
+            .create_index(IndexModel::builder().keys(doc! { "category": 1, "created_at": -1 }).build())
+            .await?;
+        
+        // Chunks are replaced and removed per article
+        self.knowledge_collection()
+            .create_index(IndexModel::builder().keys(doc! { "parent_id": 1, "chunk_index": 1 }).build())
+            .await?;
+        
+        // Retrieval and re-embedding select chunks by model
+        self.knowledge_collection()
+            .create_index(IndexModel::builder().keys(doc! { "embedding_model": 1 }).build())
+            .await?;
+        
+        // API keys are looked up by hash on every authenticated request
+        let key_hash = IndexModel::builder()
+            .keys(doc! { "key_hash": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        self.api_keys_collection().create_index(key_hash).await?;
+        
+        // Per-user lookups for export and deletion
+        for name in USER_DATA_COLLECTIONS {
+            self.db
+                .collection::<Document>(name)
+                .create_index(IndexModel::builder().keys(doc! { "user_id": 1 }).build())
+                .await?;
+        }
+        Ok(())
+    }
+    
+    /// Ids of the chunks cut from an article
+    pub async fn chunk_ids(&self, parent_id: &str) -> Result<Vec<String>, mongodb::error::Error> {
+        let chunks: Vec<Document> = self
+            .knowledge_collection()
+            .clone_with_type::<Document>()
+            .find(doc! { "parent_id": parent_id })
+            .projection(doc! { "_id": 1 })
+            .await?
+            .try_collect()
+            .await?;
+        Ok(chunks
+            .iter()
+            .filter_map(|chunk| chunk.get_str("_id").ok().map(str::to_string))
+            .collect())
+    }
+    
+    /// Give knowledge documents ingested before chunking existed a parent article.
+    ///
+    /// Each legacy document becomes a single-chunk article with the same id, so
+    /// nothing has to be re-embedded. Returns the number of documents migrated.
+    pub async fn backfill_parent_documents(&self) -> Result<u64, mongodb::error::Error> {
+        let legacy: Vec<KnowledgeDocument> = self
+            .knowledge_collection()
+            .find(doc! { "parent_id": null })
+            .await?
+            .try_collect()
+            .await?;
+        
+        let mut migrated = 0;
+        for document in legacy {
+            let parent = ParentDocument {
+                id: document.id.clone(),
+                title: document.title.clone(),
+                category: document.category.clone(),
+                content: document.content.clone(),
+                chunk_count: 1,
+                created_at: document.created_at,
+                content_hash: None,
+            };
+            self.documents_collection()
+                .replace_one(doc! { "_id": &parent.id }, &parent)
+                .upsert(true)
+                .await?;
+            self.knowledge_collection()
+                .update_one(
+                    doc! { "_id": &document.id },
+                    doc! { "$set": { "parent_id": &document.id, "chunk_index": 0 } },
+                )
+                .await?;
+            migrated += 1;
+        }
+        Ok(migrated)
+    }
+    
+    /// Hash the content of articles stored before deduplication.
+    ///
+    /// An article whose content duplicates one that already has a hash is left without one.
+    pub async fn backfill_content_hashes(&self) -> Result<u64, mongodb::error::Error> {
+        let unhashed: Vec<ParentDocument> = self
+            .documents_collection()
+            .find(doc! { "content_hash": { "$not": { "$type": "string" } } })
+            .await?
+            .try_collect()
+            .await?;
+        
+        let mut hashed = 0;
+        for document in unhashed {
+            let update = doc! { "$set": { "content_hash": content_hash(&document.content) } };
+            match self.documents_collection().update_one(doc! { "_id": &document.id }, update).await {
+                Ok(_) => hashed += 1,
+                Err(e) if is_duplicate_key(&e) => {
+                    tracing::warn!("Document {} has the same content as another document", document.id);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(hashed)
+    }
+    
+    /// Record `model` as the origin of every chunk stored before embeddings carried their model
+    pub async fn backfill_embedding_model(&self, model: &str) -> Result<u64, mongodb::error::Error> {
+        let result = self
+            .knowledge_collection()
+            .update_many(
+                doc! { "embedding_model": null },
+                vec![doc! { "$set": {
+                    "embedding_model": model,
+                    "embedding_dim": { "$size": { "$ifNull": ["$embedding", []] } },
+                } }],
+            )
+            .await?;
+        Ok(result.modified_count)
+    }
+    
+    /// Number of chunks per embedding model
+    pub async fn embedding_model_counts(&self) -> Result<BTreeMap<String, u64>, mongodb::error::Error> {
+        let mut cursor = self
+            .knowledge_collection()
+            .aggregate(vec![doc! { "$group": { "_id": "$embedding_model", "count": { "$sum": 1 } } }])
+            .await?;
+        let mut counts = BTreeMap::new();
+        while let Some(group) = cursor.try_next().await? {
+            let model = group.get_str("_id").unwrap_or("unknown").to_string();
+            let count = match group.get("count") {
+                Some(mongodb::bson::Bson::Int32(n)) => *n as u64,
+                Some(mongodb::bson::Bson::Int64(n)) => *n as u64,
+                _ => 0,
+            };
+            counts.insert(model, count);
+        }
+        Ok(counts)
+    }
+    
+    /// Create the Atlas vector search index on `knowledge.embedding` unless it already exists.
+    ///
+    /// Atlas builds the index asynchronously, so `$vectorSearch` may return nothing for a
+    /// short while after the first start.
+    pub async fn ensure_vector_search_index(&self, name: &str, dimensions: u32) -> Result<(), mongodb::error::Error> {
+        let existing: Vec<Document> = self
+            .knowledge_collection()
+            .list_search_indexes()
+            .name(name)
+            .await?
+            .try_collect()
+            .await?;
+        if !existing.is_empty() {
+            return Ok(());
+        }
+        
+        let model = SearchIndexModel::builder()
+            .name(name.to_string())
+            .index_type(SearchIndexType::VectorSearch)
+            .definition(doc! {
+                "fields": [
+                    { "type": "vector", "path": "embedding", "numDimensions": dimensions, "similarity": "cosine" },
+                    { "type": "filter", "path": "category" },
+                ]
+            })
+            .build();
+        self.knowledge_collection().create_search_index(model).await?;
+        tracing::info!("Created Atlas vector search index '{}'", name);
+        Ok(())
+    }
+    
+    /// Load a session that has not expired yet
+    pub async fn find_session(
+        &self,
+        id: &str,
+        user_id: Option<&str>,
+    ) -> Result<Option<SessionDocument>, mongodb::error::Error> {
+        // The TTL monitor only runs once a minute, so filter on expiry as well
+        let mut filter = session_filter(id, user_id);
+        filter.insert("expires_at", doc! { "$gt": mongodb::bson::DateTime::now() });
+        self.sessions_collection().find_one(filter).await
+    }
+    
+    /// Append messages to a session and push its expiry forward
+    pub async fn append_session_messages(
+        &self,
+        id: &str,
+        messages: &[ConversationMessage],
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), mongodb::error::Error> {
+        let messages = mongodb::bson::to_bson(messages)?;
+        self.sessions_collection()
+            .update_one(
+                doc! { "_id": id },
+                doc! {
+                    "$push": { "messages": { "$each": messages } },
+                    "$set": { "expires_at": mongodb::bson::DateTime::from_chrono(expires_at) },
+                },
+            )
+            .await?;
+        Ok(())
+    }
+    
+    /// Every document stored about a user, grouped by collection
+    pub async fn export_user_data(
+        &self,
+        user_id: &str,
+    ) -> Result<BTreeMap<String, Vec<Document>>, mongodb::error::Error> {
+        let mut export = BTreeMap::new();
+        for name in USER_DATA_COLLECTIONS {
+            let documents: Vec<Document> = self
+                .db
+                .collection::<Document>(name)
+                .find(doc! { "user_id": user_id })
+                .await?
+                .try_collect()
+                .await?;
+            export.insert(name.to_string(), documents);
+        }
+        Ok(export)
+    }
+    
+    /// Delete every document stored about a user, returning the count per collection
+    pub async fn delete_user_data(&self, user_id: &str) -> Result<BTreeMap<String, u64>, mongodb::error::Error> {
+        let mut deleted = BTreeMap::new();
+        for name in USER_DATA_COLLECTIONS {
+            let result = self
+                .db
+                .collection::<Document>(name)
+                .delete_many(doc! { "user_id": user_id })
+                .await?;
+            deleted.insert(name.to_string(), result.deleted_count);
+        }
+        Ok(deleted)
+    }
+    
     /// Check connection health
     pub async fn ping(&self) -> Result<(), mongodb::error::Error> {
         self.db.run_command(mongodb::bson::doc! { "ping": 1 }).await?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_filters_are_scoped_to_the_owner() {
+        assert_eq!(session_filter("s1", Some("u1")), doc! { "_id": "s1", "user_id": "u1" });
+        // `null` also matches sessions stored without a `user_id`, and nothing owned by a user
+        assert_eq!(
+            session_filter("s1", None),
+            doc! { "_id": "s1", "user_id": mongodb::bson::Bson::Null }
+        );
+    }
+
+    #[test]
+    fn stored_and_unstored_user_data_do_not_overlap() {
+        assert!(USER_DATA_COLLECTIONS.iter().all(|c| !NOT_STORED_USER_DATA.contains(c)));
+    }
+}
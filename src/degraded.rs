@@ -0,0 +1,136 @@
+//! Replies served when the chat model cannot answer.
+//!
+//! Someone who reached out should not be met with an error message. When every model
+//! fails, the chat handlers answer with a fixed, localized supportive message instead,
+//! optionally followed by the most relevant knowledge snippet and the crisis hotlines,
+//! and mark the reply `degraded`. The failure is still logged at error level and every
+//! degraded reply is counted on `/health`.
+
+use crate::crisis::{self, Hotline};
+use crate::language::Language;
+use crate::llm::ProviderError;
+use crate::rag::RetrievedDocument;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Longest knowledge excerpt included in a degraded reply, in characters
+const SNIPPET_CHARS: usize = 400;
+
+/// Excerpt of the best retrieved chunk, kept in case the model fails
+#[derive(Debug, Clone)]
+pub struct Snippet {
+    pub title: String,
+    pub excerpt: String,
+}
+
+impl Snippet {
+    pub fn from_context(context: &[RetrievedDocument]) -> Option<Self> {
+        let top = context.first()?;
+        let content = top.content.trim();
+        let excerpt = if content.chars().count() <= SNIPPET_CHARS {
+            content.to_string()
+        } else {
+            let cut: String = content.chars().take(SNIPPET_CHARS).collect();
+            // End on a word boundary rather than mid-word
+            let cut = cut.rsplit_once(char::is_whitespace).map_or(cut.as_str(), |(head, _)| head);
+            format!("{}…", cut.trim_end())
+        };
+        Some(Self {
+            title: top.title.clone(),
+            excerpt,
+        })
+    }
+}
+
+/// What to send instead of a model reply
+pub struct DegradedReply {
+    pub text: String,
+    /// Title of the knowledge article quoted in `text`
+    pub source: Option<String>,
+    pub hotlines: Option<Vec<Hotline>>,
+}
+
+fn apology(language: Language) -> &'static str {
+    match language {
+        Language::Id => "Maaf, aku sedang tidak bisa membalas dengan baik karena ada gangguan teknis. Terima kasih sudah mau bercerita. Apa yang kamu rasakan tetap penting, dan kamu bisa mencoba lagi sebentar lagi.\n\nSementara itu, coba tarik napas perlahan beberapa kali dan beri dirimu waktu sejenak.",
+        Language::En => "I'm sorry, I can't reply properly right now because of a technical problem. Thank you for sharing. What you're feeling still matters, and you can try again in a little while.\n\nIn the meantime, try taking a few slow breaths and give yourself a moment.",
+    }
+}
+
+fn snippet_intro(language: Language) -> &'static str {
+    match language {
+        Language::Id => "Ini sesuatu dari pustaka kami yang mungkin membantu:",
+        Language::En => "Here is something from our library that might help:",
+    }
+}
+
+fn hotline_text(language: Language) -> String {
+    match language {
+        Language::Id => format!(
+            "Jika kamu butuh bicara dengan seseorang sekarang, kamu bisa menghubungi {}.",
+            crisis::hotline_list("atau")
+        ),
+        Language::En => format!(
+            "If you need to talk to someone right now, you can contact {}.",
+            crisis::hotline_list("or")
+        ),
+    }
+}
+
+/// Degraded mode settings and the count of degraded replies served
+pub struct DegradedMode {
+    enabled: bool,
+    include_snippet: bool,
+    include_hotlines: bool,
+    served: AtomicU64,
+}
+
+impl DegradedMode {
+    /// Read `DEGRADED_MODE`, `DEGRADED_INCLUDE_SNIPPET` and `DEGRADED_INCLUDE_HOTLINES`
+    /// (all on by default)
+    pub fn from_env() -> Self {
+        let flag = |name: &str| std::env::var(name).map(|v| v != "false" && v != "0").unwrap_or(true);
+        Self {
+            enabled: flag("DEGRADED_MODE"),
+            include_snippet: flag("DEGRADED_INCLUDE_SNIPPET"),
+            include_hotlines: flag("DEGRADED_INCLUDE_HOTLINES"),
+            served: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether failures are answered with a degraded reply instead of an error
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Degraded replies served since startup
+    pub fn served(&self) -> u64 {
+        self.served.load(Ordering::Relaxed)
+    }
+
+    /// Build the reply for a failed completion and record the failure
+    pub fn reply(&self, language: Language, snippet: Option<&Snippet>, error: &ProviderError) -> DegradedReply {
+        let served = self.served.fetch_add(1, Ordering::Relaxed) + 1;
+        tracing::error!("Serving degraded reply ({} since startup), chat model failed: {}", served, error);
+
+        let mut text = apology(language).to_string();
+        let snippet = snippet.filter(|_| self.include_snippet);
+        if let Some(snippet) = snippet {
+            text.push_str(&format!(
+                "\n\n{}\n\n**{}**\n{}",
+                snippet_intro(language),
+                snippet.title,
+                snippet.excerpt
+            ));
+        }
+        if self.include_hotlines {
+            text.push_str("\n\n");
+            text.push_str(&hotline_text(language));
+        }
+
+        DegradedReply {
+            text,
+            source: snippet.map(|s| s.title.clone()),
+            hotlines: self.include_hotlines.then(crisis::hotlines),
+        }
+    }
+}
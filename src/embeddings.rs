@@ -1,11 +1,19 @@
-use reqwest::Client;
+use crate::http::ResilientClient;
 use serde::{Deserialize, Serialize};
 
 /// OpenRouter embedding request
 #[derive(Debug, Serialize)]
-struct EmbeddingRequest {
-    model: String,
-    input: String,
+struct EmbeddingRequest<'a> {
+    model: &'a str,
+    input: EmbeddingInput<'a>,
+}
+
+/// The embeddings API takes either one string or an array of them
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum EmbeddingInput<'a> {
+    One(&'a str),
+    Many(&'a [String]),
 }
 
 /// OpenRouter embedding response
@@ -17,56 +25,144 @@ struct EmbeddingResponse {
 #[derive(Debug, Deserialize)]
 struct EmbeddingData {
     embedding: Vec<f64>,
+    /// Position of the input this embedding belongs to; some providers leave it out
+    #[serde(default)]
+    index: Option<usize>,
+}
+
+/// Put embeddings in input order and check there is exactly one per input.
+///
+/// Providers are not required to keep input order, so `index` decides it; without any
+/// index the response order is taken as is. A response where only some entries carry an
+/// index, or the indexes are not `0..expected`, is rejected rather than guessed at.
+fn order_embeddings(data: Vec<EmbeddingData>, expected: usize) -> Result<Vec<Vec<f64>>, String> {
+    if data.len() != expected {
+        return Err(format!("Embedding API returned {} embeddings for {} inputs", data.len(), expected));
+    }
+    if data.iter().all(|d| d.index.is_none()) {
+        return Ok(data.into_iter().map(|d| d.embedding).collect());
+    }
+
+    let mut ordered: Vec<Option<Vec<f64>>> = vec![None; expected];
+    for d in data {
+        let slot = d
+            .index
+            .and_then(|i| ordered.get_mut(i))
+            .filter(|slot| slot.is_none())
+            .ok_or("Embedding API returned missing, duplicate or out of range indexes")?;
+        *slot = Some(d.embedding);
+    }
+    Ok(ordered.into_iter().flatten().collect())
+}
+
+/// Model used when `EMBEDDING_MODEL` is unset; also the model behind documents stored without provenance
+pub const DEFAULT_EMBEDDING_MODEL: &str = "openai/text-embedding-3-small";
+
+/// Model named by `EMBEDDING_MODEL`, if it is set
+pub fn configured_model() -> Option<String> {
+    std::env::var("EMBEDDING_MODEL").ok().filter(|m| !m.trim().is_empty())
+}
+
+/// Pick the embedding model to start with.
+///
+/// `active` is the model a completed re-embedding job recorded; stored chunks use it, so
+/// a different `EMBEDDING_MODEL` would leave retrieval with nothing and is refused.
+pub fn resolve_model(configured: Option<&str>, active: Option<&str>) -> Result<String, String> {
+    match (configured, active) {
+        (Some(configured), Some(active)) if configured != active => Err(format!(
+            "EMBEDDING_MODEL is '{}' but the knowledge base is embedded with '{}'; set EMBEDDING_MODEL={} (or unset it), then re-embed via POST /api/admin/embeddings/reembed to change models",
+            configured, active, active
+        )),
+        (_, Some(model)) | (Some(model), None) => Ok(model.to_string()),
+        (None, None) => Ok(DEFAULT_EMBEDDING_MODEL.to_string()),
+    }
 }
 
 /// Embedding service using OpenRouter
+#[derive(Clone)]
 pub struct EmbeddingService {
-    client: Client,
+    http: ResilientClient,
     api_key: String,
     model: String,
+    /// Largest number of inputs sent in one request
+    batch_size: usize,
 }
 
 impl EmbeddingService {
-    pub fn new(api_key: String) -> Self {
+    /// Uses the model named by `EMBEDDING_MODEL`
+    pub fn new(api_key: String, http: ResilientClient) -> Self {
         Self {
-            client: Client::new(),
+            http,
             api_key,
-            model: "openai/text-embedding-3-small".to_string(),
+            model: configured_model().unwrap_or_else(|| DEFAULT_EMBEDDING_MODEL.to_string()),
+            batch_size: std::env::var("EMBEDDING_BATCH_SIZE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|&n| n > 0)
+                .unwrap_or(64),
         }
     }
     
+    /// Same credentials and batching, different model
+    pub fn with_model(&self, model: &str) -> Self {
+        Self {
+            model: model.to_string(),
+            ..self.clone()
+        }
+    }
+    
+    /// Model every embedding from this service comes from
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+    
+    /// Largest number of inputs sent in one request
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+    
     /// Generate embedding vector for text
     pub async fn generate_embedding(&self, text: &str) -> Result<Vec<f64>, String> {
+        let mut embeddings = self.request(EmbeddingInput::One(text), 1).await?;
+        embeddings.pop().ok_or_else(|| "No embedding returned".to_string())
+    }
+    
+    /// Generate embeddings for many texts, `batch_size` inputs per API call.
+    ///
+    /// The result is in the same order as `texts`.
+    pub async fn generate_embeddings(&self, texts: &[String]) -> Result<Vec<Vec<f64>>, String> {
+        let mut embeddings = Vec::with_capacity(texts.len());
+        for batch in texts.chunks(self.batch_size) {
+            embeddings.extend(self.request(EmbeddingInput::Many(batch), batch.len()).await?);
+        }
+        Ok(embeddings)
+    }
+    
+    /// Embed `input`, which holds `expected` texts; the result is in input order
+    async fn request(&self, input: EmbeddingInput<'_>, expected: usize) -> Result<Vec<Vec<f64>>, String> {
         let request = EmbeddingRequest {
-            model: self.model.clone(),
-            input: text.to_string(),
+            model: &self.model,
+            input,
         };
         
-        let response = self.client
-            .post("https://openrouter.ai/api/v1/embeddings")
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .header("Content-Type", "application/json")
-            .json(&request)
-            .send()
+        let response = self
+            .http
+            .send(|client| {
+                client
+                    .post("https://openrouter.ai/api/v1/embeddings")
+                    .header("Authorization", format!("Bearer {}", self.api_key))
+                    .header("Content-Type", "application/json")
+                    .json(&request)
+            })
             .await
-            .map_err(|e| format!("Failed to call embedding API: {}", e))?;
-        
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(format!("Embedding API error: {} - {}", status, error_text));
-        }
+            .map_err(|e| format!("Embedding API error: {}", e))?;
         
         let embedding_response: EmbeddingResponse = response
             .json()
             .await
             .map_err(|e| format!("Failed to parse embedding response: {}", e))?;
         
-        embedding_response
-            .data
-            .first()
-            .map(|d| d.embedding.clone())
-            .ok_or_else(|| "No embedding returned".to_string())
+        order_embeddings(embedding_response.data, expected)
     }
 }
 
@@ -86,3 +182,49 @@ pub fn cosine_similarity(a: &[f64], b: &[f64]) -> f64 {
     
     dot_product / (magnitude_a * magnitude_b)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(json: &str) -> Vec<EmbeddingData> {
+        serde_json::from_str::<EmbeddingResponse>(json).expect("response parses").data
+    }
+
+    #[test]
+    fn embeddings_are_put_back_in_input_order() {
+        let ordered = order_embeddings(data(r#"{"data": [{"embedding": [2.0], "index": 1}, {"embedding": [1.0], "index": 0}]}"#), 2);
+        assert_eq!(ordered.unwrap(), vec![vec![1.0], vec![2.0]]);
+    }
+
+    #[test]
+    fn embeddings_without_an_index_keep_the_response_order() {
+        let ordered = order_embeddings(data(r#"{"data": [{"embedding": [1.0]}, {"embedding": [2.0]}]}"#), 2);
+        assert_eq!(ordered.unwrap(), vec![vec![1.0], vec![2.0]]);
+    }
+
+    #[test]
+    fn inconsistent_embedding_responses_are_rejected() {
+        // One embedding short
+        assert!(order_embeddings(data(r#"{"data": [{"embedding": [1.0], "index": 0}]}"#), 2).is_err());
+        // Only some entries carry an index
+        assert!(order_embeddings(data(r#"{"data": [{"embedding": [1.0], "index": 1}, {"embedding": [2.0]}]}"#), 2).is_err());
+        // Duplicate and out of range indexes
+        assert!(order_embeddings(data(r#"{"data": [{"embedding": [1.0], "index": 0}, {"embedding": [2.0], "index": 0}]}"#), 2).is_err());
+        assert!(order_embeddings(data(r#"{"data": [{"embedding": [1.0], "index": 0}, {"embedding": [2.0], "index": 2}]}"#), 2).is_err());
+    }
+
+    #[test]
+    fn the_recorded_model_wins_when_nothing_is_configured() {
+        assert_eq!(resolve_model(None, Some("m2")).unwrap(), "m2");
+        assert_eq!(resolve_model(Some("m2"), Some("m2")).unwrap(), "m2");
+        assert_eq!(resolve_model(Some("m1"), None).unwrap(), "m1");
+        assert_eq!(resolve_model(None, None).unwrap(), DEFAULT_EMBEDDING_MODEL);
+    }
+
+    #[test]
+    fn a_configured_model_that_disagrees_with_the_recorded_one_is_refused() {
+        let error = resolve_model(Some("m1"), Some("m2")).unwrap_err();
+        assert!(error.contains("EMBEDDING_MODEL=m2"));
+    }
+}
@@ -0,0 +1,532 @@
+//! Post-generation guardrails.
+//!
+//! Model replies are checked against the boundaries in the general prompt
+//! (never diagnose, never prescribe, never say "you should", never claim to be a
+//! professional). Imperative advice is softened in place; the other rules trigger
+//! one regeneration and, if the reply still violates them, a safe fallback. Streamed
+//! replies cannot be regenerated, so `StreamGuard` checks them sentence by sentence
+//! before anything is sent and ends the stream with the fallback instead.
+
+use crate::language::Language;
+use crate::llm::{ChatProvider, CompletionRequest};
+use crate::Message;
+use regex::{Captures, Regex};
+use serde::Serialize;
+use std::sync::LazyLock;
+use utoipa::ToSchema;
+
+/// Boundary a reply violated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum GuardrailRule {
+    /// Labels the user with a mental health condition
+    Diagnosis,
+    /// Names a medication or a dosage
+    Medication,
+    /// Tells the user what they should or must do
+    ImperativeAdvice,
+    /// Claims to be a therapist, doctor or other professional
+    ProfessionalClaim,
+}
+
+/// What the guardrail pipeline did about a violation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum GuardrailAction {
+    /// The offending wording was rewritten in place
+    Rewritten,
+    /// The model was asked once more and produced a compliant reply
+    Regenerated,
+    /// The reply was replaced with a safe fallback message
+    Fallback,
+}
+
+/// A guardrail that fired for a reply
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct GuardrailReport {
+    pub rule: GuardrailRule,
+    pub action: GuardrailAction,
+}
+
+/// Reply after guardrails were applied
+#[derive(Debug, Clone)]
+pub struct GuardedReply {
+    pub text: String,
+    pub reports: Vec<GuardrailReport>,
+    /// Model that produced `text`; `None` when it is the fallback message
+    pub model: Option<String>,
+}
+
+impl GuardrailRule {
+    /// Whether the rule can be fixed by rewriting the reply locally
+    fn is_rewritable(self) -> bool {
+        matches!(self, GuardrailRule::ImperativeAdvice)
+    }
+
+    /// Reminder appended to the prompt when regenerating
+    fn correction(self) -> &'static str {
+        match self {
+            GuardrailRule::Diagnosis => "Do not name or suggest any mental health diagnosis or condition.",
+            GuardrailRule::Medication => "Do not mention any medication, supplement or dosage.",
+            GuardrailRule::ImperativeAdvice => "Do not tell the user what they should or must do.",
+            GuardrailRule::ProfessionalClaim => "Do not claim to be a therapist, doctor or any kind of professional.",
+        }
+    }
+}
+
+const CONDITIONS_EN: &str = r"(?:clinical\s+)?(?:depression|an?\s+anxiety\s+disorder|anxiety\s+disorder|generali[sz]ed\s+anxiety|bipolar(?:\s+disorder)?|ptsd|ocd|adhd|borderline(?:\s+personality\s+disorder)?|schizophrenia|an?\s+eating\s+disorder|panic\s+disorder|insomnia)";
+const CONDITIONS_ID: &str = r"(?:gangguan\s+)?(?:depresi|kecemasan\s+klinis|gangguan\s+kecemasan|bipolar|ptsd|ocd|adhd|skizofrenia|gangguan\s+makan|gangguan\s+panik|borderline|insomnia)";
+
+static DIAGNOSIS: LazyLock<Vec<Regex>> = LazyLock::new(|| {
+    vec![
+        Regex::new(&format!(
+            r"(?i)\byou\s+(?:might|may|probably|likely|clearly|definitely|could)?\s*(?:have|are\s+suffering\s+from|suffer\s+from|are\s+showing\s+signs\s+of)\s+{}\b",
+            CONDITIONS_EN
+        ))
+        .unwrap(),
+        Regex::new(&format!(r"(?i)\b(?:sounds|seems|looks)\s+like\s+(?:you\s+have\s+)?{}\b", CONDITIONS_EN)).unwrap(),
+        Regex::new(&format!(
+            r"(?i)\b(?:kamu|anda|kau)\s+(?:mungkin|sepertinya|kemungkinan|jelas|pasti)?\s*(?:mengalami|menderita|mengidap|terkena|punya|memiliki)\s+{}\b",
+            CONDITIONS_ID
+        ))
+        .unwrap(),
+        Regex::new(&format!(r"(?i)\b(?:sepertinya|kedengarannya)\s+(?:itu\s+)?{}\b", CONDITIONS_ID)).unwrap(),
+    ]
+});
+
+static MEDICATION: LazyLock<Vec<Regex>> = LazyLock::new(|| {
+    vec![
+        Regex::new(
+            r"(?i)\b(?:sertraline|zoloft|fluoxetine|prozac|escitalopram|lexapro|citalopram|paroxetine|venlafaxine|duloxetine|bupropion|mirtazapine|amitriptyline|alprazolam|xanax|diazepam|valium|lorazepam|clonazepam|quetiapine|olanzapine|risperidone|aripiprazole|lithium|zolpidem|antidepressants?|antidepresan|anti-anxiety\s+medication|sleeping\s+pills?|obat\s+(?:penenang|tidur|antidepresan))\b",
+        )
+        .unwrap(),
+        // Only units that are used for doses; volumes like "250 ml" are ordinary drinks
+        Regex::new(r"(?i)\b\d+(?:[.,]\d+)?\s?(?:mg|mcg|µg|miligram|milligrams?)\b").unwrap(),
+    ]
+});
+
+static PROFESSIONAL_CLAIM: LazyLock<Vec<Regex>> = LazyLock::new(|| {
+    vec![
+        Regex::new(
+            r"(?i)\b(?:i\s+am|i'm|as)\s+(?:a|an|your)\s+(?:licensed\s+|certified\s+|trained\s+)?(?:therapist|psychologist|psychiatrist|counsell?or|doctor|physician|mental\s+health\s+professional)\b",
+        )
+        .unwrap(),
+        Regex::new(
+            r"(?i)\b(?:saya|aku)\s+(?:adalah\s+)?(?:seorang\s+)?(?:terapis|psikolog|psikiater|konselor|dokter)\b|\bsebagai\s+(?:terapis|psikolog|psikiater|konselor|dokter)(?:mu|\s+kamu|\s+anda)\b",
+        )
+        .unwrap(),
+    ]
+});
+
+// The trailing group catches negated forms ("you should not", "kamu sebaiknya tidak"),
+// which are left alone: softening them to "you could not" would flip their meaning
+static IMPERATIVE_EN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)\byou\s+(?:should|must|have\s+to|need\s+to|ought\s+to)\b(n't\b|\s+not\b|\s+never\b)?").unwrap()
+});
+
+// The leading group catches a hedge already in place ("mungkin kamu harus"), so the
+// rewrite does not add a second one
+static IMPERATIVE_ID: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)(\b(?:mungkin|maybe|bisa)\s+)?\b(kamu|anda|kau)\s+(?:harus|wajib|sebaiknya|mesti)\b(\s+(?:tidak|jangan)\b)?").unwrap()
+});
+
+/// Whether `text` tells the user what they should do, ignoring negated forms
+fn has_imperative(text: &str) -> bool {
+    IMPERATIVE_EN.captures_iter(text).any(|caps| caps.get(1).is_none())
+        || IMPERATIVE_ID.captures_iter(text).any(|caps| caps.get(3).is_none())
+}
+
+/// Keep the capitalization of the first letter of the replaced phrase
+fn match_case(original: &str, replacement: &str) -> String {
+    if original.chars().next().is_some_and(char::is_uppercase) {
+        let mut chars = replacement.chars();
+        match chars.next() {
+            Some(first) => first.to_uppercase().chain(chars).collect(),
+            None => String::new(),
+        }
+    } else {
+        replacement.to_string()
+    }
+}
+
+/// Soften imperative advice into an invitation ("you should" -> "you could"), leaving
+/// negated forms unchanged
+fn rewrite_imperatives(text: &str) -> String {
+    let text = IMPERATIVE_EN.replace_all(text, |caps: &Captures| match caps.get(1) {
+        Some(_) => caps[0].to_string(),
+        None => match_case(&caps[0], "you could"),
+    });
+    IMPERATIVE_ID
+        .replace_all(&text, |caps: &Captures| match (caps.get(3), caps.get(1)) {
+            (Some(_), _) => caps[0].to_string(),
+            (None, Some(hedge)) => format!("{}{} bisa", hedge.as_str(), &caps[2]),
+            (None, None) => match_case(&caps[0], &format!("{} mungkin bisa", caps[2].to_lowercase())),
+        })
+        .into_owned()
+}
+
+/// Run every detector over a reply
+pub fn check(text: &str) -> Vec<GuardrailRule> {
+    let mut fired = Vec::new();
+    if DIAGNOSIS.iter().any(|re| re.is_match(text)) {
+        fired.push(GuardrailRule::Diagnosis);
+    }
+    if MEDICATION.iter().any(|re| re.is_match(text)) {
+        fired.push(GuardrailRule::Medication);
+    }
+    if has_imperative(text) {
+        fired.push(GuardrailRule::ImperativeAdvice);
+    }
+    if PROFESSIONAL_CLAIM.iter().any(|re| re.is_match(text)) {
+        fired.push(GuardrailRule::ProfessionalClaim);
+    }
+    fired
+}
+
+/// Safe reply used when a violation cannot be fixed
+pub fn fallback_text(language: Language) -> &'static str {
+    match language {
+        Language::Id => "Terima kasih sudah mau bercerita. Aku bukan tenaga profesional, jadi aku tidak bisa memberi diagnosis atau saran pengobatan, tapi aku di sini untuk mendengarkan. Untuk hal-hal medis, tenaga kesehatan profesional adalah orang yang tepat untuk diajak bicara.\n\nBagaimana perasaanmu saat ini?",
+        Language::En => "Thank you for sharing this with me. I'm not a professional, so I can't offer a diagnosis or treatment advice, but I'm here to listen. For medical questions, a qualified health professional is the right person to talk to.\n\nHow are you feeling right now?",
+    }
+}
+
+/// Apply only the local fixes: rewrite what can be rewritten, otherwise fall back.
+///
+/// `model` produced `text` and is kept unless the reply falls back.
+fn apply_local(text: &str, model: String, language: Language) -> GuardedReply {
+    let fired = check(text);
+    if fired.is_empty() {
+        return GuardedReply {
+            text: text.to_string(),
+            reports: Vec::new(),
+            model: Some(model),
+        };
+    }
+
+    if fired.iter().all(|rule| rule.is_rewritable()) {
+        return GuardedReply {
+            text: rewrite_imperatives(text),
+            reports: fired
+                .into_iter()
+                .map(|rule| GuardrailReport { rule, action: GuardrailAction::Rewritten })
+                .collect(),
+            model: Some(model),
+        };
+    }
+
+    GuardedReply {
+        text: fallback_text(language).to_string(),
+        reports: fired
+            .into_iter()
+            .map(|rule| GuardrailReport { rule, action: GuardrailAction::Fallback })
+            .collect(),
+        model: None,
+    }
+}
+
+/// Add reports, keeping one per rule
+fn merge_reports(reports: &mut Vec<GuardrailReport>, fired: Vec<GuardrailRule>, action: GuardrailAction) {
+    for rule in fired {
+        match reports.iter_mut().find(|r| r.rule == rule) {
+            Some(existing) => existing.action = action,
+            None => reports.push(GuardrailReport { rule, action }),
+        }
+    }
+}
+
+/// End of the last complete sentence in `text`: a `.`, `!`, `?` or newline followed by
+/// whitespace, so "3.5" or "e.g." mid-sentence do not split it
+fn sentence_end(text: &str) -> Option<usize> {
+    let mut end = None;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let boundary = match c {
+            '\n' => true,
+            '.' | '!' | '?' => chars.peek().is_some_and(|(_, next)| next.is_whitespace()),
+            _ => false,
+        };
+        if boundary {
+            end = Some(i + c.len_utf8());
+        }
+    }
+    end
+}
+
+/// Guardrails for a streamed reply.
+///
+/// Model deltas are held back until a sentence is complete, and each sentence is checked
+/// before it is released, so nothing unchecked reaches the client. Imperative advice is
+/// rewritten in place; any other violation drops the sentence, ends the reply with the
+/// safe fallback and discards the rest of the model output.
+pub struct StreamGuard {
+    language: Language,
+    pending: String,
+    /// Everything released so far, as the user saw it
+    released: String,
+    reports: Vec<GuardrailReport>,
+    stopped: bool,
+}
+
+impl StreamGuard {
+    pub fn new(language: Language) -> Self {
+        Self {
+            language,
+            pending: String::new(),
+            released: String::new(),
+            reports: Vec::new(),
+            stopped: false,
+        }
+    }
+
+    /// Feed a model delta; returns the checked text that may be sent now
+    pub fn push(&mut self, delta: &str) -> String {
+        if self.stopped {
+            return String::new();
+        }
+        self.pending.push_str(delta);
+        match sentence_end(&self.pending) {
+            Some(end) => {
+                let rest = self.pending.split_off(end);
+                let sentences = std::mem::replace(&mut self.pending, rest);
+                self.release(&sentences)
+            }
+            None => String::new(),
+        }
+    }
+
+    /// Check and release whatever is still held back at the end of the reply
+    pub fn finish(&mut self) -> String {
+        let rest = std::mem::take(&mut self.pending);
+        if self.stopped || rest.is_empty() {
+            return String::new();
+        }
+        self.release(&rest)
+    }
+
+    /// True once a violation ended the reply; further model output is ignored
+    pub fn stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// The reply as released to the client
+    pub fn released(&self) -> &str {
+        &self.released
+    }
+
+    /// Guardrails that fired so far
+    pub fn reports(&self) -> Vec<GuardrailReport> {
+        self.reports.clone()
+    }
+
+    fn release(&mut self, text: &str) -> String {
+        let fired = check(text);
+        let out = if fired.is_empty() {
+            text.to_string()
+        } else if fired.iter().all(|rule| rule.is_rewritable()) {
+            merge_reports(&mut self.reports, fired, GuardrailAction::Rewritten);
+            rewrite_imperatives(text)
+        } else {
+            tracing::warn!("Guardrails fired on streamed reply: {:?}, ending it with the fallback", fired);
+            merge_reports(&mut self.reports, fired, GuardrailAction::Fallback);
+            self.stopped = true;
+            let separator = if self.released.trim().is_empty() { "" } else { "\n\n" };
+            format!("{}{}", separator, fallback_text(self.language))
+        };
+        self.released.push_str(&out);
+        out
+    }
+}
+
+/// Check a reply and repair it: rewrite imperative advice, regenerate once for the
+/// other rules, and fall back to a safe message if the second attempt still violates.
+///
+/// `model` produced `reply`; the result names the model behind the text it returns.
+pub async fn enforce(
+    provider: &dyn ChatProvider,
+    request: &CompletionRequest,
+    reply: String,
+    model: String,
+    language: Language,
+) -> GuardedReply {
+    let fired = check(&reply);
+    if fired.iter().all(|rule| rule.is_rewritable()) {
+        return apply_local(&reply, model, language);
+    }
+
+    tracing::warn!("Guardrails fired on model reply: {:?}, regenerating once", fired);
+    let corrections: Vec<&str> = fired.iter().map(|rule| rule.correction()).collect();
+    let mut retry = request.clone();
+    retry.messages.push(Message {
+        role: "system".to_string(),
+        content: format!(
+            "Your previous draft broke the assistant boundaries. Answer the user's last message again. {}",
+            corrections.join(" ")
+        ),
+    });
+
+    let (regenerated, model) = match provider.complete(&retry).await {
+        Ok(completion) => (completion.content.unwrap_or_default(), completion.model),
+        Err(e) => {
+            tracing::error!("Guardrail regeneration failed: {}", e);
+            (String::new(), model)
+        }
+    };
+
+    let refired = check(&regenerated);
+    let repaired = !regenerated.trim().is_empty() && refired.iter().all(|rule| rule.is_rewritable());
+    if !repaired {
+        return GuardedReply {
+            text: fallback_text(language).to_string(),
+            reports: fired
+                .into_iter()
+                .map(|rule| GuardrailReport { rule, action: GuardrailAction::Fallback })
+                .collect(),
+            model: None,
+        };
+    }
+
+    let mut guarded = apply_local(&regenerated, model, language);
+    let mut reports = Vec::new();
+    merge_reports(&mut reports, fired, GuardrailAction::Regenerated);
+    for report in guarded.reports.drain(..) {
+        merge_reports(&mut reports, vec![report.rule], report.action);
+    }
+    guarded.reports = reports;
+    guarded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::{CompletionParams, ScriptedProvider};
+
+    fn request() -> CompletionRequest {
+        CompletionRequest {
+            model: "test-model".to_string(),
+            messages: Vec::new(),
+            params: CompletionParams::default(),
+        }
+    }
+
+    #[test]
+    fn each_rule_is_detected() {
+        assert_eq!(check("It sounds like you have clinical depression."), vec![GuardrailRule::Diagnosis]);
+        assert_eq!(check("Sepertinya kamu mengalami gangguan kecemasan."), vec![GuardrailRule::Diagnosis]);
+        assert_eq!(check("Maybe ask about sertraline."), vec![GuardrailRule::Medication]);
+        assert_eq!(check("As a licensed therapist, I hear you."), vec![GuardrailRule::ProfessionalClaim]);
+        assert_eq!(check("Sebagai psikologmu, aku paham."), vec![GuardrailRule::ProfessionalClaim]);
+        assert_eq!(check("Kamu harus lebih sabar."), vec![GuardrailRule::ImperativeAdvice]);
+        assert!(check("That sounds really heavy. I'm here to listen.").is_empty());
+    }
+
+    #[tokio::test]
+    async fn enforce_regenerates_once_and_keeps_a_compliant_reply() {
+        let provider = ScriptedProvider::new(vec!["I'm here with you. You should take a break.".to_string()]);
+        let guarded = enforce(
+            &provider,
+            &request(),
+            "You might have PTSD.".to_string(),
+            "first-model".to_string(),
+            Language::En,
+        )
+        .await;
+        assert_eq!(guarded.text, "I'm here with you. You could take a break.");
+        // The regenerated reply is the one shown, so its model is reported
+        assert_eq!(guarded.model.as_deref(), Some("test-model"));
+        let actions: Vec<(GuardrailRule, GuardrailAction)> = guarded.reports.iter().map(|r| (r.rule, r.action)).collect();
+        assert_eq!(
+            actions,
+            vec![
+                (GuardrailRule::Diagnosis, GuardrailAction::Regenerated),
+                (GuardrailRule::ImperativeAdvice, GuardrailAction::Rewritten),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn enforce_falls_back_when_the_retry_still_violates() {
+        let provider = ScriptedProvider::new(vec!["I am a psychologist, trust me.".to_string()]);
+        let guarded = enforce(
+            &provider,
+            &request(),
+            "Kamu pasti mengidap bipolar.".to_string(),
+            "first-model".to_string(),
+            Language::Id,
+        )
+        .await;
+        assert_eq!(guarded.text, fallback_text(Language::Id));
+        assert!(guarded.model.is_none());
+        assert_eq!(guarded.reports[0].action, GuardrailAction::Fallback);
+    }
+
+    #[test]
+    fn imperatives_are_softened() {
+        assert_eq!(rewrite_imperatives("You should rest tonight."), "You could rest tonight.");
+        assert_eq!(rewrite_imperatives("kamu harus istirahat"), "kamu mungkin bisa istirahat");
+        assert_eq!(rewrite_imperatives("Kamu sebaiknya tidur"), "Kamu mungkin bisa tidur");
+    }
+
+    #[test]
+    fn hedged_imperatives_are_not_hedged_twice() {
+        assert_eq!(rewrite_imperatives("mungkin kamu harus istirahat"), "mungkin kamu bisa istirahat");
+        assert_eq!(rewrite_imperatives("Mungkin kamu sebaiknya cerita"), "Mungkin kamu bisa cerita");
+        assert_eq!(rewrite_imperatives("Maybe you should rest"), "Maybe you could rest");
+    }
+
+    #[test]
+    fn negated_imperatives_keep_their_meaning() {
+        for text in [
+            "You should not blame yourself.",
+            "you must not carry this alone",
+            "you shouldn't feel ashamed",
+            "kamu sebaiknya tidak memendamnya sendiri",
+        ] {
+            assert_eq!(rewrite_imperatives(text), text);
+            assert!(check(text).is_empty(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn dosages_fire_but_volumes_do_not() {
+        assert_eq!(check("coba minum 50 mg sertraline"), vec![GuardrailRule::Medication]);
+        assert_eq!(check("take 0.5mg before bed"), vec![GuardrailRule::Medication]);
+        assert!(check("coba minum 250 ml air putih dulu").is_empty());
+    }
+
+    #[test]
+    fn stream_guard_holds_text_until_the_sentence_ends() {
+        let mut guard = StreamGuard::new(Language::En);
+        assert_eq!(guard.push("That sounds hard"), "");
+        assert_eq!(guard.push(". You should "), "That sounds hard.");
+        assert_eq!(guard.push("rest. Take it slow"), " You could rest.");
+        assert_eq!(guard.finish(), " Take it slow");
+        assert_eq!(guard.released(), "That sounds hard. You could rest. Take it slow");
+        assert_eq!(guard.reports().len(), 1);
+        assert_eq!(guard.reports()[0].action, GuardrailAction::Rewritten);
+    }
+
+    #[test]
+    fn stream_guard_never_releases_a_violating_sentence() {
+        let mut guard = StreamGuard::new(Language::En);
+        let mut shown = guard.push("I hear you. It sounds like you have ");
+        shown.push_str(&guard.push("clinical depression. Try 50 mg of"));
+        assert!(guard.stopped());
+        shown.push_str(&guard.push(" sertraline."));
+        shown.push_str(&guard.finish());
+
+        assert!(shown.starts_with("I hear you.\n\n"));
+        assert!(shown.ends_with(fallback_text(Language::En)));
+        assert!(!shown.contains("depression"));
+        assert!(!shown.contains("sertraline"));
+        assert_eq!(guard.reports()[0].rule, GuardrailRule::Diagnosis);
+        assert_eq!(guard.reports()[0].action, GuardrailAction::Fallback);
+    }
+
+    #[test]
+    fn decimals_do_not_end_a_sentence() {
+        assert_eq!(sentence_end("about 3.5 hours"), None);
+        assert_eq!(sentence_end("Okay. Then"), Some(5));
+        assert_eq!(sentence_end("line\nnext"), Some(5));
+    }
+}
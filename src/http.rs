@@ -0,0 +1,445 @@
+//! Resilient outbound HTTP for the chat provider and the embeddings API.
+//!
+//! Every attempt has a timeout, retryable failures (timeouts, connection errors, 429 and
+//! 5xx) are retried with jittered exponential backoff that honours `Retry-After`, and a
+//! circuit breaker per upstream fails fast after repeated failures so a dead provider
+//! does not hold every request until the proxy gives up. Breaker states are reported by
+//! `/health`.
+
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Client, RequestBuilder, Response};
+use serde::Serialize;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use utoipa::ToSchema;
+
+/// Read `<PREFIX>_<SUFFIX>` as a number, falling back to `default`
+fn env_or(prefix: &str, suffix: &str, default: u64) -> u64 {
+    std::env::var(format!("{}_{}", prefix, suffix))
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Timeouts and retry schedule for one upstream
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Time allowed per attempt until the response headers arrive, and between body reads
+    pub timeout: Duration,
+    /// Attempts after the first one
+    pub max_retries: u32,
+    /// Backoff before the first retry, doubled for each further one
+    pub base_delay: Duration,
+    /// Longest wait between attempts; a longer `Retry-After` is not waited for
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Read `<PREFIX>_TIMEOUT_SECS`, `<PREFIX>_MAX_RETRIES` (default 2),
+    /// `<PREFIX>_RETRY_BASE_MS` (default 500) and `<PREFIX>_RETRY_MAX_MS` (default 10000)
+    pub fn from_env(prefix: &str, default_timeout_secs: u64) -> Self {
+        Self {
+            timeout: Duration::from_secs(env_or(prefix, "TIMEOUT_SECS", default_timeout_secs).max(1)),
+            max_retries: env_or(prefix, "MAX_RETRIES", 2) as u32,
+            base_delay: Duration::from_millis(env_or(prefix, "RETRY_BASE_MS", 500)),
+            max_delay: Duration::from_millis(env_or(prefix, "RETRY_MAX_MS", 10_000)),
+        }
+    }
+
+    /// Exponential backoff with jitter: somewhere between half and all of `base * 2^attempt`
+    fn backoff(&self, attempt: u32) -> Duration {
+        let full = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        // A fresh RandomState is randomly keyed, which is all the randomness jitter needs
+        let random = std::collections::hash_map::RandomState::new().build_hasher().finish();
+        let fraction = 0.5 + (random % 1000) as f64 / 2000.0;
+        full.mul_f64(fraction)
+    }
+}
+
+/// `Retry-After` in its delay-seconds form; HTTP dates fall back to the normal backoff
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let seconds: u64 = headers.get(RETRY_AFTER)?.to_str().ok()?.trim().parse().ok()?;
+    Some(Duration::from_secs(seconds))
+}
+
+/// Failure of a call made through `ResilientClient`
+#[derive(Debug)]
+pub enum HttpError {
+    /// The breaker is open, the upstream was not called
+    CircuitOpen { retry_in: Duration },
+    /// No response within the policy timeout
+    Timeout(Duration),
+    /// The upstream could not be reached
+    Connect(String),
+    /// The upstream answered with a non-success status
+    Status {
+        status: u16,
+        body: String,
+        retry_after: Option<Duration>,
+    },
+}
+
+impl HttpError {
+    fn is_retryable(&self) -> bool {
+        match self {
+            HttpError::CircuitOpen { .. } => false,
+            HttpError::Timeout(_) | HttpError::Connect(_) => true,
+            HttpError::Status { status, .. } => *status == 429 || *status >= 500,
+        }
+    }
+}
+
+impl std::fmt::Display for HttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HttpError::CircuitOpen { retry_in } => {
+                write!(f, "circuit open, retrying the upstream in {}s", retry_in.as_secs().max(1))
+            }
+            HttpError::Timeout(timeout) => write!(f, "no response within {}s", timeout.as_secs()),
+            HttpError::Connect(e) => write!(f, "connection failed: {}", e),
+            HttpError::Status { status, body, .. } => write!(f, "{} - {}", status, body),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    /// Calls go through
+    Closed,
+    /// Calls fail immediately until the cool-down has passed
+    Open,
+    /// The cool-down has passed; the next call is a probe that closes or reopens the breaker
+    HalfOpen,
+}
+
+/// Breaker state reported by `/health`
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct BreakerStatus {
+    #[schema(example = "chat")]
+    pub name: String,
+    pub state: CircuitState,
+    /// Failed calls since the last success
+    pub consecutive_failures: u32,
+    /// Seconds until an open breaker lets a probe through
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_in_secs: Option<u64>,
+}
+
+struct BreakerInner {
+    consecutive_failures: u32,
+    /// Set while the breaker is open or half-open
+    opened_at: Option<Instant>,
+    /// Start of the half-open probe, if one is running
+    probe_started: Option<Instant>,
+}
+
+/// Circuit breaker for one upstream, counting failed calls (not attempts)
+pub struct CircuitBreaker {
+    name: String,
+    /// Consecutive failed calls that open the breaker; zero disables it
+    failure_threshold: u32,
+    /// How long the breaker stays open before letting a probe through
+    open_for: Duration,
+    inner: Mutex<BreakerInner>,
+}
+
+impl CircuitBreaker {
+    pub fn new(name: &str, failure_threshold: u32, open_for: Duration) -> Self {
+        Self {
+            name: name.to_string(),
+            failure_threshold,
+            open_for,
+            inner: Mutex::new(BreakerInner {
+                consecutive_failures: 0,
+                opened_at: None,
+                probe_started: None,
+            }),
+        }
+    }
+
+    /// Read `<PREFIX>_CIRCUIT_THRESHOLD` (default 5) and `<PREFIX>_CIRCUIT_OPEN_SECS` (default 30)
+    pub fn from_env(name: &str, prefix: &str) -> Self {
+        Self::new(
+            name,
+            env_or(prefix, "CIRCUIT_THRESHOLD", 5) as u32,
+            Duration::from_secs(env_or(prefix, "CIRCUIT_OPEN_SECS", 30)),
+        )
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BreakerInner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Allow a call, or return how long until the breaker lets one through
+    fn try_acquire(&self) -> Result<(), Duration> {
+        let mut inner = self.lock();
+        let Some(opened_at) = inner.opened_at else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        let elapsed = now.duration_since(opened_at);
+        if elapsed < self.open_for {
+            return Err(self.open_for - elapsed);
+        }
+        // One probe at a time; a probe that never reported back (its caller went away)
+        // is replaced after another cool-down
+        match inner.probe_started {
+            Some(started) if now.duration_since(started) < self.open_for => {
+                Err(self.open_for - now.duration_since(started))
+            }
+            _ => {
+                inner.probe_started = Some(now);
+                Ok(())
+            }
+        }
+    }
+
+    fn record_success(&self) {
+        let mut inner = self.lock();
+        if inner.opened_at.is_some() {
+            tracing::info!("Circuit for '{}' closed again", self.name);
+        }
+        inner.consecutive_failures = 0;
+        inner.opened_at = None;
+        inner.probe_started = None;
+    }
+
+    fn record_failure(&self) {
+        let mut inner = self.lock();
+        inner.consecutive_failures = inner.consecutive_failures.saturating_add(1);
+        let probe_failed = inner.probe_started.take().is_some();
+        if probe_failed || (self.failure_threshold > 0 && inner.consecutive_failures >= self.failure_threshold) {
+            if inner.opened_at.is_none() {
+                tracing::warn!(
+                    "Circuit for '{}' opened after {} failed calls, failing fast for {}s",
+                    self.name,
+                    inner.consecutive_failures,
+                    self.open_for.as_secs()
+                );
+            }
+            inner.opened_at = Some(Instant::now());
+        }
+    }
+
+    pub fn status(&self) -> BreakerStatus {
+        let inner = self.lock();
+        let (state, retry_in_secs) = match inner.opened_at {
+            None => (CircuitState::Closed, None),
+            Some(opened_at) => match self.open_for.checked_sub(opened_at.elapsed()) {
+                Some(remaining) if !remaining.is_zero() => (CircuitState::Open, Some(remaining.as_secs().max(1))),
+                _ => (CircuitState::HalfOpen, None),
+            },
+        };
+        BreakerStatus {
+            name: self.name.clone(),
+            state,
+            consecutive_failures: inner.consecutive_failures,
+            retry_in_secs,
+        }
+    }
+}
+
+/// `reqwest` client wrapped with a retry policy and a circuit breaker
+#[derive(Clone)]
+pub struct ResilientClient {
+    client: Client,
+    policy: RetryPolicy,
+    breaker: Arc<CircuitBreaker>,
+}
+
+impl ResilientClient {
+    pub fn new(policy: RetryPolicy, breaker: Arc<CircuitBreaker>) -> Self {
+        let client = Client::builder()
+            .connect_timeout(policy.timeout.min(Duration::from_secs(10)))
+            .read_timeout(policy.timeout)
+            .build()
+            .unwrap_or_else(|e| {
+                tracing::warn!("Failed to configure HTTP client, using defaults: {}", e);
+                Client::new()
+            });
+        Self { client, policy, breaker }
+    }
+
+    /// Policy and breaker for the upstream behind `prefix`, see `RetryPolicy::from_env`
+    /// and `CircuitBreaker::from_env`
+    pub fn from_env(name: &str, prefix: &str, default_timeout_secs: u64) -> Self {
+        Self::new(
+            RetryPolicy::from_env(prefix, default_timeout_secs),
+            Arc::new(CircuitBreaker::from_env(name, prefix)),
+        )
+    }
+
+    pub fn breaker(&self) -> Arc<CircuitBreaker> {
+        self.breaker.clone()
+    }
+
+    /// Send the request made by `build`, retrying as the policy allows.
+    ///
+    /// `build` runs once per attempt. Only success responses are returned; reading the
+    /// body is left to the caller and is bounded by the read timeout.
+    pub async fn send(&self, build: impl Fn(&Client) -> RequestBuilder) -> Result<Response, HttpError> {
+        if let Err(retry_in) = self.breaker.try_acquire() {
+            return Err(HttpError::CircuitOpen { retry_in });
+        }
+
+        let mut attempt = 0;
+        loop {
+            let error = match tokio::time::timeout(self.policy.timeout, build(&self.client).send()).await {
+                Ok(Ok(response)) if response.status().is_success() => {
+                    self.breaker.record_success();
+                    return Ok(response);
+                }
+                Ok(Ok(response)) => {
+                    let status = response.status().as_u16();
+                    let retry_after = retry_after(response.headers());
+                    let body = response.text().await.unwrap_or_default();
+                    HttpError::Status { status, body, retry_after }
+                }
+                Ok(Err(e)) if e.is_timeout() => HttpError::Timeout(self.policy.timeout),
+                Ok(Err(e)) => HttpError::Connect(e.to_string()),
+                Err(_) => HttpError::Timeout(self.policy.timeout),
+            };
+
+            if !error.is_retryable() {
+                // A client error still means the upstream is up
+                self.breaker.record_success();
+                return Err(error);
+            }
+
+            let delay = match &error {
+                HttpError::Status {
+                    retry_after: Some(delay), ..
+                } => *delay,
+                _ => self.policy.backoff(attempt),
+            };
+            if attempt >= self.policy.max_retries || delay > self.policy.max_delay {
+                self.breaker.record_failure();
+                return Err(error);
+            }
+
+            attempt += 1;
+            tracing::warn!(
+                "Call to '{}' failed ({}), retry {}/{} in {}ms",
+                self.breaker.name,
+                error,
+                attempt,
+                self.policy.max_retries,
+                delay.as_millis()
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn breaker_opens_after_consecutive_failures() {
+        let breaker = CircuitBreaker::new("chat", 2, Duration::from_secs(60));
+        breaker.record_failure();
+        assert!(breaker.try_acquire().is_ok());
+        breaker.record_failure();
+        assert!(breaker.try_acquire().is_err());
+
+        let status = breaker.status();
+        assert_eq!(status.state, CircuitState::Open);
+        assert_eq!(status.consecutive_failures, 2);
+        assert!(status.retry_in_secs.is_some_and(|s| s > 0));
+    }
+
+    #[test]
+    fn a_success_resets_the_failure_count() {
+        let breaker = CircuitBreaker::new("chat", 2, Duration::from_secs(60));
+        breaker.record_failure();
+        breaker.record_success();
+        breaker.record_failure();
+        assert!(breaker.try_acquire().is_ok());
+        assert_eq!(breaker.status().state, CircuitState::Closed);
+    }
+
+    #[test]
+    fn half_open_breaker_lets_one_probe_through() {
+        let breaker = CircuitBreaker::new("chat", 1, Duration::from_millis(20));
+        breaker.record_failure();
+        assert!(breaker.try_acquire().is_err());
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(breaker.status().state, CircuitState::HalfOpen);
+        assert!(breaker.try_acquire().is_ok());
+        assert!(breaker.try_acquire().is_err(), "only one probe at a time");
+
+        breaker.record_success();
+        assert_eq!(breaker.status().state, CircuitState::Closed);
+        assert!(breaker.try_acquire().is_ok());
+    }
+
+    #[test]
+    fn failed_probe_reopens_the_breaker() {
+        let breaker = CircuitBreaker::new("chat", 3, Duration::from_millis(20));
+        for _ in 0..3 {
+            breaker.record_failure();
+        }
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(breaker.try_acquire().is_ok());
+        breaker.record_failure();
+        assert_eq!(breaker.status().state, CircuitState::Open);
+    }
+
+    #[test]
+    fn zero_threshold_disables_the_breaker() {
+        let breaker = CircuitBreaker::new("chat", 0, Duration::from_secs(60));
+        for _ in 0..10 {
+            breaker.record_failure();
+        }
+        assert!(breaker.try_acquire().is_ok());
+    }
+
+    #[test]
+    fn backoff_grows_with_jitter_and_is_capped() {
+        let policy = RetryPolicy {
+            timeout: Duration::from_secs(1),
+            max_retries: 3,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(300),
+        };
+        for _ in 0..20 {
+            let second = policy.backoff(1);
+            assert!(second >= Duration::from_millis(100) && second <= Duration::from_millis(200));
+            assert!(policy.backoff(5) <= Duration::from_millis(300));
+        }
+    }
+
+    #[test]
+    fn retry_after_accepts_seconds_only() {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, "3".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(3)));
+        headers.insert(RETRY_AFTER, "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap());
+        assert_eq!(retry_after(&headers), None);
+    }
+
+    #[tokio::test]
+    async fn open_breaker_fails_fast_without_calling_upstream() {
+        let breaker = Arc::new(CircuitBreaker::new("chat", 1, Duration::from_secs(60)));
+        breaker.record_failure();
+        let client = ResilientClient::new(RetryPolicy::from_env("TEST_UNSET", 5), breaker);
+
+        let calls = AtomicUsize::new(0);
+        let result = client
+            .send(|client| {
+                calls.fetch_add(1, Ordering::Relaxed);
+                client.get("http://127.0.0.1:1/")
+            })
+            .await;
+        assert!(matches!(result, Err(HttpError::CircuitOpen { .. })));
+        assert_eq!(calls.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,49 @@
+//! Anonymous user identifier.
+//!
+//! There are no accounts: the client generates a random UUID once, keeps it on
+//! the device and sends it as `X-Anonymous-Id`. Everything the server stores for
+//! that user is keyed by this value, so it can be exported or purged on request.
+
+use crate::ErrorResponse;
+use axum::extract::{FromRequestParts, OptionalFromRequestParts};
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use axum::response::Response;
+use uuid::Uuid;
+
+/// Header carrying the anonymous user identifier
+pub const ANONYMOUS_ID_HEADER: &str = "x-anonymous-id";
+
+/// Validated anonymous user identifier taken from the `X-Anonymous-Id` header
+#[derive(Debug, Clone)]
+pub struct AnonymousUser(pub String);
+
+fn parse(parts: &Parts) -> Result<Option<AnonymousUser>, &'static str> {
+    let Some(value) = parts.headers.get(ANONYMOUS_ID_HEADER) else {
+        return Ok(None);
+    };
+    value
+        .to_str()
+        .ok()
+        .and_then(|v| Uuid::parse_str(v.trim()).ok())
+        .map(|id| Some(AnonymousUser(id.to_string())))
+        .ok_or("X-Anonymous-Id must be a UUID")
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for AnonymousUser {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parse(parts)
+            .and_then(|user| user.ok_or("X-Anonymous-Id header is required"))
+            .map_err(|e| ErrorResponse::respond(StatusCode::BAD_REQUEST, e))
+    }
+}
+
+impl<S: Send + Sync> OptionalFromRequestParts<S> for AnonymousUser {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Option<Self>, Self::Rejection> {
+        parse(parts).map_err(|e| ErrorResponse::respond(StatusCode::BAD_REQUEST, e))
+    }
+}
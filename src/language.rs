@@ -0,0 +1,55 @@
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// Languages the assistant answers in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    Id,
+    En,
+}
+
+impl Language {
+    /// English name of the language, as used in prompts
+    pub fn name(self) -> &'static str {
+        match self {
+            Language::Id => "Indonesian",
+            Language::En => "English",
+        }
+    }
+}
+
+/// Common Indonesian function words and slang used to tell Indonesian from English
+const INDONESIAN_MARKERS: &[&str] = &[
+    "aku", "saya", "gue", "gw", "kamu", "dia", "yang", "dan", "tidak", "nggak", "gak", "ga",
+    "ini", "itu", "di", "ke", "dari", "untuk", "dengan", "sudah", "udah", "lagi", "banget",
+    "mau", "ingin", "pengen", "merasa", "rasa", "sedih", "capek", "cemas", "hidup", "aja",
+    "saja", "karena", "tapi", "juga", "apa", "kenapa", "bagaimana", "gimana", "ya", "sih",
+];
+
+/// Common English function words
+const ENGLISH_MARKERS: &[&str] = &[
+    "i", "i'm", "im", "me", "my", "you", "the", "and", "is", "are", "am", "to", "of", "not",
+    "don't", "dont", "feel", "feeling", "want", "just", "it", "this", "that", "what", "why",
+    "how", "with", "have", "so", "but", "can't", "cant", "really",
+];
+
+/// Guess whether a message is Indonesian or English, defaulting to Indonesian
+pub fn detect(text: &str) -> Language {
+    let lowered = text.to_lowercase();
+    let (mut id, mut en) = (0usize, 0usize);
+    for word in lowered.split(|c: char| !(c.is_alphanumeric() || c == '\'')) {
+        if INDONESIAN_MARKERS.contains(&word) {
+            id += 1;
+        }
+        if ENGLISH_MARKERS.contains(&word) {
+            en += 1;
+        }
+    }
+
+    if en > id {
+        Language::En
+    } else {
+        Language::Id
+    }
+}
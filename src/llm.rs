@@ -0,0 +1,767 @@
+use crate::http::{HttpError, ResilientClient};
+use crate::Message;
+use futures::future::BoxFuture;
+use futures::stream::{self, BoxStream, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+const OPENROUTER_BASE_URL: &str = "https://openrouter.ai/api/v1";
+
+/// Sampling parameters forwarded to the model
+#[derive(Debug, Clone)]
+pub struct CompletionParams {
+    pub max_tokens: u32,
+    pub temperature: f32,
+}
+
+impl Default for CompletionParams {
+    fn default() -> Self {
+        Self {
+            max_tokens: 500,
+            temperature: 0.7,
+        }
+    }
+}
+
+/// A single chat completion request
+#[derive(Debug, Clone)]
+pub struct CompletionRequest {
+    pub model: String,
+    pub messages: Vec<Message>,
+    pub params: CompletionParams,
+}
+
+/// Result of a chat completion
+#[derive(Debug, Clone)]
+pub struct Completion {
+    /// Text of the first choice, `None` when the provider returned no choices
+    pub content: Option<String>,
+    /// Model that produced the completion, as reported by the provider
+    pub model: String,
+}
+
+/// Errors returned by chat providers
+#[derive(Debug)]
+pub enum ProviderError {
+    /// The provider could not be reached
+    Connect(String),
+    /// The provider answered with a non-success status
+    Upstream { status: u16, body: String },
+    /// The provider answered but the payload could not be understood
+    InvalidResponse(String),
+    /// The first choice carried no content (a refusal, a tool call or an empty completion)
+    EmptyCompletion(String),
+    /// No answer within the configured timeout, retries included
+    Timeout(String),
+    /// The circuit breaker is open, the provider was not called
+    Unavailable(String),
+}
+
+impl From<HttpError> for ProviderError {
+    fn from(error: HttpError) -> Self {
+        match error {
+            HttpError::Status { status, body, .. } => ProviderError::Upstream { status, body },
+            HttpError::Connect(e) => ProviderError::Connect(e),
+            HttpError::Timeout(_) => ProviderError::Timeout(error.to_string()),
+            HttpError::CircuitOpen { .. } => ProviderError::Unavailable(error.to_string()),
+        }
+    }
+}
+
+impl ProviderError {
+    /// Message that is safe to show to API clients
+    pub fn user_message(&self) -> &'static str {
+        match self {
+            ProviderError::Connect(_) => "Failed to connect to AI service",
+            ProviderError::Upstream { .. } | ProviderError::Unavailable(_) => "AI service temporarily unavailable",
+            ProviderError::InvalidResponse(_) => "Failed to process AI response",
+            ProviderError::EmptyCompletion(_) => "AI service returned an empty reply",
+            ProviderError::Timeout(_) => "AI service took too long to respond",
+        }
+    }
+}
+
+impl std::fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProviderError::Connect(e) => write!(f, "connection failed: {}", e),
+            ProviderError::Upstream { status, body } => write!(f, "upstream error: {} - {}", status, body),
+            ProviderError::InvalidResponse(e) => write!(f, "invalid response: {}", e),
+            ProviderError::EmptyCompletion(e) => write!(f, "empty completion: {}", e),
+            ProviderError::Timeout(e) => write!(f, "timed out: {}", e),
+            ProviderError::Unavailable(e) => write!(f, "unavailable: {}", e),
+        }
+    }
+}
+
+/// Stream of content deltas produced by a streaming completion
+pub type DeltaStream = BoxStream<'static, Result<String, ProviderError>>;
+
+/// A streaming completion that has started
+pub struct CompletionStream {
+    pub deltas: DeltaStream,
+    /// Model producing the deltas
+    pub model: String,
+}
+
+/// A backend capable of producing chat completions
+pub trait ChatProvider: Send + Sync {
+    /// Short identifier used in logs
+    fn name(&self) -> &str;
+
+    /// Run a chat completion and return the first choice
+    fn complete<'a>(
+        &'a self,
+        request: &'a CompletionRequest,
+    ) -> BoxFuture<'a, Result<Completion, ProviderError>>;
+
+    /// Run a streaming chat completion and yield content deltas as they arrive
+    fn stream<'a>(
+        &'a self,
+        request: &'a CompletionRequest,
+    ) -> BoxFuture<'a, Result<CompletionStream, ProviderError>>;
+}
+
+// ===== OpenAI-compatible wire types =====
+#[derive(Debug, Serialize)]
+struct ChatCompletionRequest<'a> {
+    model: &'a str,
+    messages: &'a [Message],
+    max_tokens: u32,
+    temperature: f32,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatCompletionResponse {
+    #[serde(default)]
+    model: Option<String>,
+    choices: Vec<ChatCompletionChoice>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatCompletionChoice {
+    message: ChatCompletionMessage,
+    #[serde(default)]
+    finish_reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatCompletionMessage {
+    /// `null` for refusals, tool calls and empty completions
+    #[serde(default)]
+    content: Option<String>,
+}
+
+impl ChatCompletionResponse {
+    /// First choice as a completion; a choice without content is an error so the caller
+    /// does not mistake it for a reply
+    fn into_completion(self, requested_model: &str) -> Result<Completion, ProviderError> {
+        let model = self.model.unwrap_or_else(|| requested_model.to_string());
+        let content = match self.choices.into_iter().next() {
+            Some(ChatCompletionChoice {
+                message: ChatCompletionMessage { content: Some(content) },
+                ..
+            }) => Some(content),
+            Some(choice) => {
+                return Err(ProviderError::EmptyCompletion(format!(
+                    "model {} returned no content (finish reason: {})",
+                    model,
+                    choice.finish_reason.as_deref().unwrap_or("unknown")
+                )))
+            }
+            None => None,
+        };
+        Ok(Completion { content, model })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatCompletionChunk {
+    choices: Vec<ChatCompletionChunkChoice>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatCompletionChunkChoice {
+    delta: ChatCompletionDelta,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatCompletionDelta {
+    #[serde(default)]
+    content: Option<String>,
+}
+
+/// State of an upstream server-sent events body being decoded into deltas
+struct SseDecoder<S> {
+    body: S,
+    buffer: Vec<u8>,
+    pending: VecDeque<Result<String, ProviderError>>,
+    done: bool,
+}
+
+impl<S> SseDecoder<S> {
+    /// Consume every complete line currently held in the buffer
+    fn drain_lines(&mut self) {
+        while let Some(newline) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=newline).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim();
+
+            // Blank separators and `:` keep-alive comments carry no data
+            let Some(data) = line.strip_prefix("data:") else {
+                continue;
+            };
+            let data = data.trim();
+            if data == "[DONE]" {
+                self.done = true;
+                return;
+            }
+
+            match serde_json::from_str::<ChatCompletionChunk>(data) {
+                Ok(chunk) => {
+                    if let Some(content) = chunk
+                        .choices
+                        .into_iter()
+                        .next()
+                        .and_then(|c| c.delta.content)
+                        .filter(|c| !c.is_empty())
+                    {
+                        self.pending.push_back(Ok(content));
+                    }
+                }
+                Err(e) => {
+                    self.pending.push_back(Err(ProviderError::InvalidResponse(e.to_string())));
+                    self.done = true;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Turn an OpenAI-style `text/event-stream` body into a stream of content deltas
+fn decode_sse<S, B>(body: S) -> DeltaStream
+where
+    S: Stream<Item = Result<B, reqwest::Error>> + Send + Unpin + 'static,
+    B: AsRef<[u8]>,
+{
+    let decoder = SseDecoder {
+        body,
+        buffer: Vec::new(),
+        pending: VecDeque::new(),
+        done: false,
+    };
+
+    stream::unfold(decoder, |mut decoder| async move {
+        loop {
+            if let Some(item) = decoder.pending.pop_front() {
+                return Some((item, decoder));
+            }
+            if decoder.done {
+                return None;
+            }
+            match decoder.body.next().await {
+                Some(Ok(chunk)) => {
+                    decoder.buffer.extend_from_slice(chunk.as_ref());
+                    decoder.drain_lines();
+                }
+                Some(Err(e)) => {
+                    decoder.done = true;
+                    return Some((Err(ProviderError::Connect(e.to_string())), decoder));
+                }
+                None => {
+                    // Flush a trailing line that was not newline-terminated
+                    decoder.buffer.push(b'\n');
+                    decoder.drain_lines();
+                    decoder.done = true;
+                }
+            }
+        }
+    })
+    .boxed()
+}
+
+/// Provider for any server exposing the OpenAI `/chat/completions` API
+/// (vLLM, llama.cpp server, Ollama, ...)
+pub struct OpenAiCompatibleProvider {
+    http: ResilientClient,
+    base_url: String,
+    api_key: Option<String>,
+    extra_headers: Vec<(&'static str, String)>,
+}
+
+impl OpenAiCompatibleProvider {
+    pub fn new(base_url: &str, api_key: Option<String>, http: ResilientClient) -> Self {
+        Self {
+            http,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key: api_key.filter(|k| !k.is_empty()),
+            extra_headers: Vec::new(),
+        }
+    }
+
+    fn endpoint(&self) -> String {
+        format!("{}/chat/completions", self.base_url)
+    }
+
+    async fn post(&self, request: &CompletionRequest, stream: bool) -> Result<reqwest::Response, ProviderError> {
+        let body = ChatCompletionRequest {
+            model: &request.model,
+            messages: &request.messages,
+            max_tokens: request.params.max_tokens,
+            temperature: request.params.temperature,
+            stream,
+        };
+
+        let endpoint = self.endpoint();
+        let response = self
+            .http
+            .send(|client| {
+                let mut builder = client.post(&endpoint).header("Content-Type", "application/json");
+                if let Some(api_key) = &self.api_key {
+                    builder = builder.header("Authorization", format!("Bearer {}", api_key));
+                }
+                for (name, value) in &self.extra_headers {
+                    builder = builder.header(*name, value);
+                }
+                builder.json(&body)
+            })
+            .await?;
+
+        Ok(response)
+    }
+
+    async fn send(&self, request: &CompletionRequest) -> Result<Completion, ProviderError> {
+        let response = self.post(request, false).await?;
+        let parsed: ChatCompletionResponse = response
+            .json()
+            .await
+            .map_err(|e| ProviderError::InvalidResponse(e.to_string()))?;
+
+        parsed.into_completion(&request.model)
+    }
+
+    async fn send_streaming(&self, request: &CompletionRequest) -> Result<CompletionStream, ProviderError> {
+        let response = self.post(request, true).await?;
+        Ok(CompletionStream {
+            deltas: decode_sse(response.bytes_stream()),
+            model: request.model.clone(),
+        })
+    }
+}
+
+impl ChatProvider for OpenAiCompatibleProvider {
+    fn name(&self) -> &str {
+        "openai-compatible"
+    }
+
+    fn complete<'a>(
+        &'a self,
+        request: &'a CompletionRequest,
+    ) -> BoxFuture<'a, Result<Completion, ProviderError>> {
+        Box::pin(self.send(request))
+    }
+
+    fn stream<'a>(
+        &'a self,
+        request: &'a CompletionRequest,
+    ) -> BoxFuture<'a, Result<CompletionStream, ProviderError>> {
+        Box::pin(self.send_streaming(request))
+    }
+}
+
+/// Provider for OpenRouter
+pub struct OpenRouterProvider {
+    inner: OpenAiCompatibleProvider,
+}
+
+impl OpenRouterProvider {
+    pub fn new(api_key: String, http: ResilientClient) -> Self {
+        let mut inner = OpenAiCompatibleProvider::new(OPENROUTER_BASE_URL, Some(api_key), http);
+        inner.extra_headers = vec![
+            ("HTTP-Referer", "https://Curhatin.app".to_string()),
+            ("X-Title", "Curhatin".to_string()),
+        ];
+        Self { inner }
+    }
+}
+
+impl ChatProvider for OpenRouterProvider {
+    fn name(&self) -> &str {
+        "openrouter"
+    }
+
+    fn complete<'a>(
+        &'a self,
+        request: &'a CompletionRequest,
+    ) -> BoxFuture<'a, Result<Completion, ProviderError>> {
+        Box::pin(self.inner.send(request))
+    }
+
+    fn stream<'a>(
+        &'a self,
+        request: &'a CompletionRequest,
+    ) -> BoxFuture<'a, Result<CompletionStream, ProviderError>> {
+        Box::pin(self.inner.send_streaming(request))
+    }
+}
+
+/// In-process provider that replays a fixed script of replies.
+///
+/// Replies are returned in order and the script wraps around when exhausted.
+/// An entry of the form `error:<status>` simulates an upstream failure.
+pub struct ScriptedProvider {
+    script: Vec<String>,
+    cursor: AtomicUsize,
+}
+
+impl ScriptedProvider {
+    pub fn new(script: Vec<String>) -> Self {
+        let script = if script.is_empty() {
+            vec!["I'm here to listen. How are you feeling today?".to_string()]
+        } else {
+            script
+        };
+        Self {
+            script,
+            cursor: AtomicUsize::new(0),
+        }
+    }
+
+    fn next_reply(&self) -> &str {
+        let index = self.cursor.fetch_add(1, Ordering::Relaxed) % self.script.len();
+        &self.script[index]
+    }
+}
+
+impl ChatProvider for ScriptedProvider {
+    fn name(&self) -> &str {
+        "mock"
+    }
+
+    fn complete<'a>(
+        &'a self,
+        request: &'a CompletionRequest,
+    ) -> BoxFuture<'a, Result<Completion, ProviderError>> {
+        let reply = self.next_reply();
+        let result = match reply.strip_prefix("error:") {
+            Some(status) => Err(ProviderError::Upstream {
+                status: status.trim().parse().unwrap_or(500),
+                body: "scripted failure".to_string(),
+            }),
+            None => Ok(Completion {
+                content: Some(reply.to_string()),
+                model: request.model.clone(),
+            }),
+        };
+        Box::pin(async move { result })
+    }
+
+    fn stream<'a>(
+        &'a self,
+        request: &'a CompletionRequest,
+    ) -> BoxFuture<'a, Result<CompletionStream, ProviderError>> {
+        Box::pin(async move {
+            let completion = self.complete(request).await?;
+            // Replay the scripted reply word by word, keeping the separating whitespace
+            let deltas: Vec<Result<String, ProviderError>> = completion
+                .content
+                .unwrap_or_default()
+                .split_inclusive(' ')
+                .map(|word| Ok(word.to_string()))
+                .collect();
+            Ok(CompletionStream {
+                deltas: stream::iter(deltas).boxed(),
+                model: completion.model,
+            })
+        })
+    }
+}
+
+/// Asks each provider and model in turn until one answers.
+///
+/// A step fails on any provider error, timeouts and open circuit breakers included.
+/// The model in the incoming request is ignored: every step carries its own. Streams only
+/// fall back while starting; once deltas flow, a broken stream is reported as is.
+pub struct FallbackProvider {
+    steps: Vec<(Arc<dyn ChatProvider>, String)>,
+}
+
+impl FallbackProvider {
+    pub fn new(steps: Vec<(Arc<dyn ChatProvider>, String)>) -> Self {
+        Self { steps }
+    }
+
+    fn step_request(request: &CompletionRequest, model: &str) -> CompletionRequest {
+        CompletionRequest {
+            model: model.to_string(),
+            ..request.clone()
+        }
+    }
+
+    fn log_failure(&self, index: usize, error: &ProviderError) {
+        let (provider, model) = &self.steps[index];
+        let next = self.steps.get(index + 1).map(|(_, model)| model.as_str());
+        match next {
+            Some(next) => tracing::warn!(
+                "Model {} via '{}' failed ({}), falling back to {}",
+                model,
+                provider.name(),
+                error,
+                next
+            ),
+            None => tracing::warn!("Model {} via '{}' failed ({}), no fallback left", model, provider.name(), error),
+        }
+    }
+
+    async fn complete_chain(&self, request: &CompletionRequest) -> Result<Completion, ProviderError> {
+        let mut last_error = None;
+        for (index, (provider, model)) in self.steps.iter().enumerate() {
+            match provider.complete(&Self::step_request(request, model)).await {
+                Ok(completion) => return Ok(completion),
+                Err(e) => {
+                    self.log_failure(index, &e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| ProviderError::Unavailable("no models configured".to_string())))
+    }
+
+    async fn stream_chain(&self, request: &CompletionRequest) -> Result<CompletionStream, ProviderError> {
+        let mut last_error = None;
+        for (index, (provider, model)) in self.steps.iter().enumerate() {
+            match provider.stream(&Self::step_request(request, model)).await {
+                Ok(stream) => return Ok(stream),
+                Err(e) => {
+                    self.log_failure(index, &e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| ProviderError::Unavailable("no models configured".to_string())))
+    }
+}
+
+impl ChatProvider for FallbackProvider {
+    fn name(&self) -> &str {
+        "fallback-chain"
+    }
+
+    fn complete<'a>(
+        &'a self,
+        request: &'a CompletionRequest,
+    ) -> BoxFuture<'a, Result<Completion, ProviderError>> {
+        Box::pin(self.complete_chain(request))
+    }
+
+    fn stream<'a>(
+        &'a self,
+        request: &'a CompletionRequest,
+    ) -> BoxFuture<'a, Result<CompletionStream, ProviderError>> {
+        Box::pin(self.stream_chain(request))
+    }
+}
+
+/// Provider selection, usually read from the environment
+#[derive(Debug, Clone)]
+pub enum ProviderConfig {
+    OpenRouter { api_key: String },
+    OpenAiCompatible { base_url: String, api_key: Option<String> },
+    Mock { script: Vec<String> },
+}
+
+impl ProviderConfig {
+    /// Read `LLM_PROVIDER` (`openrouter` | `openai-compatible` | `mock`) and its settings
+    pub fn from_env(openrouter_api_key: &str) -> Result<Self, String> {
+        let kind = std::env::var("LLM_PROVIDER").unwrap_or_else(|_| "openrouter".to_string());
+        Self::for_kind(&kind, openrouter_api_key)?.ok_or_else(|| format!("Unknown LLM_PROVIDER: {}", kind))
+    }
+
+    /// Settings for the provider called `kind`, `None` if there is no such provider
+    fn for_kind(kind: &str, openrouter_api_key: &str) -> Result<Option<Self>, String> {
+        let config = match kind.to_lowercase().as_str() {
+            "openrouter" => {
+                if openrouter_api_key.is_empty() {
+                    return Err("OPENROUTER_API_KEY must be set to use the openrouter provider".to_string());
+                }
+                ProviderConfig::OpenRouter {
+                    api_key: openrouter_api_key.to_string(),
+                }
+            }
+            "openai-compatible" | "openai" | "local" => {
+                let base_url = std::env::var("LLM_BASE_URL")
+                    .map_err(|_| "LLM_BASE_URL must be set to use the openai-compatible provider".to_string())?;
+                ProviderConfig::OpenAiCompatible {
+                    base_url,
+                    api_key: std::env::var("LLM_API_KEY").ok(),
+                }
+            }
+            "mock" => {
+                let script = match std::env::var("MOCK_LLM_RESPONSES") {
+                    Ok(raw) => serde_json::from_str::<Vec<String>>(&raw)
+                        .map_err(|e| format!("MOCK_LLM_RESPONSES must be a JSON array of strings: {}", e))?,
+                    Err(_) => Vec::new(),
+                };
+                ProviderConfig::Mock { script }
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(config))
+    }
+
+    /// `http` carries the timeouts, retries and circuit breaker for network providers
+    pub fn build(&self, http: ResilientClient) -> Arc<dyn ChatProvider> {
+        match self {
+            ProviderConfig::OpenRouter { api_key } => Arc::new(OpenRouterProvider::new(api_key.clone(), http)),
+            ProviderConfig::OpenAiCompatible { base_url, api_key } => {
+                Arc::new(OpenAiCompatibleProvider::new(base_url, api_key.clone(), http))
+            }
+            ProviderConfig::Mock { script } => Arc::new(ScriptedProvider::new(script.clone())),
+        }
+    }
+}
+
+/// A provider and the model to ask it for
+#[derive(Debug, Clone)]
+pub struct ChainEntry {
+    pub config: ProviderConfig,
+    pub model: String,
+}
+
+/// The primary provider and model followed by the entries of `LLM_FALLBACK_CHAIN`.
+///
+/// The variable is a comma-separated list of models tried in order when the previous one
+/// fails. An entry may name another provider as `<provider>:<model>`
+/// (`openai-compatible:llama3.1`); otherwise the primary provider is used. Model names
+/// that merely contain a colon (`deepseek/deepseek-chat:free`) are left alone.
+pub fn chain_from_env(primary: ChainEntry, openrouter_api_key: &str) -> Result<Vec<ChainEntry>, String> {
+    let mut chain = Vec::new();
+    for raw in std::env::var("LLM_FALLBACK_CHAIN").unwrap_or_default().split(',') {
+        let raw = raw.trim();
+        if raw.is_empty() {
+            continue;
+        }
+        let named = match raw.split_once(':') {
+            Some((kind, model)) => ProviderConfig::for_kind(kind, openrouter_api_key)
+                .map_err(|e| format!("LLM_FALLBACK_CHAIN entry '{}': {}", raw, e))?
+                .map(|config| (config, model.trim())),
+            None => None,
+        };
+        let (config, model) = named.unwrap_or_else(|| (primary.config.clone(), raw));
+        if model.is_empty() {
+            return Err(format!("LLM_FALLBACK_CHAIN entry '{}' has no model", raw));
+        }
+        chain.push(ChainEntry {
+            config,
+            model: model.to_string(),
+        });
+    }
+    chain.insert(0, primary);
+    Ok(chain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> CompletionRequest {
+        CompletionRequest {
+            model: "ignored".to_string(),
+            messages: Vec::new(),
+            params: CompletionParams::default(),
+        }
+    }
+
+    fn scripted(replies: &[&str]) -> Arc<dyn ChatProvider> {
+        Arc::new(ScriptedProvider::new(replies.iter().map(|r| r.to_string()).collect()))
+    }
+
+    fn chain(steps: &[(&[&str], &str)]) -> FallbackProvider {
+        FallbackProvider::new(
+            steps
+                .iter()
+                .map(|(replies, model)| (scripted(replies), model.to_string()))
+                .collect(),
+        )
+    }
+
+    fn parse(body: &str) -> Result<Completion, ProviderError> {
+        serde_json::from_str::<ChatCompletionResponse>(body)
+            .expect("response parses")
+            .into_completion("requested")
+    }
+
+    #[test]
+    fn completion_takes_the_first_choice_and_reported_model() {
+        let completion = parse(r#"{"model": "served", "choices": [{"message": {"content": "Hi"}}]}"#).unwrap();
+        assert_eq!(completion.content.as_deref(), Some("Hi"));
+        assert_eq!(completion.model, "served");
+        let completion = parse(r#"{"choices": []}"#).unwrap();
+        assert!(completion.content.is_none());
+        assert_eq!(completion.model, "requested");
+    }
+
+    #[test]
+    fn null_content_is_an_empty_completion_error() {
+        let body = r#"{"choices": [{"message": {"role": "assistant", "content": null, "refusal": "no"}, "finish_reason": "content_filter"}]}"#;
+        match parse(body) {
+            Err(ProviderError::EmptyCompletion(e)) => assert!(e.contains("content_filter"), "{}", e),
+            other => panic!("expected an empty completion error, got {:?}", other.map(|c| c.content)),
+        }
+        let body = r#"{"choices": [{"message": {"role": "assistant", "tool_calls": []}}]}"#;
+        assert!(matches!(parse(body), Err(ProviderError::EmptyCompletion(_))));
+    }
+
+    #[tokio::test]
+    async fn first_model_that_answers_wins() {
+        let provider = chain(&[(&["primary reply"], "model-a"), (&["fallback reply"], "model-b")]);
+        let completion = provider.complete(&request()).await.unwrap();
+        assert_eq!(completion.content.as_deref(), Some("primary reply"));
+        assert_eq!(completion.model, "model-a");
+    }
+
+    #[tokio::test]
+    async fn failing_models_are_skipped_in_order() {
+        let provider = chain(&[
+            (&["error:503"], "model-a"),
+            (&["error:429"], "model-b"),
+            (&["third reply"], "model-c"),
+        ]);
+        let completion = provider.complete(&request()).await.unwrap();
+        assert_eq!(completion.content.as_deref(), Some("third reply"));
+        assert_eq!(completion.model, "model-c");
+    }
+
+    #[tokio::test]
+    async fn the_last_error_is_reported_when_every_model_fails() {
+        let provider = chain(&[(&["error:503"], "model-a"), (&["error:502"], "model-b")]);
+        match provider.complete(&request()).await {
+            Err(ProviderError::Upstream { status, .. }) => assert_eq!(status, 502),
+            other => panic!("expected the last upstream error, got {:?}", other.map(|c| c.content)),
+        }
+    }
+
+    #[tokio::test]
+    async fn streams_fall_back_before_the_first_delta() {
+        let provider = chain(&[(&["error:500"], "model-a"), (&["hello there"], "model-b")]);
+        let stream = provider.stream(&request()).await.unwrap();
+        assert_eq!(stream.model, "model-b");
+        let text: Vec<String> = stream.deltas.map(|d| d.unwrap()).collect().await;
+        assert_eq!(text.concat(), "hello there");
+    }
+
+    #[tokio::test]
+    async fn scripted_provider_replays_its_script_in_a_loop() {
+        let provider = ScriptedProvider::new(vec!["one".to_string(), "two".to_string()]);
+        let mut replies = Vec::new();
+        for _ in 0..3 {
+            replies.push(provider.complete(&request()).await.unwrap().content.unwrap());
+        }
+        assert_eq!(replies, vec!["one", "two", "one"]);
+    }
+}
@@ -1,55 +1,126 @@
+mod auth;
+mod bm25;
+mod categories;
+mod chunker;
+mod crisis;
 mod db;
+mod degraded;
 mod embeddings;
+mod guardrails;
+mod http;
+mod identity;
+mod language;
+mod llm;
+mod prompts;
 mod rag;
+mod ratelimit;
+mod redaction;
+mod reembed;
+mod vector_index;
 
 use axum::{
-    extract::{Json, State},
+    extract::{DefaultBodyLimit, Json, Path, Query, State},
     http::StatusCode,
-    response::IntoResponse,
-    routing::{get, post},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    middleware,
+    routing::{delete, get, post},
     Router,
 };
+use auth::{ApiKeyDocument, Authorized, Scope};
+use categories::{Category, CategoryInfo, CategoryNames};
 use chrono::Utc;
-use db::{AppDatabase, KnowledgeDocument};
+use crisis::{CrisisMatch, Hotline};
+use db::{AppDatabase, ConversationMessage, ParentDocument, SessionDocument};
+use degraded::{DegradedMode, DegradedReply, Snippet};
 use embeddings::EmbeddingService;
-use rag::RagService;
+use guardrails::{GuardrailReport, StreamGuard};
+use http::{BreakerStatus, CircuitBreaker, CircuitState, ResilientClient};
+use identity::AnonymousUser;
+use language::Language;
+use mongodb::bson::doc;
+use prompts::PromptStore;
+use llm::{ChainEntry, ChatProvider, CompletionRequest, FallbackProvider, ProviderConfig};
+use chunker::ChunkerConfig;
+use rag::{
+    CategoryMode, IngestOutcome, IngestStatus, NearDuplicate, NewDocument, RagService, RetrievalBackend, RetrievalConfig,
+    RetrievalOverrides,
+};
+use ratelimit::{RateLimits, UpstreamLimiter};
+use redaction::PiiVault;
+use reembed::ReembedProgress;
+use futures::stream::{self, StreamExt, TryStreamExt};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use tokio::sync::mpsc;
 use tower_http::cors::{Any, CorsLayer};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-use utoipa::{OpenApi, ToSchema};
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, SecurityScheme};
+use utoipa::{IntoParams, Modify, OpenApi, ToSchema};
 use utoipa_swagger_ui::SwaggerUi;
 use uuid::Uuid;
 
 // ===== Configuration =====
 struct AppConfig {
-    openrouter_api_key: String,
-    openrouter_model: String,
+    chat_model: String,
+    /// Put redacted personal data back into the reply shown to the user
+    restore_pii: bool,
+    /// Idle time after which a chat session expires
+    session_ttl: chrono::Duration,
+    /// Require API keys on every `/api` route
+    auth_enabled: bool,
 }
 
 // ===== Shared State =====
 struct AppState {
     config: AppConfig,
     db: AppDatabase,
-    embedding_service: EmbeddingService,
+    rag: RagService,
+    provider: Arc<dyn ChatProvider>,
+    prompts: Arc<PromptStore>,
+    rate_limits: RateLimits,
+    /// Caps concurrent calls to the chat provider
+    upstream: UpstreamLimiter,
+    /// Breakers of the outbound HTTP clients, reported by `/health`
+    breakers: Vec<Arc<CircuitBreaker>>,
+    /// Answers chats when the model fails
+    degraded: DegradedMode,
 }
 
 // ===== Request/Response Types =====
 #[derive(Debug, Serialize, ToSchema)]
 struct HealthResponse {
+    /// `ok`, or `degraded` while an upstream circuit breaker is not closed
     status: String,
     message: String,
+    /// Circuit breakers of the chat provider and the embeddings API
+    upstreams: Vec<BreakerStatus>,
+    /// Chat replies served in degraded mode since startup
+    degraded_responses: u64,
 }
 
 #[derive(Debug, Deserialize, ToSchema)]
 pub struct ChatRequest {
     #[schema(example = "Halo, saya merasa cemas")]
     message: String,
+    /// Category id or alias from `GET /api/categories`; defaults to `general`
     #[schema(example = "general")]
     category: Option<String>,
+    /// Ignored when `session_id` is set: the stored transcript is used instead
     #[serde(default)]
     #[schema(default)]
     conversation_history: Vec<Message>,
+    /// Session created with `POST /api/sessions`
+    #[serde(default)]
+    session_id: Option<String>,
+    /// Override the deployment's retrieval settings, within its bounds
+    #[serde(default)]
+    retrieval: Option<RetrievalOverrides>,
 }
 
 #[derive(Debug, Serialize, ToSchema)]
@@ -59,6 +130,100 @@ struct ChatResponse {
     error: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     sources: Option<Vec<String>>,
+    /// True when a crisis phrase was detected and the model was bypassed
+    crisis: bool,
+    /// True when the model failed and a fixed supportive message was sent instead
+    degraded: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hotlines: Option<Vec<Hotline>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata: Option<ResponseMetadata>,
+}
+
+/// Details about how a reply was produced
+#[derive(Debug, Default, Serialize, ToSchema)]
+struct ResponseMetadata {
+    /// Model that produced the reply; with a fallback chain, the one that answered, and after
+    /// a guardrail regeneration, the one that regenerated it. Absent when the reply is the
+    /// guardrail fallback message
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(example = "openai/gpt-4o-mini")]
+    model: Option<String>,
+    /// Guardrails that fired on the model reply and what was done about them
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    guardrails: Vec<GuardrailReport>,
+}
+
+impl ResponseMetadata {
+    /// `None` when there is nothing worth reporting
+    fn into_option(self) -> Option<Self> {
+        if self.model.is_none() && self.guardrails.is_empty() {
+            None
+        } else {
+            Some(self)
+        }
+    }
+}
+
+impl ChatResponse {
+    fn error(message: impl Into<String>) -> Self {
+        Self {
+            response: String::new(),
+            error: Some(message.into()),
+            sources: None,
+            crisis: false,
+            degraded: false,
+            hotlines: None,
+            metadata: None,
+        }
+    }
+
+    fn crisis(detected: &CrisisMatch) -> Self {
+        Self {
+            response: crisis::response_text(detected.language),
+            error: None,
+            sources: None,
+            crisis: true,
+            degraded: false,
+            hotlines: Some(crisis::hotlines()),
+            metadata: None,
+        }
+    }
+
+    fn degraded(reply: DegradedReply) -> Self {
+        Self {
+            response: reply.text,
+            error: None,
+            sources: reply.source.map(|title| vec![title]),
+            crisis: false,
+            degraded: true,
+            hotlines: reply.hotlines,
+            metadata: None,
+        }
+    }
+}
+
+/// Payload of a `delta` event on `/api/chat/stream`
+#[derive(Debug, Serialize, ToSchema)]
+struct ChatStreamDelta {
+    content: String,
+}
+
+/// Payload of the final `done` event on `/api/chat/stream`
+#[derive(Debug, Default, Serialize, ToSchema)]
+struct ChatStreamDone {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sources: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// True when a crisis phrase was detected and the model was bypassed
+    crisis: bool,
+    /// True when the model failed and the deltas carry a fixed supportive message
+    degraded: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hotlines: Option<Vec<Hotline>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata: Option<ResponseMetadata>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
@@ -69,188 +234,338 @@ pub struct Message {
     content: String,
 }
 
+#[derive(Debug, Default, Deserialize, ToSchema)]
+struct CreateSessionRequest {
+    /// Default category for every message in the session, see `GET /api/categories`
+    #[serde(default)]
+    #[schema(example = "karir")]
+    category: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct SessionResponse {
+    session_id: String,
+    expires_at: chrono::DateTime<Utc>,
+}
+
+/// Everything stored about one anonymous user
+#[derive(Debug, Serialize, ToSchema)]
+struct UserExport {
+    user_id: String,
+    exported_at: chrono::DateTime<Utc>,
+    /// Documents per collection, as relaxed extended JSON
+    #[schema(value_type = Object)]
+    collections: BTreeMap<String, Vec<serde_json::Value>>,
+    /// Kinds of data never stored about a user, so absent from the export
+    not_stored: Vec<String>,
+}
+
+/// Proof that a user's data was deleted
+#[derive(Debug, Serialize, ToSchema)]
+struct DeletionReceipt {
+    receipt_id: String,
+    deleted_at: chrono::DateTime<Utc>,
+    /// Number of deleted documents per collection
+    deleted: BTreeMap<String, u64>,
+    /// Kinds of data never stored about a user, so there was nothing to delete
+    not_stored: Vec<String>,
+}
+
+impl DeletionReceipt {
+    fn new(deleted: BTreeMap<String, u64>) -> Self {
+        Self {
+            receipt_id: Uuid::new_v4().to_string(),
+            deleted_at: Utc::now(),
+            deleted,
+            not_stored: not_stored_user_data(),
+        }
+    }
+}
+
+fn not_stored_user_data() -> Vec<String> {
+    db::NOT_STORED_USER_DATA.iter().map(|s| s.to_string()).collect()
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct CreateApiKeyRequest {
+    #[schema(example = "mobile-app")]
+    name: String,
+    #[schema(example = json!(["chat"]))]
+    scopes: Vec<Scope>,
+}
+
+/// API key metadata, safe to list
+#[derive(Debug, Serialize, ToSchema)]
+struct ApiKeyInfo {
+    id: String,
+    name: String,
+    prefix: String,
+    scopes: Vec<Scope>,
+    created_at: chrono::DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    revoked_at: Option<chrono::DateTime<Utc>>,
+}
+
+impl From<ApiKeyDocument> for ApiKeyInfo {
+    fn from(document: ApiKeyDocument) -> Self {
+        Self {
+            id: document.id,
+            name: document.name,
+            prefix: document.prefix,
+            scopes: document.scopes,
+            created_at: document.created_at,
+            revoked_at: document.revoked_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct CreatedApiKey {
+    /// The full key; store it now, it cannot be retrieved again
+    key: String,
+    #[serde(flatten)]
+    info: ApiKeyInfo,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct ReembedRequest {
+    /// Embedding model to move the knowledge base to
+    #[schema(example = "openai/text-embedding-3-large")]
+    model: String,
+}
+
+/// Embedding model in use and how far the knowledge base is from it
+#[derive(Debug, Serialize, ToSchema)]
+struct EmbeddingStatus {
+    /// Model used for queries and new chunks
+    active_model: String,
+    /// Number of chunks per embedding model; only chunks of `active_model` are retrieved
+    chunks_per_model: BTreeMap<String, u64>,
+    job: ReembedProgress,
+}
+
+/// Error body for endpoints without a richer response type
+#[derive(Debug, Serialize, ToSchema)]
+struct ErrorResponse {
+    error: String,
+}
+
+impl ErrorResponse {
+    fn respond(status: StatusCode, message: impl Into<String>) -> Response {
+        (status, Json(ErrorResponse { error: message.into() })).into_response()
+    }
+}
+
 #[derive(Debug, Deserialize, ToSchema)]
 struct IngestRequest {
     title: String,
     content: String,
     category: String,
+    /// If the same content is already stored, update its title and category instead of
+    /// just returning its id
+    #[serde(default)]
+    #[schema(default)]
+    upsert: bool,
 }
 
-#[derive(Debug, Serialize, ToSchema)]
+#[derive(Debug, Default, Serialize, ToSchema)]
 struct IngestResponse {
     success: bool,
+    /// The new document, or the existing one when the content was already stored
     id: String,
+    /// Number of chunks the document was split into
+    #[serde(skip_serializing_if = "Option::is_none")]
+    chunks: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<IngestStatus>,
+    /// Set when very similar documents already exist; the document was stored anyway
+    #[serde(skip_serializing_if = "Option::is_none")]
+    warning: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    near_duplicates: Vec<NearDuplicate>,
     #[serde(skip_serializing_if = "Option::is_none")]
     error: Option<String>,
 }
 
-// ===== OpenRouter Types =====
-#[derive(Debug, Serialize)]
-struct OpenRouterRequest {
-    model: String,
-    messages: Vec<Message>,
-    max_tokens: u32,
-    temperature: f32,
+impl IngestResponse {
+    fn failed(error: impl Into<String>) -> Self {
+        Self {
+            error: Some(error.into()),
+            ..Default::default()
+        }
+    }
 }
 
-#[derive(Debug, Deserialize)]
-struct OpenRouterResponse {
-    choices: Vec<OpenRouterChoice>,
+impl From<IngestOutcome> for IngestResponse {
+    fn from(outcome: IngestOutcome) -> Self {
+        Self {
+            success: true,
+            id: outcome.document.id,
+            chunks: Some(outcome.document.chunk_count),
+            status: Some(outcome.status),
+            warning: near_duplicate_warning(&outcome.near_duplicates),
+            near_duplicates: outcome.near_duplicates,
+            error: None,
+        }
+    }
 }
 
-#[derive(Debug, Deserialize)]
-struct OpenRouterChoice {
-    message: OpenRouterMessage,
+fn near_duplicate_warning(near_duplicates: &[NearDuplicate]) -> Option<String> {
+    let closest = near_duplicates.first()?;
+    Some(format!(
+        "Very similar to {} existing document(s), closest '{}' ({:.2})",
+        near_duplicates.len(),
+        closest.title,
+        closest.similarity
+    ))
 }
 
-#[derive(Debug, Deserialize)]
-struct OpenRouterMessage {
-    content: String,
+impl From<IngestRequest> for NewDocument {
+    fn from(request: IngestRequest) -> Self {
+        Self {
+            title: request.title,
+            category: request.category,
+            content: request.content,
+            upsert: request.upsert,
+        }
+    }
+}
+
+/// Upper bound on documents in one bulk ingest request
+const BULK_INGEST_MAX_DOCUMENTS: usize = 500;
+/// Bulk ingest bodies may exceed axum's default 2 MB limit
+const BULK_INGEST_MAX_BYTES: usize = 16 * 1024 * 1024;
+
+/// Outcome for one document of a bulk ingest
+#[derive(Debug, Default, Serialize, ToSchema)]
+struct BulkIngestItem {
+    /// Position of the document in the request
+    index: usize,
+    success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    chunks: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<IngestStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    warning: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    near_duplicates: Vec<NearDuplicate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct BulkIngestResponse {
+    total: usize,
+    succeeded: usize,
+    failed: usize,
+    results: Vec<BulkIngestItem>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+struct KnowledgeListQuery {
+    /// Page number, starting at 1
+    page: Option<u64>,
+    /// Items per page (default 20, at most 100)
+    per_page: Option<u64>,
+    /// Only documents in this category
+    category: Option<String>,
 }
 
-// ===== Mental Health System Prompt =====
-const SYSTEM_PROMPT_GENERAL: &str = r#"You are a compassionate mental wellness companion called Curhatin Assistant. Your role is to provide a safe space for reflection and emotional support.
-
-## Your Approach:
-- Listen with genuine empathy and reflect back what users share
-- Ask thoughtful, open-ended questions to help users explore their feelings
-- Summarize and validate emotions without judgment
-- Use warm, supportive language that feels natural and caring
-- Be present and patient, not rushing to solve problems
-
-## Important Boundaries (NEVER violate these):
-1. NEVER diagnose mental health conditions (no "you might have depression/anxiety")
-2. NEVER prescribe treatments, medications, or specific therapies
-3. NEVER give direct advice like "You should..." or "You must..."
-4. NEVER claim to be a therapist, doctor, or medical professional
-5. If someone expresses thoughts of self-harm or suicide, respond with:
-   - Acknowledge their pain with compassion
-   - Gently encourage them to reach out to crisis support:
-     "I hear that you're going through something really difficult. Please consider reaching out to a crisis helpline - in Indonesia you can contact Into The Light (119 ext 8) or Yayasan Pulih (021-788-42580). You deserve support from people who can truly help."
-
-## Response Style:
-- Keep responses warm but concise (2-4 paragraphs max)
-- Use reflective statements: "It sounds like...", "I hear that..."
-- Ask one thoughtful question at a time to encourage deeper reflection
-- Validate feelings before exploring further
-- Respond in the same language the user writes in (Indonesian or English)
-
-
-
-const SYSTEM_PROMPT_GENERAL: &str = r#"You are a compassionate mental wellness companion called Curhatin Assistant. Your role is to provide a safe space for reflection and emotional support.
-
-## Your Approach:
-- Listen with genuine empathy and reflect back what users share
-- Ask thoughtful, open-ended questions to help users explore their feelings
-- Summarize and validate emotions without judgment
-- Use warm, supportive language that feels natural and caring
-- Be present and patient, not rushing to solve problems
-
-## Important Boundaries (NEVER violate these):
-1. NEVER diagnose mental health conditions (no "you might have depression/anxiety")
-2. NEVER prescribe treatments, medications, or specific therapies
-3. NEVER give direct advice like "You should..." or "You must..."
-4. NEVER claim to be a therapist, doctor, or medical professional
-5. If someone expresses thoughts of self-harm or suicide, respond with:
-   - Acknowledge their pain with compassion
-   - Gently encourage them to reach out to crisis support:
-     "I hear that you're going through something really difficult. Please consider reaching out to a crisis helpline - in Indonesia you can contact Into The Light (119 ext 8) or Yayasan Pulih (021-788-42580). You deserve support from people who can truly help."
-
-## Response Style:
-- Keep responses warm but concise (2-4 paragraphs max)
-- Use reflective statements: "It sounds like...", "I hear that..."
-- Ask one thoughtful question at a time to encourage deeper reflection
-- Validate feelings before exploring further
-- Respond in the same language the user writes in (Indonesian or English)
-- If the user discusses a specific topic (Career, Romance, etc.), maintain this general supportive stance but acknowledge the context.
-
-Remember: You are a mirror for reflection, not a problem-solver. Help users discover their own insights."#;
-
-const SYSTEM_PROMPT_CAREER: &str = r#"You are a supportive career confident and mental wellness companion called Curhatin Assistant. Your role is to listen to career-related concerns (burnout, office politics, direction, failure) and help the user reflect.
-
-## Your Approach:
-- Focus on the user's feelings about their work, not just the technical details.
-- Validate feelings of stress, inadequacy, or confusion.
-- Ask questions that help them clarify their values and what they want from their career.
-- Avoid giving specific career advice (e.g., "apply to this job"), instead help them uncover their own answers.
-
-## Important Boundaries:
-- adhere to the same safety and non-medical boundaries as the General prompt.
-
-## Response Style:
-- Professional yet empathetic tone.
-- Use phrases like "It sounds like this situation is draining you..." or "What does success look like to you in this context?"
-"#;
-
-const SYSTEM_PROMPT_ROMANCE: &str = r#"You are a compassionate relationship confidant and mental wellness companion called Curhatin Assistant. Your role is to listen to concerns about love, dating, breakups, and loneliness.
-
-## Your Approach:
-- Create a safe space to vent about heartbreaks or relationship anxiety.
-- Validate feelings of rejection, love, or confusion without taking sides (if they complain about a partner).
-- Encourage healthy communication and self-respect.
-- Help them distinguish between what they can control and what they cannot.
-
-## Important Boundaries:
-- adhere to the same safety and non-medical boundaries as the General prompt.
-
-## Response Style:
-- Warm, gentle, and understanding.
-- Use phrases like "It hurts to feel disconnected..." or "What do you need most from a partner right now?"
-"#;
-
-const SYSTEM_PROMPT_FAMILY: &str = r#"You are a compassionate listener for family matters, called Curhatin Assistant. Your role is to support users dealing with family conflict, distance, or expectations.
-
-## Your Approach:
-- Validate the complexity of family dynamics (guilt, obligation, love).
-- Help the user establish healthy boundaries in their mind.
-- Encourage empathy for themselves and family members (where safe).
-
-## Important Boundaries:
-- adhere to the same safety and non-medical boundaries as the General prompt.
-
-## Response Style:
-- Respectful of cultural nuances regarding family.
-- Gentle and grounding.
-"#;
-
-const SYSTEM_PROMPT_SELF_DEVELOPMENT: &str = r#"You are a growth-oriented companion called Curhatin Assistant. Your role is to support the user in their journey of self-improvement, habits, and self-worth.
-
-## Your Approach:
-- Celebrate small wins and intentions.
-- Help them explore "why" they want to change or grow.
-- Be a sounding board for their goals, helping them break down overwhelming feelings.
-- Challenge negative self-talk gently.
-
-## Important Boundaries:
-- adhere to the same safety and non-medical boundaries as the General prompt.
+/// Knowledge document without its content
+#[derive(Debug, Serialize, ToSchema)]
+struct KnowledgeSummary {
+    id: String,
+    title: String,
+    category: String,
+    chunk_count: u32,
+    created_at: chrono::DateTime<Utc>,
+}
+
+impl From<ParentDocument> for KnowledgeSummary {
+    fn from(document: ParentDocument) -> Self {
+        Self {
+            id: document.id,
+            title: document.title,
+            category: document.category,
+            chunk_count: document.chunk_count,
+            created_at: document.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct KnowledgeListResponse {
+    items: Vec<KnowledgeSummary>,
+    page: u64,
+    per_page: u64,
+    total: u64,
+}
 
-## Response Style:
-- Encouraging, motivating (but not "toxic positivity"), and reflective.
-"#;
+/// Knowledge document as ingested (embeddings are never returned)
+#[derive(Debug, Serialize, ToSchema)]
+struct KnowledgeItem {
+    id: String,
+    title: String,
+    category: String,
+    content: String,
+    chunk_count: u32,
+    created_at: chrono::DateTime<Utc>,
+}
 
-fn get_system_prompt(category: Option<&str>) -> String {
-    match category.unwrap_or("general").to_lowercase().as_str() {
-        "karir" | "career" => format!("{}\n\n{}", SYSTEM_PROMPT_GENERAL, SYSTEM_PROMPT_CAREER),
-        "asmara" | "romance" | "love" => format!("{}\n\n{}", SYSTEM_PROMPT_GENERAL, SYSTEM_PROMPT_ROMANCE),
-        "keluarga" | "family" => format!("{}\n\n{}", SYSTEM_PROMPT_GENERAL, SYSTEM_PROMPT_FAMILY),
-        "pengembangan diri" | "self development" | "growth" => format!("{}\n\n{}", SYSTEM_PROMPT_GENERAL, SYSTEM_PROMPT_SELF_DEVELOPMENT),
-        _ => SYSTEM_PROMPT_GENERAL.to_string(),
+impl From<ParentDocument> for KnowledgeItem {
+    fn from(document: ParentDocument) -> Self {
+        Self {
+            id: document.id,
+            title: document.title,
+            category: document.category,
+            content: document.content,
+            chunk_count: document.chunk_count,
+            created_at: document.created_at,
+        }
     }
 }
 
+/// Fields to change; a new `content` is re-chunked and re-embedded
+#[derive(Debug, Deserialize, ToSchema)]
+struct UpdateKnowledgeRequest {
+    title: Option<String>,
+    category: Option<String>,
+    content: Option<String>,
+}
+
 // ===== ApiDoc =====
 #[derive(OpenApi)]
 #[openapi(
-    paths(health_check, chat, ingest_document),
+    paths(health_check, list_categories, chat, chat_stream, create_session, delete_session, export_me, delete_me, create_api_key, list_api_keys, revoke_api_key, embedding_status, start_reembed, ingest_document, ingest_bulk, list_knowledge, get_knowledge, update_knowledge, delete_knowledge),
     components(
-        schemas(HealthResponse, ChatRequest, ChatResponse, Message, IngestRequest, IngestResponse)
+        schemas(HealthResponse, BreakerStatus, CircuitState, CategoryInfo, CategoryNames, ChatRequest, RetrievalOverrides, ChatResponse, ChatStreamDelta, ChatStreamDone, ResponseMetadata, GuardrailReport, guardrails::GuardrailRule, guardrails::GuardrailAction, Hotline, Message, CreateSessionRequest, SessionResponse, UserExport, DeletionReceipt, Scope, CreateApiKeyRequest, ApiKeyInfo, CreatedApiKey, ReembedRequest, EmbeddingStatus, ReembedProgress, reembed::JobState, ErrorResponse, IngestRequest, IngestResponse, IngestStatus, NearDuplicate, BulkIngestItem, BulkIngestResponse, KnowledgeSummary, KnowledgeListResponse, KnowledgeItem, UpdateKnowledgeRequest)
     ),
+    modifiers(&SecurityAddon),
     tags(
         (name = "ai-mental-chatbot", description = "AI Mental Chatbot Backend API")
     )
 )]
 struct ApiDoc;
 
+/// Registers the `X-API-Key` security scheme used by protected paths
+struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "api_key",
+                SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-API-Key"))),
+            );
+        }
+    }
+}
+
 // ===== Handlers =====
 
 /// Health check endpoint
@@ -268,256 +583,1291 @@ async fn health_check(State(state): State<Arc<AppState>>) -> impl IntoResponse {
         Err(_) => "disconnected",
     };
     
+    let upstreams: Vec<BreakerStatus> = state.breakers.iter().map(|b| b.status()).collect();
+    let degraded = upstreams.iter().any(|u| u.state != CircuitState::Closed);
+
     Json(HealthResponse {
-        status: "ok".to_string(),
+        status: if degraded { "degraded" } else { "ok" }.to_string(),
         message: format!("AI Mental Chatbot Backend is running. MongoDB: {}", db_status),
+        upstreams,
+        degraded_responses: state.degraded.served(),
     })
 }
 
-/// Chat with AI
+/// Chat categories accepted in the `category` field, in display order
+///
+/// Public: the list is static and the frontend needs it before it has a key.
 #[utoipa::path(
-    post,
-    path = "/api/chat",
-    request_body = ChatRequest,
+    get,
+    path = "/api/categories",
     responses(
-        (status = 200, description = "Chat response", body = ChatResponse),
-        (status = 400, description = "Bad request", body = ChatResponse),
-        (status = 500, description = "Internal server error")
+        (status = 200, description = "Available categories", body = [CategoryInfo])
     )
 )]
-async fn chat(
-    State(state): State<Arc<AppState>>,
-    Json(payload): Json<ChatRequest>,
-) -> impl IntoResponse {
+async fn list_categories() -> Json<Vec<CategoryInfo>> {
+    Json(categories::all().iter().map(CategoryInfo::from).collect())
+}
+
+/// Session turn to persist once the reply is known
+struct SessionTurn {
+    session_id: String,
+    /// Redacted user message
+    user_message: String,
+}
+
+/// A completion ready to be sent to the model
+struct GeneratePlan {
+    request: CompletionRequest,
+    sources: Option<Vec<String>>,
+    /// Best knowledge excerpt, quoted if the model fails
+    snippet: Option<Snippet>,
+    language: Language,
+    /// Placeholders used in `request`, for restoring the reply
+    pii: PiiVault,
+    session: Option<SessionTurn>,
+}
+
+/// What a chat request resolves to before the model is called
+enum ChatPlan {
+    /// Send this completion request to the model
+    Generate(GeneratePlan),
+    /// A crisis phrase was detected; answer with the fixed crisis response instead
+    Crisis {
+        detected: CrisisMatch,
+        session: Option<SessionTurn>,
+    },
+}
+
+/// Roles a client is allowed to put in `conversation_history`
+fn is_conversation_role(role: &str) -> bool {
+    role == "user" || role == "assistant"
+}
+
+/// Category stored on a session; sessions created before the registry may hold a name it
+/// does not know, those fall back to the default category
+fn session_category(name: Option<&str>) -> &'static Category {
+    categories::resolve(name).unwrap_or_else(|e| {
+        tracing::warn!("Session category ignored: {}", e);
+        categories::default_category()
+    })
+}
+
+/// Validate a chat request and plan the reply shared by `chat` and `chat_stream`:
+/// either the crisis bypass, or a completion request made of the persona system prompt,
+/// RAG context, trimmed history and the new user message.
+///
+/// A session is only found for the user it was created for.
+async fn prepare_chat(
+    state: &AppState,
+    payload: ChatRequest,
+    user: Option<AnonymousUser>,
+) -> Result<ChatPlan, (StatusCode, String)> {
+    // Hard-coded crisis rule: runs before any validation and never reaches the model,
+    // so a bad category or an expired session cannot hold back the hotlines
+    if let Some(detected) = crisis::detect(&payload.message) {
+        tracing::warn!("Crisis phrase '{}' detected, bypassing the model", detected.phrase);
+        let session = crisis_session(state, &payload, user).await;
+        return Ok(ChatPlan::Crisis { detected, session });
+    }
+
     // Validate input
     if payload.message.trim().is_empty() {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(ChatResponse {
-                response: String::new(),
-                error: Some("Message cannot be empty".to_string()),
-                sources: None,
-            }),
-        );
+        return Err((StatusCode::BAD_REQUEST, "Message cannot be empty".to_string()));
     }
+    let retrieval = state
+        .rag
+        .retrieval_config()
+        .resolve(payload.retrieval.as_ref())
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid retrieval settings: {}", e)))?;
+
+    // Unknown categories are rejected instead of silently becoming the general persona
+    let requested = match payload.category.as_deref() {
+        Some(name) => Some(categories::resolve(Some(name)).map_err(|e| (StatusCode::BAD_REQUEST, e))?),
+        None => None,
+    };
 
-    // Create RAG service and retrieve context
-    let rag_service = RagService::new(state.db.clone(), EmbeddingService::new(state.config.openrouter_api_key.clone()));
-    
-    let (augmented_prompt, sources) = match rag_service.retrieve_context(&payload.message, 3).await {
+    // With a session, the stored transcript replaces any client-supplied history
+    let (history, category) = match &payload.session_id {
+        Some(session_id) => {
+            let session = state
+                .db
+                .find_session(session_id, user.as_ref().map(|u| u.0.as_str()))
+                .await
+                .map_err(|e| {
+                    tracing::error!("Failed to load session: {}", e);
+                    (StatusCode::INTERNAL_SERVER_ERROR, "Failed to load session".to_string())
+                })?
+                .ok_or((StatusCode::NOT_FOUND, "Session not found or expired".to_string()))?;
+            let history: Vec<Message> = session
+                .messages
+                .into_iter()
+                .map(|m| Message {
+                    role: m.role,
+                    content: m.content,
+                })
+                .collect();
+            let category = requested.unwrap_or_else(|| session_category(session.category.as_deref()));
+            (history, category)
+        }
+        None => {
+            let history: Vec<Message> = payload
+                .conversation_history
+                .into_iter()
+                .filter(|m| is_conversation_role(&m.role))
+                .collect();
+            (history, requested.unwrap_or_else(categories::default_category))
+        }
+    };
+
+    let language = language::detect(&payload.message);
+
+    // Redact personal data before anything leaves the server
+    // (history is limited to the last 10 messages for context)
+    let history_start = if history.len() > 10 {
+        history.len() - 10
+    } else {
+        0
+    };
+    let history = &history[history_start..];
+    let mut texts: Vec<String> = history.iter().map(|m| m.content.clone()).collect();
+    texts.push(payload.message.clone());
+    let mut pii = PiiVault::new();
+    pii.redact_all(&mut texts);
+    let message = texts.pop().unwrap_or_default();
+    if !pii.is_empty() {
+        tracing::debug!("Redacted {} PII value(s) from the conversation", pii.len());
+    }
+
+    let session = payload.session_id.map(|session_id| SessionTurn {
+        session_id,
+        user_message: message.clone(),
+    });
+
+    // Retrieve context from the knowledge base
+    let system_prompt = state.prompts.system_prompt(category.prompt, language);
+    let (mut augmented_prompt, sources, snippet) = match state.rag.retrieve_context(&message, &retrieval, category.knowledge_categories).await {
         Ok(context) => {
-            let sources: Vec<String> = context.iter().map(|d| d.title.clone()).collect();
-            let prompt = rag_service.augment_prompt(&get_system_prompt(payload.category.as_deref()), &context);
-            (prompt, if sources.is_empty() { None } else { Some(sources) })
+            let sources = rag::source_titles(&context);
+            let prompt = state.rag.augment_prompt(&system_prompt, &context);
+            (prompt, if sources.is_empty() { None } else { Some(sources) }, Snippet::from_context(&context))
         }
         Err(e) => {
             tracing::warn!("RAG retrieval failed, using base prompt: {}", e);
-            (get_system_prompt(payload.category.as_deref()), None)
+            (system_prompt, None, None)
         }
     };
 
+    if !pii.is_empty() {
+        augmented_prompt.push_str(
+            "\n\nNote: personal details in the conversation were replaced with placeholders such as [NAME_1] or [PHONE_1]. Refer to them using the same placeholders and never guess the real values.",
+        );
+    }
+
     // Build messages with system prompt and conversation history
     let mut messages = vec![Message {
         role: "system".to_string(),
         content: augmented_prompt,
     }];
 
-    // Add conversation history (limited to last 10 messages for context)
-    let history_start = if payload.conversation_history.len() > 10 {
-        payload.conversation_history.len() - 10
-    } else {
-        0
-    };
-    messages.extend(payload.conversation_history[history_start..].to_vec());
+    messages.extend(history.iter().zip(texts).map(|(m, content)| Message {
+        role: m.role.clone(),
+        content,
+    }));
 
     // Add current user message
     messages.push(Message {
         role: "user".to_string(),
-        content: payload.message,
+        content: message,
     });
 
-    // Call OpenRouter API
-    let client = reqwest::Client::new();
-    let openrouter_request = OpenRouterRequest {
-        model: state.config.openrouter_model.clone(),
-        messages,
-        max_tokens: 500,
-        temperature: 0.7,
-    };
-
-    let response = client
-        .post("https://openrouter.ai/api/v1/chat/completions")
-        .header("Authorization", format!("Bearer {}", state.config.openrouter_api_key))
-        .header("Content-Type", "application/json")
-        .header("HTTP-Referer", "https://Curhatin.app")
-        .header("X-Title", "Curhatin")
-        .json(&openrouter_request)
-        .send()
-        .await;
+    Ok(ChatPlan::Generate(GeneratePlan {
+        request: CompletionRequest {
+            model: state.config.chat_model.clone(),
+            messages,
+            params: category.completion_params(),
+        },
+        sources,
+        snippet,
+        language,
+        pii,
+        session,
+    }))
+}
 
-    match response {
-        Ok(res) => {
-            if res.status().is_success() {
-                match res.json::<OpenRouterResponse>().await {
-                    Ok(openrouter_response) => {
-                        let ai_response = openrouter_response
-                            .choices
-                            .first()
-                            .map(|c| c.message.content.clone())
-                            .unwrap_or_else(|| "I'm here to listen. How are you feeling today?".to_string());
-
-                        (
-                            StatusCode::OK,
-                            Json(ChatResponse {
-                                response: ai_response,
-                                error: None,
-                                sources,
-                            }),
-                        )
-                    }
-                    Err(e) => {
-                        tracing::error!("Failed to parse OpenRouter response: {}", e);
-                        (
-                            StatusCode::INTERNAL_SERVER_ERROR,
-                            Json(ChatResponse {
-                                response: String::new(),
-                                error: Some("Failed to process AI response".to_string()),
-                                sources: None,
-                            }),
-                        )
-                    }
-                }
-            } else {
-                let status = res.status();
-                let error_text = res.text().await.unwrap_or_default();
-                tracing::error!("OpenRouter API error: {} - {}", status, error_text);
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ChatResponse {
-                        response: String::new(),
-                        error: Some("AI service temporarily unavailable".to_string()),
-                        sources: None,
-                    }),
-                )
-            }
-        }
+/// Session a crisis reply is recorded in: only one the caller owns and that is still live.
+///
+/// Lookup failures are logged and the reply is simply not recorded.
+async fn crisis_session(state: &AppState, payload: &ChatRequest, user: Option<AnonymousUser>) -> Option<SessionTurn> {
+    let session_id = payload.session_id.as_ref()?;
+    match state.db.find_session(session_id, user.as_ref().map(|u| u.0.as_str())).await {
+        Ok(Some(_)) => Some(SessionTurn {
+            session_id: session_id.clone(),
+            user_message: PiiVault::new().redact(&payload.message),
+        }),
+        Ok(None) => None,
         Err(e) => {
-            tracing::error!("Failed to call OpenRouter API: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ChatResponse {
-                    response: String::new(),
-                    error: Some("Failed to connect to AI service".to_string()),
-                    sources: None,
-                }),
-            )
+            tracing::error!("Failed to load session for crisis reply: {}", e);
+            None
         }
     }
 }
 
-/// Ingest a document
+/// Append a finished exchange to its session, if the request used one.
+///
+/// `reply` must be the redacted text so no personal data is stored.
+async fn record_turn(state: &AppState, turn: Option<SessionTurn>, reply: &str) {
+    let Some(turn) = turn else {
+        return;
+    };
+    let now = Utc::now();
+    let messages = [
+        ConversationMessage {
+            role: "user".to_string(),
+            content: turn.user_message,
+            timestamp: now,
+        },
+        ConversationMessage {
+            role: "assistant".to_string(),
+            content: reply.to_string(),
+            timestamp: now,
+        },
+    ];
+    if let Err(e) = state
+        .db
+        .append_session_messages(&turn.session_id, &messages, now + state.config.session_ttl)
+        .await
+    {
+        tracing::error!("Failed to record session turn: {}", e);
+    }
+}
+
+/// Chat with AI
+///
+/// If the model cannot answer, the reply is a fixed supportive message with `degraded: true`
+/// (unless degraded mode is turned off, then the request fails with 500).
 #[utoipa::path(
     post,
-    path = "/api/ingest",
-    request_body = IngestRequest,
+    path = "/api/chat",
+    security(("api_key" = [])),
+    request_body = ChatRequest,
+    params(("X-Anonymous-Id" = Option<String>, Header, description = "Anonymous user identifier (UUID); required for sessions created with one")),
     responses(
-        (status = 201, description = "Document ingested", body = IngestResponse),
-        (status = 400, description = "Bad request", body = IngestResponse)
+        (status = 200, description = "Chat response, possibly degraded", body = ChatResponse),
+        (status = 400, description = "Bad request", body = ChatResponse),
+        (status = 401, description = "Missing or invalid API key", body = ErrorResponse),
+        (status = 404, description = "Session not found or expired", body = ChatResponse),
+        (status = 429, description = "Rate limit hit or all upstream slots busy; see `Retry-After`", body = ErrorResponse),
+        (status = 500, description = "Internal server error")
     )
 )]
-async fn ingest_document(
+async fn chat(
     State(state): State<Arc<AppState>>,
-    Json(payload): Json<IngestRequest>,
-) -> impl IntoResponse {
-    // Validate input
-    if payload.content.trim().is_empty() {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(IngestResponse {
-                success: false,
-                id: String::new(),
-                error: Some("Content cannot be empty".to_string()),
-            }),
-        );
-    }
-
-    // Generate embedding for the content
-    let embedding = match state.embedding_service.generate_embedding(&payload.content).await {
-        Ok(emb) => emb,
-        Err(e) => {
-            tracing::error!("Failed to generate embedding: {}", e);
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(IngestResponse {
-                    success: false,
-                    id: String::new(),
-                    error: Some(format!("Failed to generate embedding: {}", e)),
-                }),
-            );
+    _auth: Authorized<auth::Chat>,
+    user: Option<AnonymousUser>,
+    Json(payload): Json<ChatRequest>,
+) -> Response {
+    let plan = match prepare_chat(&state, payload, user).await {
+        Ok(ChatPlan::Generate(plan)) => plan,
+        Ok(ChatPlan::Crisis { detected, session }) => {
+            let response = ChatResponse::crisis(&detected);
+            record_turn(&state, session, &response.response).await;
+            return (StatusCode::OK, Json(response)).into_response();
+        }
+        Err((status, error)) => {
+            return (status, Json(ChatResponse::error(error))).into_response();
         }
     };
 
-    // Create document
-    let doc_id = Uuid::new_v4().to_string();
-    let document = KnowledgeDocument {
-        id: doc_id.clone(),
-        title: payload.title,
-        content: payload.content,
-        category: payload.category,
-        embedding,
-        created_at: Utc::now(),
+    // Held until the guardrails are done, since they may call the provider again
+    let _permit = match state.upstream.acquire().await {
+        Ok(permit) => permit,
+        Err(retry_after) => {
+            tracing::warn!("No free upstream slot for chat request");
+            return ratelimit::too_many_requests(retry_after, "The assistant is busy right now, please try again shortly");
+        }
     };
 
-    // Insert into MongoDB
-    let collection = state.db.knowledge_collection();
-    match collection.insert_one(document).await {
-        Ok(_) => {
-            tracing::info!("Ingested document: {}", doc_id);
+    // Call the configured chat provider
+    match state.provider.complete(&plan.request).await {
+        Ok(completion) => {
+            tracing::debug!("Completion served by model {}", completion.model);
+            let ai_response = completion
+                .content
+                .unwrap_or_else(|| "I'm here to listen. How are you feeling today?".to_string());
+
+            // Check the reply against the ethics boundaries before it reaches the user
+            let guarded = guardrails::enforce(
+                state.provider.as_ref(),
+                &plan.request,
+                ai_response,
+                completion.model,
+                plan.language,
+            )
+            .await;
+            record_turn(&state, plan.session, &guarded.text).await;
+            let metadata = ResponseMetadata {
+                // The model behind the text the user sees, not the first draft
+                model: guarded.model,
+                guardrails: guarded.reports,
+            };
+            let response = if state.config.restore_pii {
+                plan.pii.restore(&guarded.text)
+            } else {
+                guarded.text
+            };
+
             (
-                StatusCode::CREATED,
-                Json(IngestResponse {
-                    success: true,
-                    id: doc_id,
+                StatusCode::OK,
+                Json(ChatResponse {
+                    response,
                     error: None,
+                    sources: plan.sources,
+                    crisis: false,
+                    degraded: false,
+                    hotlines: None,
+                    metadata: metadata.into_option(),
                 }),
             )
+                .into_response()
+        }
+        Err(e) if state.degraded.enabled() => {
+            let reply = state.degraded.reply(plan.language, plan.snippet.as_ref(), &e);
+            record_turn(&state, plan.session, &reply.text).await;
+            (StatusCode::OK, Json(ChatResponse::degraded(reply))).into_response()
         }
         Err(e) => {
-            tracing::error!("Failed to insert document: {}", e);
+            tracing::error!("Chat provider '{}' failed: {}", state.provider.name(), e);
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                Json(IngestResponse {
-                    success: false,
-                    id: String::new(),
-                    error: Some(format!("Failed to store document: {}", e)),
-                }),
+                Json(ChatResponse::error(e.user_message())),
             )
+                .into_response()
         }
     }
 }
 
-#[tokio::main]
-async fn main() {
-    // Load environment variables
-    dotenvy::dotenv().ok();
-
-    // Initialize tracing
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
+/// Chat with AI, streaming the reply as server-sent events
+///
+/// Emits `delta` events carrying `{"content": "..."}` while the model generates,
+/// followed by a single `done` event with the retrieved `sources` and any `error`.
+/// Deltas are released a sentence at a time once guardrails have checked them; a
+/// violation that cannot be rewritten ends the reply with a safe fallback message.
+/// If the model fails before sending anything, the deltas carry the degraded reply and
+/// `done.degraded` is true.
+#[utoipa::path(
+    post,
+    path = "/api/chat/stream",
+    security(("api_key" = [])),
+    request_body = ChatRequest,
+    params(("X-Anonymous-Id" = Option<String>, Header, description = "Anonymous user identifier (UUID); required for sessions created with one")),
+    responses(
+        (status = 200, description = "Stream of `delta` events followed by a `done` event", content_type = "text/event-stream", body = ChatStreamDone),
+        (status = 400, description = "Bad request", body = ChatResponse),
+        (status = 404, description = "Session not found or expired", body = ChatResponse),
+        (status = 429, description = "Rate limit hit or all upstream slots busy; see `Retry-After`", body = ErrorResponse)
+    )
+)]
+async fn chat_stream(
+    State(state): State<Arc<AppState>>,
+    _auth: Authorized<auth::Chat>,
+    user: Option<AnonymousUser>,
+    Json(payload): Json<ChatRequest>,
+) -> Response {
+    let plan = match prepare_chat(&state, payload, user).await {
+        Ok(ChatPlan::Generate(plan)) => plan,
+        Ok(ChatPlan::Crisis { detected, session }) => {
+            let text = crisis::response_text(detected.language);
+            record_turn(&state, session, &text).await;
+            let events = vec![
+                Event::default()
+                    .event("delta")
+                    .json_data(ChatStreamDelta { content: text })
+                    .expect("delta event serializes"),
+                Event::default()
+                    .event("done")
+                    .json_data(ChatStreamDone {
+                        crisis: true,
+                        hotlines: Some(crisis::hotlines()),
+                        ..Default::default()
+                    })
+                    .expect("done event serializes"),
+            ];
+            return Sse::new(stream::iter(events.into_iter().map(Ok::<_, Infallible>))).into_response();
+        }
+        Err((status, error)) => {
+            return (status, Json(ChatResponse::error(error))).into_response();
+        }
+    };
+
+    // Acquired before the stream starts so a busy server can still answer with a plain 429
+    let permit = match state.upstream.acquire().await {
+        Ok(permit) => permit,
+        Err(retry_after) => {
+            tracing::warn!("No free upstream slot for chat stream");
+            return ratelimit::too_many_requests(retry_after, "The assistant is busy right now, please try again shortly");
+        }
+    };
+
+    let (tx, rx) = mpsc::channel::<Event>(32);
+    tokio::spawn(async move {
+        let _permit = permit;
+        let mut failure = None;
+        // Reply as produced by the model, placeholders included
+        let mut raw = String::new();
+        let empty_vault = PiiVault::new();
+        let mut restorer = if state.config.restore_pii { plan.pii.restorer() } else { empty_vault.restorer() };
+        let mut guard = StreamGuard::new(plan.language);
+        let mut model = None;
+        match state.provider.stream(&plan.request).await {
+            Ok(mut stream) => {
+                model = Some(stream.model);
+                while let Some(delta) = stream.deltas.next().await {
+                    match delta {
+                        Ok(content) => {
+                            raw.push_str(&content);
+                            let content = restorer.push(&guard.push(&content));
+                            if !content.is_empty() {
+                                let event = Event::default()
+                                    .event("delta")
+                                    .json_data(ChatStreamDelta { content })
+                                    .expect("delta event serializes");
+                                if tx.send(event).await.is_err() {
+                                    // Client went away, stop pulling from upstream
+                                    return;
+                                }
+                            }
+                            if guard.stopped() {
+                                // The reply ended with the guardrail fallback, drop the rest
+                                break;
+                            }
+                        }
+                        Err(e) => {
+                            tracing::error!("Chat provider '{}' stream failed: {}", state.provider.name(), e);
+                            failure = Some(e);
+                            break;
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                tracing::error!("Chat provider '{}' failed: {}", state.provider.name(), e);
+                failure = Some(e);
+            }
+        }
+
+        // Nothing reached the user yet, so the degraded reply can stand in for the model
+        if let Some(e) = failure.as_ref().filter(|_| raw.is_empty() && state.degraded.enabled()) {
+            let reply = state.degraded.reply(plan.language, plan.snippet.as_ref(), e);
+            record_turn(&state, plan.session, &reply.text).await;
+            let events = [
+                Event::default()
+                    .event("delta")
+                    .json_data(ChatStreamDelta { content: reply.text })
+                    .expect("delta event serializes"),
+                Event::default()
+                    .event("done")
+                    .json_data(ChatStreamDone {
+                        sources: reply.source.map(|title| vec![title]),
+                        degraded: true,
+                        hotlines: reply.hotlines,
+                        ..Default::default()
+                    })
+                    .expect("done event serializes"),
+            ];
+            for event in events {
+                if tx.send(event).await.is_err() {
+                    return;
+                }
+            }
+            return;
+        }
+        let error = failure.map(|e| e.user_message().to_string());
+
+        // Release what the guard still holds back, then whatever the restorer kept
+        let mut tail = restorer.push(&guard.finish());
+        tail.push_str(&restorer.finish());
+        if !tail.is_empty() {
+            let event = Event::default()
+                .event("delta")
+                .json_data(ChatStreamDelta { content: tail })
+                .expect("delta event serializes");
+            let _ = tx.send(event).await;
+        }
+
+        if error.is_none() {
+            record_turn(&state, plan.session, guard.released()).await;
+        }
+        let metadata = ResponseMetadata {
+            model,
+            guardrails: guard.reports(),
+        };
+
+        let done = Event::default()
+            .event("done")
+            .json_data(ChatStreamDone {
+                sources: plan.sources,
+                error,
+                metadata: metadata.into_option(),
+                ..Default::default()
+            })
+            .expect("done event serializes");
+        let _ = tx.send(done).await;
+    });
+
+    let events = stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|event| (Ok::<_, Infallible>(event), rx))
+    });
+
+    Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+/// Start a server-side chat session
+///
+/// Send `X-Anonymous-Id` to link the session to the user for export and deletion.
+#[utoipa::path(
+    post,
+    path = "/api/sessions",
+    security(("api_key" = [])),
+    request_body = CreateSessionRequest,
+    params(("X-Anonymous-Id" = Option<String>, Header, description = "Anonymous user identifier (UUID)")),
+    responses(
+        (status = 201, description = "Session created", body = SessionResponse),
+        (status = 400, description = "Unknown category", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    )
+)]
+async fn create_session(
+    State(state): State<Arc<AppState>>,
+    _auth: Authorized<auth::Chat>,
+    user: Option<AnonymousUser>,
+    Json(payload): Json<CreateSessionRequest>,
+) -> Response {
+    // Store the canonical id so later lookups do not depend on the alias used
+    let category = match payload.category.as_deref().map(|c| categories::resolve(Some(c))).transpose() {
+        Ok(category) => category.map(|c| c.id.to_string()),
+        Err(e) => return ErrorResponse::respond(StatusCode::BAD_REQUEST, e),
+    };
+
+    let now = Utc::now();
+    let session = SessionDocument {
+        id: Uuid::new_v4().to_string(),
+        user_id: user.map(|u| u.0),
+        category,
+        messages: Vec::new(),
+        created_at: now,
+        expires_at: now + state.config.session_ttl,
+    };
+
+    match state.db.sessions_collection().insert_one(&session).await {
+        Ok(_) => (
+            StatusCode::CREATED,
+            Json(SessionResponse {
+                session_id: session.id,
+                expires_at: session.expires_at,
+            }),
+        )
+            .into_response(),
+        Err(e) => {
+            tracing::error!("Failed to create session: {}", e);
+            ErrorResponse::respond(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create session")
+        }
+    }
+}
+
+/// Delete a chat session and its transcript
+#[utoipa::path(
+    delete,
+    path = "/api/sessions/{id}",
+    security(("api_key" = [])),
+    params(
+        ("id" = String, Path, description = "Session id"),
+        ("X-Anonymous-Id" = Option<String>, Header, description = "Anonymous user identifier (UUID) the session was created with")
+    ),
+    responses(
+        (status = 204, description = "Session deleted"),
+        (status = 404, description = "Session not found", body = ErrorResponse)
+    )
+)]
+async fn delete_session(
+    State(state): State<Arc<AppState>>,
+    _auth: Authorized<auth::Chat>,
+    user: Option<AnonymousUser>,
+    Path(id): Path<String>,
+) -> Response {
+    let filter = db::session_filter(&id, user.as_ref().map(|u| u.0.as_str()));
+    match state.db.sessions_collection().delete_one(filter).await {
+        Ok(result) if result.deleted_count > 0 => StatusCode::NO_CONTENT.into_response(),
+        Ok(_) => ErrorResponse::respond(StatusCode::NOT_FOUND, "Session not found"),
+        Err(e) => {
+            tracing::error!("Failed to delete session: {}", e);
+            ErrorResponse::respond(StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete session")
+        }
+    }
+}
+
+/// Export everything stored about the calling user
+#[utoipa::path(
+    get,
+    path = "/api/me/export",
+    security(("api_key" = [])),
+    params(("X-Anonymous-Id" = String, Header, description = "Anonymous user identifier (UUID)")),
+    responses(
+        (status = 200, description = "All stored data, grouped by collection", body = UserExport),
+        (status = 400, description = "Missing or invalid identifier", body = ErrorResponse)
+    )
+)]
+async fn export_me(
+    State(state): State<Arc<AppState>>,
+    _auth: Authorized<auth::Chat>,
+    user: AnonymousUser,
+) -> Response {
+    match state.db.export_user_data(&user.0).await {
+        Ok(collections) => {
+            let collections = collections
+                .into_iter()
+                .map(|(name, documents)| {
+                    let documents = documents
+                        .into_iter()
+                        .map(|d| mongodb::bson::Bson::Document(d).into_relaxed_extjson())
+                        .collect();
+                    (name, documents)
+                })
+                .collect();
+            Json(UserExport {
+                user_id: user.0,
+                exported_at: Utc::now(),
+                collections,
+                not_stored: not_stored_user_data(),
+            })
+            .into_response()
+        }
+        Err(e) => {
+            tracing::error!("Failed to export user data: {}", e);
+            ErrorResponse::respond(StatusCode::INTERNAL_SERVER_ERROR, "Failed to export user data")
+        }
+    }
+}
+
+/// Delete everything stored about the calling user ("right to be forgotten")
+#[utoipa::path(
+    delete,
+    path = "/api/me",
+    security(("api_key" = [])),
+    params(("X-Anonymous-Id" = String, Header, description = "Anonymous user identifier (UUID)")),
+    responses(
+        (status = 200, description = "Data deleted", body = DeletionReceipt),
+        (status = 400, description = "Missing or invalid identifier", body = ErrorResponse)
+    )
+)]
+async fn delete_me(
+    State(state): State<Arc<AppState>>,
+    _auth: Authorized<auth::Chat>,
+    user: AnonymousUser,
+) -> Response {
+    match state.db.delete_user_data(&user.0).await {
+        Ok(deleted) => {
+            let receipt = DeletionReceipt::new(deleted);
+            // The receipt is logged without the identifier, which no longer maps to any data
+            tracing::info!("User data deleted, receipt {}: {:?}", receipt.receipt_id, receipt.deleted);
+            Json(receipt).into_response()
+        }
+        Err(e) => {
+            tracing::error!("Failed to delete user data: {}", e);
+            ErrorResponse::respond(StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete user data")
+        }
+    }
+}
+
+/// Create an API key; the key itself is only ever returned in this response
+#[utoipa::path(
+    post,
+    path = "/api/admin/keys",
+    security(("api_key" = [])),
+    request_body = CreateApiKeyRequest,
+    responses(
+        (status = 201, description = "Key created", body = CreatedApiKey),
+        (status = 400, description = "Bad request", body = ErrorResponse),
+        (status = 403, description = "API key lacks the `admin` scope", body = ErrorResponse)
+    )
+)]
+async fn create_api_key(
+    State(state): State<Arc<AppState>>,
+    _auth: Authorized<auth::Admin>,
+    Json(payload): Json<CreateApiKeyRequest>,
+) -> Response {
+    if payload.name.trim().is_empty() || payload.scopes.is_empty() {
+        return ErrorResponse::respond(StatusCode::BAD_REQUEST, "A name and at least one scope are required");
+    }
+
+    let (key, document) = auth::generate_key(payload.name, payload.scopes);
+    match state.db.api_keys_collection().insert_one(&document).await {
+        Ok(_) => {
+            tracing::info!("Created API key {} ({})", document.id, document.prefix);
+            (
+                StatusCode::CREATED,
+                Json(CreatedApiKey {
+                    key,
+                    info: ApiKeyInfo::from(document),
+                }),
+            )
+                .into_response()
+        }
+        Err(e) => {
+            tracing::error!("Failed to store API key: {}", e);
+            ErrorResponse::respond(StatusCode::INTERNAL_SERVER_ERROR, "Failed to store API key")
+        }
+    }
+}
+
+/// List API keys (hashes are never returned)
+#[utoipa::path(
+    get,
+    path = "/api/admin/keys",
+    security(("api_key" = [])),
+    responses(
+        (status = 200, description = "All keys", body = Vec<ApiKeyInfo>),
+        (status = 403, description = "API key lacks the `admin` scope", body = ErrorResponse)
+    )
+)]
+async fn list_api_keys(State(state): State<Arc<AppState>>, _auth: Authorized<auth::Admin>) -> Response {
+    let keys: Result<Vec<ApiKeyDocument>, _> = match state.db.api_keys_collection().find(doc! {}).await {
+        Ok(cursor) => cursor.try_collect().await,
+        Err(e) => Err(e),
+    };
+    match keys {
+        Ok(keys) => Json(keys.into_iter().map(ApiKeyInfo::from).collect::<Vec<_>>()).into_response(),
+        Err(e) => {
+            tracing::error!("Failed to list API keys: {}", e);
+            ErrorResponse::respond(StatusCode::INTERNAL_SERVER_ERROR, "Failed to list API keys")
+        }
+    }
+}
+
+/// Revoke an API key
+#[utoipa::path(
+    delete,
+    path = "/api/admin/keys/{id}",
+    security(("api_key" = [])),
+    params(("id" = String, Path, description = "Key id")),
+    responses(
+        (status = 204, description = "Key revoked"),
+        (status = 403, description = "API key lacks the `admin` scope", body = ErrorResponse),
+        (status = 404, description = "Key not found", body = ErrorResponse)
+    )
+)]
+async fn revoke_api_key(
+    State(state): State<Arc<AppState>>,
+    _auth: Authorized<auth::Admin>,
+    Path(id): Path<String>,
+) -> Response {
+    let revoked = state
+        .db
+        .api_keys_collection()
+        .update_one(
+            doc! { "_id": &id, "revoked_at": null },
+            doc! { "$set": { "revoked_at": mongodb::bson::DateTime::now() } },
+        )
+        .await;
+    match revoked {
+        Ok(result) if result.matched_count > 0 => {
+            tracing::info!("Revoked API key {}", id);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(_) => ErrorResponse::respond(StatusCode::NOT_FOUND, "API key not found"),
+        Err(e) => {
+            tracing::error!("Failed to revoke API key: {}", e);
+            ErrorResponse::respond(StatusCode::INTERNAL_SERVER_ERROR, "Failed to revoke API key")
+        }
+    }
+}
+
+/// Embedding model in use, chunks per model and re-embedding progress
+#[utoipa::path(
+    get,
+    path = "/api/admin/embeddings",
+    security(("api_key" = [])),
+    responses(
+        (status = 200, description = "Embedding status", body = EmbeddingStatus),
+        (status = 403, description = "API key lacks the `admin` scope", body = ErrorResponse)
+    )
+)]
+async fn embedding_status(State(state): State<Arc<AppState>>, _auth: Authorized<auth::Admin>) -> Response {
+    match state.db.embedding_model_counts().await {
+        Ok(chunks_per_model) => Json(EmbeddingStatus {
+            active_model: state.rag.embedding_model(),
+            chunks_per_model,
+            job: state.rag.reembed_progress(),
+        })
+        .into_response(),
+        Err(e) => {
+            tracing::error!("Failed to count chunks per embedding model: {}", e);
+            ErrorResponse::respond(StatusCode::INTERNAL_SERVER_ERROR, "Failed to load embedding status")
+        }
+    }
+}
+
+/// Re-embed the knowledge base with another model
+///
+/// Runs in the background; follow it with `GET /api/admin/embeddings`. Retrieval keeps
+/// using the current vectors until every chunk has been re-embedded, then switches to
+/// the new model. A failed job can be started again and resumes where it stopped.
+#[utoipa::path(
+    post,
+    path = "/api/admin/embeddings/reembed",
+    security(("api_key" = [])),
+    request_body = ReembedRequest,
+    responses(
+        (status = 202, description = "Job started", body = ReembedProgress),
+        (status = 400, description = "Bad request", body = ErrorResponse),
+        (status = 403, description = "API key lacks the `admin` scope", body = ErrorResponse),
+        (status = 409, description = "A job is already running", body = ErrorResponse)
+    )
+)]
+async fn start_reembed(
+    State(state): State<Arc<AppState>>,
+    _auth: Authorized<auth::Admin>,
+    Json(payload): Json<ReembedRequest>,
+) -> Response {
+    let model = payload.model.trim().to_string();
+    if model.is_empty() {
+        return ErrorResponse::respond(StatusCode::BAD_REQUEST, "A model is required");
+    }
+
+    match state.rag.begin_reembed(&model) {
+        Ok(progress) => {
+            tracing::info!("Re-embedding knowledge base with '{}'", model);
+            let job_state = state.clone();
+            tokio::spawn(async move { job_state.rag.run_reembed(&model).await });
+            (StatusCode::ACCEPTED, Json(progress)).into_response()
+        }
+        Err(running) => ErrorResponse::respond(
+            StatusCode::CONFLICT,
+            format!(
+                "A re-embedding job to '{}' is already running",
+                running.target_model.unwrap_or_default()
+            ),
+        ),
+    }
+}
+
+/// Ingest many documents in one call
+///
+/// Send a JSON array of documents, or JSON Lines (one document per line, e.g. with
+/// `Content-Type: application/x-ndjson`). Chunks are embedded in batches. Every
+/// document gets its own result, so one bad entry does not fail the others.
+#[utoipa::path(
+    post,
+    path = "/api/ingest/bulk",
+    security(("api_key" = [])),
+    request_body(content(
+        (Vec<IngestRequest> = "application/json"),
+        (String = "application/x-ndjson")
+    )),
+    responses(
+        (status = 200, description = "Per-document results", body = BulkIngestResponse),
+        (status = 400, description = "Body is not a JSON array or JSON Lines, or has too many documents", body = ErrorResponse),
+        (status = 403, description = "API key lacks the `knowledge:write` scope", body = ErrorResponse),
+        (status = 429, description = "Rate limit hit; see `Retry-After`", body = ErrorResponse)
+    )
+)]
+async fn ingest_bulk(
+    State(state): State<Arc<AppState>>,
+    _auth: Authorized<auth::KnowledgeWrite>,
+    body: String,
+) -> Response {
+    // Parse every entry on its own so a malformed one only fails itself
+    let entries: Vec<Result<IngestRequest, String>> = if body.trim_start().starts_with('[') {
+        match serde_json::from_str::<Vec<serde_json::Value>>(&body) {
+            Ok(values) => values
+                .into_iter()
+                .map(|value| serde_json::from_value(value).map_err(|e| format!("Invalid document: {}", e)))
+                .collect(),
+            Err(e) => {
+                return ErrorResponse::respond(StatusCode::BAD_REQUEST, format!("Invalid JSON array: {}", e));
+            }
+        }
+    } else {
+        body.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str(line).map_err(|e| format!("Invalid document: {}", e)))
+            .collect()
+    };
+
+    if entries.is_empty() {
+        return ErrorResponse::respond(StatusCode::BAD_REQUEST, "No documents in request body");
+    }
+    if entries.len() > BULK_INGEST_MAX_DOCUMENTS {
+        return ErrorResponse::respond(
+            StatusCode::BAD_REQUEST,
+            format!("At most {} documents per request", BULK_INGEST_MAX_DOCUMENTS),
+        );
+    }
+
+    let mut results: Vec<BulkIngestItem> = Vec::with_capacity(entries.len());
+    let mut accepted = Vec::new();
+    for (index, entry) in entries.into_iter().enumerate() {
+        let entry = entry.and_then(|request| {
+            if request.content.trim().is_empty() {
+                Err("Content cannot be empty".to_string())
+            } else {
+                Ok(request)
+            }
+        });
+        match entry {
+            Ok(request) => {
+                results.push(BulkIngestItem {
+                    index,
+                    title: Some(request.title.clone()),
+                    ..Default::default()
+                });
+                accepted.push((index, NewDocument::from(request)));
+            }
+            Err(error) => results.push(BulkIngestItem {
+                index,
+                error: Some(error),
+                ..Default::default()
+            }),
+        }
+    }
+
+    let (indices, documents): (Vec<usize>, Vec<NewDocument>) = accepted.into_iter().unzip();
+    for (index, outcome) in indices.into_iter().zip(state.rag.ingest_many(documents).await) {
+        let item = &mut results[index];
+        match outcome {
+            Ok(outcome) => {
+                item.success = true;
+                item.chunks = Some(outcome.document.chunk_count);
+                item.id = Some(outcome.document.id);
+                item.status = Some(outcome.status);
+                item.warning = near_duplicate_warning(&outcome.near_duplicates);
+                item.near_duplicates = outcome.near_duplicates;
+            }
+            Err(e) => item.error = Some(e),
+        }
+    }
+
+    let succeeded = results.iter().filter(|r| r.success).count();
+    tracing::info!("Bulk ingest: {} of {} documents stored", succeeded, results.len());
+    Json(BulkIngestResponse {
+        total: results.len(),
+        succeeded,
+        failed: results.len() - succeeded,
+        results,
+    })
+    .into_response()
+}
+
+/// List knowledge documents, newest first
+#[utoipa::path(
+    get,
+    path = "/api/knowledge",
+    security(("api_key" = [])),
+    params(KnowledgeListQuery),
+    responses(
+        (status = 200, description = "One page of documents", body = KnowledgeListResponse),
+        (status = 403, description = "API key lacks the `knowledge:write` scope", body = ErrorResponse)
+    )
+)]
+async fn list_knowledge(
+    State(state): State<Arc<AppState>>,
+    _auth: Authorized<auth::KnowledgeWrite>,
+    Query(query): Query<KnowledgeListQuery>,
+) -> Response {
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query.per_page.unwrap_or(20).clamp(1, 100);
+    let filter = match &query.category {
+        Some(category) => doc! { "category": category },
+        None => doc! {},
+    };
+
+    let collection = state.db.documents_collection();
+    let total = collection.count_documents(filter.clone()).await;
+    let items: Result<Vec<ParentDocument>, _> = match collection
+        .find(filter)
+        .sort(doc! { "created_at": -1 })
+        .skip((page - 1) * per_page)
+        .limit(per_page as i64)
+        .await
+    {
+        Ok(cursor) => cursor.try_collect().await,
+        Err(e) => Err(e),
+    };
+
+    match (total, items) {
+        (Ok(total), Ok(items)) => Json(KnowledgeListResponse {
+            items: items.into_iter().map(KnowledgeSummary::from).collect(),
+            page,
+            per_page,
+            total,
+        })
+        .into_response(),
+        (Err(e), _) | (_, Err(e)) => {
+            tracing::error!("Failed to list knowledge: {}", e);
+            ErrorResponse::respond(StatusCode::INTERNAL_SERVER_ERROR, "Failed to list knowledge")
+        }
+    }
+}
+
+/// Get a knowledge document
+#[utoipa::path(
+    get,
+    path = "/api/knowledge/{id}",
+    security(("api_key" = [])),
+    params(("id" = String, Path, description = "Document id")),
+    responses(
+        (status = 200, description = "The document", body = KnowledgeItem),
+        (status = 404, description = "Document not found", body = ErrorResponse)
+    )
+)]
+async fn get_knowledge(
+    State(state): State<Arc<AppState>>,
+    _auth: Authorized<auth::KnowledgeWrite>,
+    Path(id): Path<String>,
+) -> Response {
+    match state.db.documents_collection().find_one(doc! { "_id": &id }).await {
+        Ok(Some(document)) => Json(KnowledgeItem::from(document)).into_response(),
+        Ok(None) => ErrorResponse::respond(StatusCode::NOT_FOUND, "Document not found"),
+        Err(e) => {
+            tracing::error!("Failed to load knowledge document: {}", e);
+            ErrorResponse::respond(StatusCode::INTERNAL_SERVER_ERROR, "Failed to load document")
+        }
+    }
+}
+
+/// Update a knowledge document
+///
+/// Changing `content` re-chunks and re-embeds the document.
+#[utoipa::path(
+    put,
+    path = "/api/knowledge/{id}",
+    security(("api_key" = [])),
+    params(("id" = String, Path, description = "Document id")),
+    request_body = UpdateKnowledgeRequest,
+    responses(
+        (status = 200, description = "Updated document", body = KnowledgeItem),
+        (status = 400, description = "Bad request", body = ErrorResponse),
+        (status = 404, description = "Document not found", body = ErrorResponse),
+        (status = 409, description = "Another document has the same content", body = ErrorResponse)
+    )
+)]
+async fn update_knowledge(
+    State(state): State<Arc<AppState>>,
+    _auth: Authorized<auth::KnowledgeWrite>,
+    Path(id): Path<String>,
+    Json(payload): Json<UpdateKnowledgeRequest>,
+) -> Response {
+    if payload.title.is_none() && payload.category.is_none() && payload.content.is_none() {
+        return ErrorResponse::respond(StatusCode::BAD_REQUEST, "Nothing to update");
+    }
+    if payload.content.as_ref().is_some_and(|c| c.trim().is_empty()) {
+        return ErrorResponse::respond(StatusCode::BAD_REQUEST, "Content cannot be empty");
+    }
+    if let Some(content) = &payload.content {
+        match state.rag.find_duplicate(content).await {
+            Ok(Some(other)) if other.id != id => {
+                return ErrorResponse::respond(
+                    StatusCode::CONFLICT,
+                    format!("Document {} already has this content", other.id),
+                );
+            }
+            Ok(_) => {}
+            Err(e) => {
+                tracing::error!("Failed to check for duplicate content: {}", e);
+                return ErrorResponse::respond(StatusCode::INTERNAL_SERVER_ERROR, "Failed to update document");
+            }
+        }
+    }
+
+    match state
+        .rag
+        .update(&id, payload.title, payload.category, payload.content)
+        .await
+    {
+        Ok(Some(document)) => {
+            tracing::info!("Updated knowledge document {}", id);
+            Json(KnowledgeItem::from(document)).into_response()
+        }
+        Ok(None) => ErrorResponse::respond(StatusCode::NOT_FOUND, "Document not found"),
+        Err(e) => {
+            tracing::error!("Failed to update knowledge document {}: {}", id, e);
+            ErrorResponse::respond(StatusCode::INTERNAL_SERVER_ERROR, e)
+        }
+    }
+}
+
+/// Delete a knowledge document and its chunks
+#[utoipa::path(
+    delete,
+    path = "/api/knowledge/{id}",
+    security(("api_key" = [])),
+    params(("id" = String, Path, description = "Document id")),
+    responses(
+        (status = 204, description = "Document deleted"),
+        (status = 404, description = "Document not found", body = ErrorResponse)
+    )
+)]
+async fn delete_knowledge(
+    State(state): State<Arc<AppState>>,
+    _auth: Authorized<auth::KnowledgeWrite>,
+    Path(id): Path<String>,
+) -> Response {
+    match state.rag.delete(&id).await {
+        Ok(true) => {
+            tracing::info!("Deleted knowledge document {}", id);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(false) => ErrorResponse::respond(StatusCode::NOT_FOUND, "Document not found"),
+        Err(e) => {
+            tracing::error!("Failed to delete knowledge document {}: {}", id, e);
+            ErrorResponse::respond(StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete document")
+        }
+    }
+}
+
+/// Ingest a document
+#[utoipa::path(
+    post,
+    path = "/api/ingest",
+    security(("api_key" = [])),
+    request_body = IngestRequest,
+    responses(
+        (status = 201, description = "Document ingested", body = IngestResponse),
+        (status = 200, description = "Content already stored; `id` is the existing document", body = IngestResponse),
+        (status = 400, description = "Bad request", body = IngestResponse),
+        (status = 401, description = "Missing or invalid API key", body = ErrorResponse),
+        (status = 403, description = "API key lacks the `knowledge:write` scope", body = ErrorResponse),
+        (status = 429, description = "Rate limit hit; see `Retry-After`", body = ErrorResponse)
+    )
+)]
+async fn ingest_document(
+    State(state): State<Arc<AppState>>,
+    _auth: Authorized<auth::KnowledgeWrite>,
+    Json(payload): Json<IngestRequest>,
+) -> impl IntoResponse {
+    // Validate input
+    if payload.content.trim().is_empty() {
+        return (StatusCode::BAD_REQUEST, Json(IngestResponse::failed("Content cannot be empty")));
+    }
+
+    // Chunk, embed and store the document
+    match state.rag.ingest(NewDocument::from(payload)).await {
+        Ok(outcome) => {
+            let status = match outcome.status {
+                IngestStatus::Created => {
+                    tracing::info!(
+                        "Ingested document {} as {} chunks",
+                        outcome.document.id,
+                        outcome.document.chunk_count
+                    );
+                    StatusCode::CREATED
+                }
+                IngestStatus::Duplicate | IngestStatus::Updated => {
+                    tracing::info!("Document {} already has this content", outcome.document.id);
+                    StatusCode::OK
+                }
+            };
+            (status, Json(IngestResponse::from(outcome)))
+        }
+        Err(e) => {
+            tracing::error!("Failed to ingest document: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(IngestResponse::failed(e)))
+        }
+    }
+}
+
+/// Every route of the API, with rate limits and CORS applied
+fn router(state: Arc<AppState>) -> Router {
+    // Configure CORS
+    let cors = CorsLayer::new()
+        .allow_origin(Any)
+        .allow_methods(Any)
+        .allow_headers(Any);
+
+    // Build router
+    Router::new()
+        .merge(SwaggerUi::new("/swagger-ui").url("/api-docs/openapi.json", ApiDoc::openapi()))
+        .route("/health", get(health_check))
+        .route("/api/categories", get(list_categories))
+        .route(
+            "/api/chat",
+            post(chat).route_layer(middleware::from_fn_with_state(state.clone(), ratelimit::limit_chat)),
+        )
+        .route(
+            "/api/chat/stream",
+            post(chat_stream).route_layer(middleware::from_fn_with_state(state.clone(), ratelimit::limit_chat)),
+        )
+        .route("/api/sessions", post(create_session))
+        .route("/api/sessions/{id}", delete(delete_session))
+        .route("/api/me", delete(delete_me))
+        .route("/api/me/export", get(export_me))
+        .route("/api/admin/keys", post(create_api_key).get(list_api_keys))
+        .route("/api/admin/keys/{id}", delete(revoke_api_key))
+        .route("/api/admin/embeddings", get(embedding_status))
+        .route("/api/admin/embeddings/reembed", post(start_reembed))
+        .route(
+            "/api/ingest",
+            post(ingest_document).route_layer(middleware::from_fn_with_state(state.clone(), ratelimit::limit_ingest)),
+        )
+        .route(
+            "/api/ingest/bulk",
+            post(ingest_bulk)
+                .route_layer(middleware::from_fn_with_state(state.clone(), ratelimit::limit_ingest))
+                .layer(DefaultBodyLimit::max(BULK_INGEST_MAX_BYTES)),
+        )
+        .route(
+            "/api/knowledge",
+            get(list_knowledge).route_layer(middleware::from_fn_with_state(state.clone(), ratelimit::limit_ingest)),
+        )
+        .route(
+            "/api/knowledge/{id}",
+            get(get_knowledge)
+                .put(update_knowledge)
+                .delete(delete_knowledge)
+                .route_layer(middleware::from_fn_with_state(state.clone(), ratelimit::limit_ingest)),
+        )
+        .layer(cors)
+        .with_state(state)
+}
+
+#[tokio::main]
+async fn main() {
+    // Load environment variables
+    dotenvy::dotenv().ok();
+
+    // Initialize tracing
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::EnvFilter::try_from_default_env()
                 .unwrap_or_else(|_| "ai_mental_chatbot_backend=debug,tower_http=debug".into()),
         )
         .with(tracing_subscriber::fmt::layer())
         .init();
 
     // Load configuration
-    let openrouter_api_key = std::env::var("OPENROUTER_API_KEY")
-        .expect("OPENROUTER_API_KEY must be set");
+    // OPENROUTER_API_KEY is only mandatory when OpenRouter serves chat completions;
+    // embeddings still go through OpenRouter and degrade gracefully without it.
+    let openrouter_api_key = std::env::var("OPENROUTER_API_KEY").unwrap_or_default();
+    let provider_config = ProviderConfig::from_env(&openrouter_api_key)
+        .unwrap_or_else(|e| panic!("Invalid LLM provider configuration: {}", e));
     
     let config = AppConfig {
-        openrouter_api_key: openrouter_api_key.clone(),
-        openrouter_model: std::env::var("OPENROUTER_MODEL")
+        chat_model: std::env::var("LLM_MODEL")
+            .or_else(|_| std::env::var("OPENROUTER_MODEL"))
             .unwrap_or_else(|_| "openai/gpt-4o-mini".to_string()),
+        restore_pii: std::env::var("PII_RESTORE_REPLIES")
+            .map(|v| v != "false" && v != "0")
+            .unwrap_or(true),
+        session_ttl: chrono::Duration::minutes(
+            std::env::var("SESSION_TTL_MINUTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(60),
+        ),
+        auth_enabled: std::env::var("AUTH_ENABLED")
+            .map(|v| v != "false" && v != "0")
+            .unwrap_or(true),
     };
 
     // Connect to MongoDB
@@ -546,31 +1896,134 @@ async fn main() {
         }
     }
     let db = db.expect("Failed to connect to MongoDB");
+    if let Err(e) = db.ensure_indexes().await {
+        tracing::warn!("Failed to create MongoDB indexes: {}", e);
+    }
+
+    // Make sure an operator key exists so further keys can be created over the API
+    if let Ok(admin_key) = std::env::var("ADMIN_API_KEY") {
+        let document = auth::bootstrap_admin_key(&admin_key);
+        let keys = db.api_keys_collection();
+        // An existing record for the same key is left alone, so a revoked key stays revoked
+        // across restarts; a changed ADMIN_API_KEY replaces the record
+        let result = match keys.find_one(doc! { "_id": &document.id }).await {
+            Ok(Some(existing)) if existing.key_hash == document.key_hash => {
+                if existing.revoked_at.is_some() {
+                    tracing::warn!("ADMIN_API_KEY has been revoked; set a new value to bootstrap another admin key");
+                }
+                Ok(())
+            }
+            Ok(_) => keys
+                .replace_one(doc! { "_id": &document.id }, &document)
+                .upsert(true)
+                .await
+                .map(|_| ()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            tracing::error!("Failed to register ADMIN_API_KEY: {}", e);
+        }
+    } else if config.auth_enabled {
+        tracing::warn!("AUTH_ENABLED is on but ADMIN_API_KEY is not set; only existing keys will work");
+    }
+
+    // Create RAG service and prepare the retrieval backend
+    let retrieval_backend = RetrievalBackend::from_env()
+        .unwrap_or_else(|e| panic!("Invalid retrieval configuration: {}", e));
+    let category_mode = CategoryMode::from_env()
+        .unwrap_or_else(|e| panic!("Invalid retrieval configuration: {}", e));
+    let retrieval_config = RetrievalConfig::from_env()
+        .unwrap_or_else(|e| panic!("Invalid retrieval configuration: {}", e));
+    match db.backfill_parent_documents().await {
+        Ok(0) => {}
+        Ok(count) => tracing::info!("Linked {} legacy knowledge documents to parent articles", count),
+        Err(e) => tracing::warn!("Failed to backfill parent documents: {}", e),
+    }
+    // Before embeddings carried their model, every chunk came from the default one
+    match db.backfill_embedding_model(embeddings::DEFAULT_EMBEDDING_MODEL).await {
+        Ok(0) => {}
+        Ok(count) => tracing::info!("Recorded the embedding model of {} legacy knowledge documents", count),
+        Err(e) => tracing::warn!("Failed to backfill embedding models: {}", e),
+    }
+    match db.backfill_content_hashes().await {
+        Ok(0) => {}
+        Ok(count) => tracing::info!("Hashed the content of {} legacy knowledge documents", count),
+        Err(e) => tracing::warn!("Failed to backfill content hashes: {}", e),
+    }
+    // Timeouts, retries and circuit breakers for outbound calls
+    let embedding_http = ResilientClient::from_env("embeddings", "EMBEDDING", 15);
+    let mut breakers = vec![embedding_http.breaker()];
+
+    // A completed re-embedding job records its model; stored chunks only match that one
+    let active_embedding_model = db
+        .active_embedding_model()
+        .await
+        .unwrap_or_else(|e| panic!("Failed to read the active embedding model: {}", e));
+    let embedding_model = embeddings::resolve_model(
+        embeddings::configured_model().as_deref(),
+        active_embedding_model.as_deref(),
+    )
+    .unwrap_or_else(|e| panic!("Invalid embedding configuration: {}", e));
+
+    let rag = RagService::new(
+        db.clone(),
+        EmbeddingService::new(openrouter_api_key.clone(), embedding_http).with_model(&embedding_model),
+        retrieval_backend,
+        ChunkerConfig::from_env(),
+        category_mode,
+        retrieval_config,
+        std::env::var("NEAR_DUPLICATE_THRESHOLD")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0.95),
+    );
+    if let Err(e) = rag.initialize().await {
+        tracing::error!("Failed to initialize '{}' retrieval backend: {}", rag.backend().name(), e);
+    }
 
-    // Create embedding service
-    let embedding_service = EmbeddingService::new(openrouter_api_key);
+    // Persona prompts are edited without a release and reloaded while running
+    let prompts = Arc::new(PromptStore::from_env().unwrap_or_else(|e| panic!("Invalid prompt templates: {}", e)));
+    prompts::watch(prompts.clone());
+
+    // Create chat provider, with a breaker per model so one failing model does not block its fallbacks
+    let primary = ChainEntry {
+        config: provider_config,
+        model: config.chat_model.clone(),
+    };
+    let chain = llm::chain_from_env(primary, &openrouter_api_key)
+        .unwrap_or_else(|e| panic!("Invalid LLM provider configuration: {}", e));
+    let mut steps: Vec<(Arc<dyn ChatProvider>, String)> = chain
+        .into_iter()
+        .map(|entry| {
+            let http = ResilientClient::from_env(&format!("chat {}", entry.model), "LLM", 60);
+            breakers.push(http.breaker());
+            (entry.config.build(http), entry.model)
+        })
+        .collect();
+    let provider: Arc<dyn ChatProvider> = if steps.len() == 1 {
+        let (provider, model) = steps.remove(0);
+        tracing::info!("Using chat provider '{}' with model {}", provider.name(), model);
+        provider
+    } else {
+        let order: Vec<String> = steps.iter().map(|(p, model)| format!("{} via '{}'", model, p.name())).collect();
+        tracing::info!("Using chat models in fallback order: {}", order.join(", "));
+        Arc::new(FallbackProvider::new(steps))
+    };
 
     // Create shared state
     let state = Arc::new(AppState {
         config,
         db,
-        embedding_service,
+        rag,
+        provider,
+        prompts,
+        rate_limits: RateLimits::from_env(),
+        upstream: UpstreamLimiter::from_env(),
+        breakers,
+        degraded: DegradedMode::from_env(),
     });
 
-    // Configure CORS
-    let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods(Any)
-        .allow_headers(Any);
-
-    // Build router
-    let app = Router::new()
-        .merge(SwaggerUi::new("/swagger-ui").url("/api-docs/openapi.json", ApiDoc::openapi()))
-        .route("/health", get(health_check))
-        .route("/api/chat", post(chat))
-        .route("/api/ingest", post(ingest_document))
-        .layer(cors)
-        .with_state(state);
+    let app = router(state);
 
     // Start server
     let port = std::env::var("PORT").unwrap_or_else(|_| "3000".to_string());
@@ -581,5 +2034,293 @@ async fn main() {
     tracing::info!("🚀 Server running on http://localhost:{}", port);
     tracing::info!("📜 Swagger UI available at http://localhost:{}/swagger-ui", port);
 
-    axum::serve(listener, app).await.unwrap();
+    // Peer addresses feed the rate limiter when no proxy header is present
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+        .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use http::{CircuitBreaker, RetryPolicy};
+    use llm::ScriptedProvider;
+    use ratelimit::TokenBucketLimiter;
+    use serde_json::{json, Value};
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    /// State with a scripted chat model and no reachable MongoDB or embeddings API
+    async fn state_with(script: &[&str]) -> Arc<AppState> {
+        // The driver connects lazily; requests that never touch a session do not need a server
+        let db = AppDatabase::connect("mongodb://127.0.0.1:1/?serverSelectionTimeoutMS=100", "test")
+            .await
+            .expect("client builds");
+        // Every embedding call times out at once, so retrieval falls back to the base prompt
+        let embedding_http = ResilientClient::new(
+            RetryPolicy {
+                timeout: Duration::from_millis(1),
+                max_retries: 0,
+                base_delay: Duration::ZERO,
+                max_delay: Duration::ZERO,
+            },
+            Arc::new(CircuitBreaker::new("embeddings", 0, Duration::ZERO)),
+        );
+        let rag = RagService::new(
+            db.clone(),
+            EmbeddingService::new(String::new(), embedding_http),
+            RetrievalBackend::Memory,
+            ChunkerConfig::from_env(),
+            CategoryMode::Off,
+            RetrievalConfig::from_env().expect("default retrieval settings"),
+            0.95,
+        );
+
+        Arc::new(AppState {
+            config: AppConfig {
+                chat_model: "test-model".to_string(),
+                restore_pii: true,
+                session_ttl: chrono::Duration::minutes(60),
+                auth_enabled: false,
+            },
+            db,
+            rag,
+            provider: Arc::new(ScriptedProvider::new(script.iter().map(|s| s.to_string()).collect())),
+            prompts: Arc::new(PromptStore::from_env().expect("bundled prompt templates load")),
+            rate_limits: RateLimits {
+                chat: TokenBucketLimiter::new(0, 1),
+                ingest: TokenBucketLimiter::new(0, 1),
+                trust_proxy_headers: false,
+            },
+            upstream: UpstreamLimiter::new(4, Duration::from_secs(1)),
+            breakers: Vec::new(),
+            degraded: DegradedMode::from_env(),
+        })
+    }
+
+    async fn post(state: Arc<AppState>, path: &str, body: Value) -> (StatusCode, String) {
+        let request = axum::http::Request::post(path)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .expect("request builds");
+        send(state, request).await
+    }
+
+    async fn send(state: Arc<AppState>, request: axum::http::Request<Body>) -> (StatusCode, String) {
+        let response = router(state).oneshot(request).await.expect("router answers");
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("body reads");
+        (status, String::from_utf8(body.to_vec()).expect("utf-8 body"))
+    }
+
+    async fn chat(script: &[&str], body: Value) -> (StatusCode, Value) {
+        let (status, body) = post(state_with(script).await, "/api/chat", body).await;
+        (status, serde_json::from_str(&body).expect("JSON body"))
+    }
+
+    /// `(event, data)` pairs of a server-sent event stream
+    fn events(body: &str) -> Vec<(String, Value)> {
+        body.split("\n\n")
+            .filter_map(|block| {
+                let event = block.lines().find_map(|l| l.strip_prefix("event: "))?;
+                let data = block.lines().find_map(|l| l.strip_prefix("data: "))?;
+                Some((event.to_string(), serde_json::from_str(data).expect("JSON event data")))
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn chat_returns_the_model_reply_after_guardrails() {
+        let (status, body) = chat(&["That sounds hard. You should rest tonight."], json!({ "message": "I am so tired" })).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["response"], "That sounds hard. You could rest tonight.");
+        assert_eq!(body["crisis"], false);
+        assert_eq!(body["degraded"], false);
+        assert_eq!(body["metadata"]["model"], "test-model");
+        assert_eq!(
+            body["metadata"]["guardrails"],
+            json!([{ "rule": "imperative_advice", "action": "rewritten" }])
+        );
+    }
+
+    #[tokio::test]
+    async fn chat_restores_redacted_personal_data_in_the_reply() {
+        let (status, body) = chat(
+            &["Aku akan menghubungi [EMAIL_1] nanti."],
+            json!({ "message": "emailku budi@example.com, aku lagi bingung" }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["response"], "Aku akan menghubungi budi@example.com nanti.");
+    }
+
+    #[tokio::test]
+    async fn crisis_messages_never_reach_the_model() {
+        // A model call would fail, so a normal reply here means the bypass worked
+        let (status, body) = chat(&["error:500"], json!({ "message": "aku gak kuat, pengen mati aja" })).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["crisis"], true);
+        assert_eq!(body["response"], crisis::response_text(Language::Id));
+        assert!(body["hotlines"].as_array().is_some_and(|h| !h.is_empty()));
+    }
+
+    #[tokio::test]
+    async fn crisis_messages_skip_category_validation() {
+        let (status, body) = chat(&[], json!({ "message": "aku pengen bunuh diri", "category": "astrology" })).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["crisis"], true);
+    }
+
+    #[tokio::test]
+    async fn crisis_messages_are_answered_without_a_live_session() {
+        // No MongoDB is reachable, so the session cannot be found, as if it had expired
+        let body = json!({ "message": "i want to kill myself", "session_id": "expired" });
+        let (status, body) = chat(&[], body).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["crisis"], true);
+        assert_eq!(body["response"], crisis::response_text(Language::En));
+
+        let (status, _) = chat(&[], json!({ "message": "hello", "session_id": "expired" })).await;
+        assert_ne!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn crisis_messages_bypass_the_chat_rate_limit() {
+        let mut state = state_with(&["Tell me more."]).await;
+        Arc::get_mut(&mut state).expect("state is not shared yet").rate_limits.chat = TokenBucketLimiter::new(1, 1);
+
+        let (status, _) = post(state.clone(), "/api/chat", json!({ "message": "hello" })).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = post(state.clone(), "/api/chat", json!({ "message": "hello again" })).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        let (status, body) = post(state, "/api/chat", json!({ "message": "aku pengen bunuh diri" })).await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_str(&body).expect("JSON body");
+        assert_eq!(body["crisis"], true);
+    }
+
+    #[tokio::test]
+    async fn model_failures_get_a_degraded_reply() {
+        let (status, body) = chat(&["error:503"], json!({ "message": "I feel lonely lately" })).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["degraded"], true);
+        assert!(!body["response"].as_str().unwrap_or_default().is_empty());
+    }
+
+    #[tokio::test]
+    async fn invalid_chat_requests_are_rejected() {
+        let (status, _) = chat(&[], json!({ "message": "   " })).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, body) = chat(&[], json!({ "message": "halo", "category": "astrology" })).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["error"].as_str().is_some_and(|e| e.contains("astrology")));
+    }
+
+    /// Log output collected while the returned guard is alive
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl CapturedLogs {
+        fn start(&self) -> tracing::subscriber::DefaultGuard {
+            let logs = self.clone();
+            let subscriber = tracing_subscriber::fmt()
+                .with_max_level(tracing::Level::TRACE)
+                .with_writer(move || logs.clone())
+                .finish();
+            tracing::subscriber::set_default(subscriber)
+        }
+
+        fn text(&self) -> String {
+            String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+        }
+    }
+
+    #[tokio::test]
+    async fn user_data_routes_require_a_valid_anonymous_id() {
+        let state = state_with(&[]).await;
+        let request = axum::http::Request::get("/api/me/export").body(Body::empty()).unwrap();
+        let (status, _) = send(state.clone(), request).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let request = axum::http::Request::delete("/api/me")
+            .header("x-anonymous-id", "not-a-uuid")
+            .body(Body::empty())
+            .unwrap();
+        let (status, body) = send(state, request).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("UUID"));
+    }
+
+    #[tokio::test]
+    async fn user_data_requests_never_log_the_anonymous_id() {
+        let logs = CapturedLogs::default();
+        let _guard = logs.start();
+        let state = state_with(&["Tell me more."]).await;
+        let user_id = "0b6c8c52-3f0e-4a5e-9d55-6f3f8f1d2a10";
+
+        // MongoDB is unreachable, so every one of these fails and logs an error
+        let requests = [
+            axum::http::Request::get("/api/me/export").body(Body::empty()).unwrap(),
+            axum::http::Request::delete("/api/me").body(Body::empty()).unwrap(),
+            axum::http::Request::delete("/api/sessions/s1").body(Body::empty()).unwrap(),
+            axum::http::Request::post("/api/chat")
+                .header("content-type", "application/json")
+                .body(Body::from(json!({ "message": "halo", "session_id": "s1" }).to_string()))
+                .unwrap(),
+        ];
+        for mut request in requests {
+            request.headers_mut().insert("x-anonymous-id", user_id.parse().unwrap());
+            let (status, _) = send(state.clone(), request).await;
+            assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        }
+
+        let text = logs.text();
+        assert!(text.contains("Failed to delete user data"));
+        assert!(!text.contains(user_id));
+    }
+
+    #[test]
+    fn deletion_receipts_name_the_data_that_is_never_stored() {
+        let receipt = DeletionReceipt::new(BTreeMap::from([("sessions".to_string(), 2)]));
+        assert_eq!(receipt.not_stored, vec!["feedback", "consent_records", "logs"]);
+        assert_eq!(receipt.deleted["sessions"], 2);
+    }
+
+    #[tokio::test]
+    async fn stream_never_sends_a_sentence_that_breaks_guardrails() {
+        let state = state_with(&["I hear you. It sounds like you have clinical depression. Try journaling."]).await;
+        let (status, body) = post(state, "/api/chat/stream", json!({ "message": "I feel empty" })).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let events = events(&body);
+        let text: String = events
+            .iter()
+            .filter(|(event, _)| event == "delta")
+            .map(|(_, data)| data["content"].as_str().unwrap_or_default())
+            .collect();
+        assert!(text.starts_with("I hear you."));
+        assert!(text.ends_with(guardrails::fallback_text(Language::En)));
+        assert!(!text.contains("depression"));
+
+        let (event, done) = events.last().expect("stream has events");
+        assert_eq!(event, "done");
+        assert_eq!(
+            done["metadata"]["guardrails"],
+            json!([{ "rule": "diagnosis", "action": "fallback" }])
+        );
+    }
 }
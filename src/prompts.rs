@@ -0,0 +1,371 @@
+//! Persona prompts loaded from template files.
+//!
+//! Every prompt lives in `PROMPTS_DIR` as `<name>.v<version>.md` and the highest version
+//! of each name is used, so a wording change ships as a new file and rolling it back
+//! means deleting that file. Templates may reference `{{language}}` and
+//! `{{crisis_hotlines}}`. The set is validated at startup and reloaded when a file in
+//! the directory changes or the process receives SIGHUP; a set that fails validation is
+//! logged and the previous one stays in use.
+
+use crate::categories;
+use crate::crisis;
+use crate::language::Language;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+/// Base prompt, always sent first
+pub const GENERAL: &str = "general";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Variable {
+    /// Name of the language the user writes in
+    Language,
+    /// Crisis hotlines from `crisis::hotlines`, as one sentence fragment
+    CrisisHotlines,
+}
+
+impl Variable {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "language" => Some(Variable::Language),
+            "crisis_hotlines" => Some(Variable::CrisisHotlines),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Text(String),
+    Variable(Variable),
+}
+
+/// A parsed template file
+#[derive(Debug, Clone)]
+pub struct PromptTemplate {
+    pub version: u32,
+    segments: Vec<Segment>,
+}
+
+impl PromptTemplate {
+    fn parse(path: &Path, version: u32, source: &str) -> Result<Self, String> {
+        let source = source.trim();
+        if source.is_empty() {
+            return Err(format!("{} is empty", path.display()));
+        }
+
+        let mut segments = Vec::new();
+        let mut rest = source;
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                segments.push(Segment::Text(rest[..start].to_string()));
+            }
+            let after = &rest[start + 2..];
+            let end = after
+                .find("}}")
+                .ok_or_else(|| format!("{}: unclosed '{{{{'", path.display()))?;
+            let name = after[..end].trim();
+            let variable = Variable::parse(name)
+                .ok_or_else(|| format!("{}: unknown variable '{{{{{}}}}}'", path.display(), name))?;
+            segments.push(Segment::Variable(variable));
+            rest = &after[end + 2..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Text(rest.to_string()));
+        }
+
+        Ok(Self { version, segments })
+    }
+
+    fn render(&self, language: Language) -> String {
+        let mut out = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(text) => out.push_str(text),
+                Segment::Variable(Variable::Language) => out.push_str(language.name()),
+                Segment::Variable(Variable::CrisisHotlines) => out.push_str(&crisis::hotline_list("or")),
+            }
+        }
+        out
+    }
+}
+
+/// Split `career.v2.md` into `("career", 2)`
+fn parse_file_name(file_name: &str) -> Option<(&str, u32)> {
+    let stem = file_name.strip_suffix(".md")?;
+    let (name, version) = stem.rsplit_once(".v")?;
+    if name.is_empty() {
+        return None;
+    }
+    Some((name, version.parse().ok()?))
+}
+
+/// Names, sizes and modification times of the template files, used to notice edits
+type Fingerprint = Vec<(String, u64, Option<SystemTime>)>;
+
+fn fingerprint(dir: &Path) -> Result<Fingerprint, String> {
+    let entries = std::fs::read_dir(dir).map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+    let mut files = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !name.ends_with(".md") {
+            continue;
+        }
+        let metadata = entry.metadata().ok();
+        files.push((
+            name,
+            metadata.as_ref().map_or(0, |m| m.len()),
+            metadata.and_then(|m| m.modified().ok()),
+        ));
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Read and validate every template in `dir`
+fn load(dir: &Path) -> Result<BTreeMap<String, PromptTemplate>, String> {
+    let mut templates: BTreeMap<String, PromptTemplate> = BTreeMap::new();
+    for (file_name, _, _) in fingerprint(dir)? {
+        let Some((name, version)) = parse_file_name(&file_name) else {
+            tracing::warn!("Ignoring prompt file '{}': expected <name>.v<version>.md", file_name);
+            continue;
+        };
+        if let Some(existing) = templates.get(name) {
+            if existing.version == version {
+                return Err(format!("Prompt '{}' has two files for version {}", name, version));
+            }
+            if existing.version > version {
+                continue;
+            }
+        }
+        let path = dir.join(&file_name);
+        let source = std::fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        templates.insert(name.to_string(), PromptTemplate::parse(&path, version, &source)?);
+    }
+
+    // The general prompt and every persona a category refers to must exist
+    let missing: Vec<&str> = std::iter::once(GENERAL)
+        .chain(categories::prompt_names())
+        .filter(|name| !templates.contains_key(*name))
+        .collect();
+    if !missing.is_empty() {
+        return Err(format!("Missing prompt templates in {}: {}", dir.display(), missing.join(", ")));
+    }
+    Ok(templates)
+}
+
+fn describe(templates: &BTreeMap<String, PromptTemplate>) -> String {
+    templates
+        .iter()
+        .map(|(name, t)| format!("{} v{}", name, t.version))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The active prompt templates, swapped as a whole on reload
+pub struct PromptStore {
+    dir: PathBuf,
+    /// How often the directory is checked for changes; zero disables polling
+    poll_interval: Duration,
+    templates: RwLock<Arc<BTreeMap<String, PromptTemplate>>>,
+    /// Fingerprint of the directory at the last load attempt
+    seen: Mutex<Fingerprint>,
+}
+
+impl PromptStore {
+    /// Read `PROMPTS_DIR` (default `prompts`) and `PROMPTS_POLL_INTERVAL_SECS` (default 5)
+    /// and load the templates
+    pub fn from_env() -> Result<Self, String> {
+        let dir = PathBuf::from(std::env::var("PROMPTS_DIR").unwrap_or_else(|_| "prompts".to_string()));
+        let poll_interval = std::env::var("PROMPTS_POLL_INTERVAL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(5);
+        Self::open(dir, Duration::from_secs(poll_interval))
+    }
+
+    /// Load the templates in `dir`
+    fn open(dir: PathBuf, poll_interval: Duration) -> Result<Self, String> {
+        let seen = fingerprint(&dir)?;
+        let templates = load(&dir)?;
+        tracing::info!("Loaded prompt templates from {}: {}", dir.display(), describe(&templates));
+        Ok(Self {
+            dir,
+            poll_interval,
+            templates: RwLock::new(Arc::new(templates)),
+            seen: Mutex::new(seen),
+        })
+    }
+
+    fn current(&self) -> Arc<BTreeMap<String, PromptTemplate>> {
+        self.templates.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// The general prompt followed by the `persona` prompt, if one is given and exists
+    pub fn system_prompt(&self, persona: Option<&str>, language: Language) -> String {
+        let templates = self.current();
+        let mut prompt = templates.get(GENERAL).map(|t| t.render(language)).unwrap_or_default();
+        if let Some(template) = persona.filter(|p| *p != GENERAL).and_then(|p| templates.get(p)) {
+            prompt.push_str("\n\n");
+            prompt.push_str(&template.render(language));
+        }
+        prompt
+    }
+
+    /// Load the directory again, keeping the current templates if it is invalid
+    pub fn reload(&self, reason: &str) {
+        if let Ok(seen) = fingerprint(&self.dir) {
+            *self.seen.lock().unwrap_or_else(|e| e.into_inner()) = seen;
+        }
+        match load(&self.dir) {
+            Ok(templates) => {
+                tracing::info!("Reloaded prompt templates ({}): {}", reason, describe(&templates));
+                *self.templates.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(templates);
+            }
+            Err(e) => tracing::error!("Keeping current prompt templates, reload ({}) failed: {}", reason, e),
+        }
+    }
+
+    fn changed(&self) -> bool {
+        match fingerprint(&self.dir) {
+            Ok(current) => *self.seen.lock().unwrap_or_else(|e| e.into_inner()) != current,
+            Err(e) => {
+                tracing::warn!("Failed to check prompt templates for changes: {}", e);
+                false
+            }
+        }
+    }
+}
+
+/// Reload the templates when the directory changes and on SIGHUP
+pub fn watch(store: Arc<PromptStore>) {
+    if !store.poll_interval.is_zero() {
+        let store = store.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(store.poll_interval);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                if store.changed() {
+                    store.reload("file change");
+                }
+            }
+        });
+    }
+
+    #[cfg(unix)]
+    tokio::spawn(async move {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::hangup()) {
+            Ok(mut hangup) => {
+                while hangup.recv().await.is_some() {
+                    store.reload("SIGHUP");
+                }
+            }
+            Err(e) => tracing::warn!("Failed to listen for SIGHUP, prompts reload on file change only: {}", e),
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Copy of the bundled templates in a fresh directory, removed on drop
+    struct TemplateDir(PathBuf);
+
+    impl TemplateDir {
+        fn bundled() -> Self {
+            let dir = std::env::temp_dir().join(format!("prompts-{}", uuid::Uuid::new_v4()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let bundled = Path::new(env!("CARGO_MANIFEST_DIR")).join("prompts");
+            for entry in std::fs::read_dir(bundled).unwrap().flatten() {
+                std::fs::copy(entry.path(), dir.join(entry.file_name())).unwrap();
+            }
+            Self(dir)
+        }
+
+        fn write(&self, file_name: &str, content: &str) {
+            std::fs::write(self.0.join(file_name), content).unwrap();
+        }
+    }
+
+    impl Drop for TemplateDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn file_names_carry_the_name_and_version() {
+        assert_eq!(parse_file_name("career.v2.md"), Some(("career", 2)));
+        assert_eq!(parse_file_name("self_development.v10.md"), Some(("self_development", 10)));
+        assert_eq!(parse_file_name("my.notes.v3.md"), Some(("my.notes", 3)));
+        assert_eq!(parse_file_name("career.md"), None);
+        assert_eq!(parse_file_name("career.vx.md"), None);
+        assert_eq!(parse_file_name(".v1.md"), None);
+        assert_eq!(parse_file_name("career.v1.txt"), None);
+    }
+
+    #[test]
+    fn the_highest_version_is_used() {
+        let dir = TemplateDir::bundled();
+        dir.write("career.v3.md", "Career v3");
+        dir.write("career.v2.md", "Career v2");
+        let templates = load(&dir.0).unwrap();
+        assert_eq!(templates["career"].version, 3);
+        assert_eq!(templates["career"].render(Language::En), "Career v3");
+    }
+
+    #[test]
+    fn duplicate_versions_are_rejected() {
+        let dir = TemplateDir::bundled();
+        dir.write("career.v01.md", "Career, padded version");
+        let error = load(&dir.0).unwrap_err();
+        assert!(error.contains("two files for version 1"), "{}", error);
+    }
+
+    #[test]
+    fn variables_are_rendered() {
+        let template = PromptTemplate::parse(Path::new("t.v1.md"), 1, "Reply in {{ language }}.").unwrap();
+        assert_eq!(template.render(Language::Id), format!("Reply in {}.", Language::Id.name()));
+        let template = PromptTemplate::parse(Path::new("t.v1.md"), 1, "Call {{crisis_hotlines}}.").unwrap();
+        assert_eq!(template.render(Language::En), format!("Call {}.", crisis::hotline_list("or")));
+    }
+
+    #[test]
+    fn unknown_and_unclosed_variables_are_rejected() {
+        let error = PromptTemplate::parse(Path::new("t.v1.md"), 1, "Hi {{name}}").unwrap_err();
+        assert!(error.contains("unknown variable '{{name}}'"), "{}", error);
+        let error = PromptTemplate::parse(Path::new("t.v1.md"), 1, "Hi {{language").unwrap_err();
+        assert!(error.contains("unclosed"), "{}", error);
+        assert!(PromptTemplate::parse(Path::new("t.v1.md"), 1, "  \n").is_err());
+    }
+
+    #[test]
+    fn missing_prompts_are_rejected() {
+        let dir = TemplateDir::bundled();
+        std::fs::remove_file(dir.0.join("general.v1.md")).unwrap();
+        let error = load(&dir.0).unwrap_err();
+        assert!(error.contains("general"), "{}", error);
+    }
+
+    #[test]
+    fn reload_keeps_the_previous_set_when_validation_fails() {
+        let dir = TemplateDir::bundled();
+        let store = PromptStore::open(dir.0.clone(), Duration::ZERO).unwrap();
+        let before = store.system_prompt(Some("career"), Language::En);
+
+        dir.write("career.v9.md", "Broken {{mood}}");
+        assert!(store.changed());
+        store.reload("test");
+        assert_eq!(store.system_prompt(Some("career"), Language::En), before);
+        assert!(!store.changed());
+
+        dir.write("career.v9.md", "Career v9");
+        store.reload("test");
+        assert!(store.system_prompt(Some("career"), Language::En).ends_with("\n\nCareer v9"));
+    }
+}
@@ -1,42 +1,1197 @@
-use crate::db::{AppDatabase, KnowledgeDocument};
-use crate::embeddings::{cosine_similarity, EmbeddingService};
+use crate::bm25::{self, Bm25Index};
+use crate::chunker::{self, ChunkerConfig};
+use crate::db::{content_hash, is_duplicate_key, AppDatabase, KnowledgeDocument, ParentDocument};
+use crate::embeddings::{self, cosine_similarity, EmbeddingService};
+use crate::reembed::{ReembedProgress, ReembedTracker};
+use crate::vector_index::VectorIndex;
+use chrono::Utc;
 use futures::stream::TryStreamExt;
+use mongodb::bson::{doc, Document};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use utoipa::ToSchema;
+use uuid::Uuid;
 
-/// Retrieved document with similarity score
+/// Retrieved chunk with similarity score
 #[derive(Debug, Clone)]
 pub struct RetrievedDocument {
+    /// Article the chunk belongs to
+    pub parent_id: String,
+    pub chunk_index: u32,
     pub content: String,
     pub title: String,
     pub category: String,
-    #[allow(dead_code)]
+    /// Cosine similarity to the query; 0 for chunks found only by BM25
     pub similarity: f64,
+    /// BM25 score when the chunk matched the query terms
+    pub lexical_score: Option<f64>,
+    /// Chunk embedding when the backend returned it, used for diversity re-ranking
+    pub embedding: Option<Vec<f64>>,
+}
+
+/// Article submitted for ingestion
+#[derive(Debug, Clone)]
+pub struct NewDocument {
+    pub title: String,
+    pub category: String,
+    pub content: String,
+    /// When the content is already stored, update that article's title and category
+    pub upsert: bool,
+}
+
+/// What ingesting an article did
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum IngestStatus {
+    Created,
+    /// The content was already stored; nothing was written
+    Duplicate,
+    /// The content was already stored; its title and category were updated
+    Updated,
+}
+
+/// Stored article that looks almost the same as a newly ingested one
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct NearDuplicate {
+    pub id: String,
+    pub title: String,
+    /// Highest cosine similarity between their chunks
+    pub similarity: f64,
+}
+
+/// Result of ingesting one article
+#[derive(Debug, Clone)]
+pub struct IngestOutcome {
+    /// The new article, or the existing one for duplicates
+    pub document: ParentDocument,
+    pub status: IngestStatus,
+    pub near_duplicates: Vec<NearDuplicate>,
+}
+
+/// Chunks of a new article compared against the knowledge base for near duplicates
+const NEAR_DUPLICATE_CHECK_CHUNKS: usize = 3;
+
+/// Where similarity search runs, selected with `RETRIEVAL_BACKEND`
+#[derive(Debug, Clone)]
+pub enum RetrievalBackend {
+    /// In-process HNSW index (`memory`, the default)
+    Memory,
+    /// MongoDB Atlas `$vectorSearch` (`atlas`); falls back to a scan if the stage is unavailable
+    Atlas {
+        index_name: String,
+        /// Candidates the ANN search considers before returning `limit` results
+        num_candidates: u32,
+        /// Dimensions declared in the index definition
+        dimensions: u32,
+    },
+    /// Load every document and compare in process (`scan`); works on any MongoDB
+    Scan,
+}
+
+impl RetrievalBackend {
+    /// Read `RETRIEVAL_BACKEND`, `ATLAS_VECTOR_INDEX`, `ATLAS_NUM_CANDIDATES` and `EMBEDDING_DIMENSIONS`
+    pub fn from_env() -> Result<Self, String> {
+        let backend = std::env::var("RETRIEVAL_BACKEND").unwrap_or_else(|_| "memory".to_string());
+        match backend.to_lowercase().as_str() {
+            "memory" | "" => Ok(Self::Memory),
+            "scan" => Ok(Self::Scan),
+            "atlas" => Ok(Self::Atlas {
+                index_name: std::env::var("ATLAS_VECTOR_INDEX")
+                    .unwrap_or_else(|_| "knowledge_vector_index".to_string()),
+                num_candidates: std::env::var("ATLAS_NUM_CANDIDATES")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(100),
+                dimensions: std::env::var("EMBEDDING_DIMENSIONS")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(1536),
+            }),
+            other => Err(format!(
+                "unknown RETRIEVAL_BACKEND '{}', expected memory, atlas or scan",
+                other
+            )),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Memory => "memory",
+            Self::Atlas { .. } => "atlas",
+            Self::Scan => "scan",
+        }
+    }
+}
+
+/// How the chat category influences retrieval, selected with `CATEGORY_RETRIEVAL_MODE`
+#[derive(Debug, Clone, Copy)]
+pub enum CategoryMode {
+    /// Ignore the chat category (`off`)
+    Off,
+    /// Only search knowledge categories mapped to the chat category (`strict`)
+    Strict,
+    /// Search everything but add `weight` to the similarity of matching chunks (`boost`, the default)
+    Boost { weight: f64 },
+}
+
+impl CategoryMode {
+    /// Read `CATEGORY_RETRIEVAL_MODE` and `CATEGORY_BOOST` (default 0.05)
+    pub fn from_env() -> Result<Self, String> {
+        let mode = std::env::var("CATEGORY_RETRIEVAL_MODE").unwrap_or_else(|_| "boost".to_string());
+        match mode.to_lowercase().as_str() {
+            "off" => Ok(Self::Off),
+            "strict" => Ok(Self::Strict),
+            "boost" | "" => Ok(Self::Boost {
+                weight: std::env::var("CATEGORY_BOOST")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(0.05),
+            }),
+            other => Err(format!(
+                "unknown CATEGORY_RETRIEVAL_MODE '{}', expected off, strict or boost",
+                other
+            )),
+        }
+    }
+}
+
+/// Weights for fusing vector and BM25 rankings with reciprocal rank fusion
+#[derive(Debug, Clone, Copy)]
+pub struct HybridConfig {
+    pub vector_weight: f64,
+    /// Zero turns lexical retrieval off
+    pub bm25_weight: f64,
+    /// Damping constant; larger values flatten the gap between top and lower ranks
+    pub rrf_k: f64,
+    /// BM25 score a lexical hit needs; the stand-in for `min_similarity` on chunks the
+    /// vector search did not return
+    pub min_bm25_score: f64,
+}
+
+impl Default for HybridConfig {
+    fn default() -> Self {
+        Self {
+            vector_weight: 1.0,
+            bm25_weight: 1.0,
+            rrf_k: 60.0,
+            min_bm25_score: 1.0,
+        }
+    }
+}
+
+impl HybridConfig {
+    /// Read `HYBRID_VECTOR_WEIGHT`, `HYBRID_BM25_WEIGHT` (default 1 each), `RRF_K` (default 60)
+    /// and `HYBRID_MIN_BM25_SCORE` (default 1)
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let read = |name: &str, default: f64| {
+            std::env::var(name)
+                .ok()
+                .and_then(|v| v.parse::<f64>().ok())
+                .filter(|v| v.is_finite() && *v >= 0.0)
+                .unwrap_or(default)
+        };
+        Self {
+            vector_weight: read("HYBRID_VECTOR_WEIGHT", defaults.vector_weight),
+            bm25_weight: read("HYBRID_BM25_WEIGHT", defaults.bm25_weight),
+            rrf_k: read("RRF_K", defaults.rrf_k),
+            min_bm25_score: read("HYBRID_MIN_BM25_SCORE", defaults.min_bm25_score),
+        }
+    }
+
+    pub fn lexical_enabled(&self) -> bool {
+        self.bm25_weight > 0.0
+    }
+}
+
+/// Maximal marginal relevance settings
+#[derive(Debug, Clone, Copy)]
+pub struct MmrConfig {
+    /// Trade-off between relevance (1.0) and diversity (0.0); 1.0 turns re-ranking off
+    pub lambda: f64,
+}
+
+impl MmrConfig {
+    /// Read `MMR_LAMBDA` (default 0.7)
+    pub fn from_env() -> Self {
+        let lambda = std::env::var("MMR_LAMBDA")
+            .ok()
+            .and_then(|v| v.parse::<f64>().ok())
+            .filter(|v| v.is_finite())
+            .unwrap_or(0.7)
+            .clamp(0.0, 1.0);
+        Self { lambda }
+    }
+}
+
+/// Retrieval parameters used for one query
+#[derive(Debug, Clone, Copy)]
+pub struct RetrievalParams {
+    /// Chunks handed to the prompt at most
+    pub top_k: usize,
+    /// Cosine similarity a chunk from the vector search needs
+    pub min_similarity: f64,
+    /// Chunks fetched from each index before thresholding, boosting and re-ranking
+    pub candidate_pool: usize,
+}
+
+/// Per-request retrieval settings sent with a chat message
+#[derive(Debug, Clone, Default, Deserialize, ToSchema)]
+pub struct RetrievalOverrides {
+    #[schema(example = 5)]
+    pub top_k: Option<usize>,
+    #[schema(example = 0.4)]
+    pub min_similarity: Option<f64>,
+    #[schema(example = 20)]
+    pub candidate_pool: Option<usize>,
+}
+
+/// Deployment retrieval settings: defaults, the bounds requests may override them within,
+/// and how rankings are fused and diversified
+#[derive(Debug, Clone, Copy)]
+pub struct RetrievalConfig {
+    pub defaults: RetrievalParams,
+    pub max_top_k: usize,
+    pub max_candidate_pool: usize,
+    /// Lowest `min_similarity` a request may ask for
+    pub min_similarity_floor: f64,
+    pub hybrid: HybridConfig,
+    pub mmr: MmrConfig,
+}
+
+impl RetrievalConfig {
+    /// Read `RETRIEVAL_TOP_K` (3), `RETRIEVAL_MIN_SIMILARITY` (0.3), `RETRIEVAL_CANDIDATE_POOL` (12)
+    /// and the bounds `RETRIEVAL_MAX_TOP_K` (10), `RETRIEVAL_MAX_CANDIDATE_POOL` (50) and
+    /// `RETRIEVAL_MIN_SIMILARITY_FLOOR` (0), along with the hybrid and MMR settings
+    pub fn from_env() -> Result<Self, String> {
+        fn read<T: std::str::FromStr>(name: &str, default: T) -> Result<T, String> {
+            match std::env::var(name) {
+                Ok(v) if !v.trim().is_empty() => v.trim().parse().map_err(|_| format!("{} must be a number", name)),
+                _ => Ok(default),
+            }
+        }
+        let config = Self {
+            defaults: RetrievalParams {
+                top_k: read("RETRIEVAL_TOP_K", 3)?,
+                min_similarity: read("RETRIEVAL_MIN_SIMILARITY", 0.3)?,
+                candidate_pool: read("RETRIEVAL_CANDIDATE_POOL", 12)?,
+            },
+            max_top_k: read("RETRIEVAL_MAX_TOP_K", 10)?,
+            max_candidate_pool: read("RETRIEVAL_MAX_CANDIDATE_POOL", 50)?,
+            min_similarity_floor: read("RETRIEVAL_MIN_SIMILARITY_FLOOR", 0.0)?,
+            hybrid: HybridConfig::from_env(),
+            mmr: MmrConfig::from_env(),
+        };
+        config
+            .check(&config.defaults)
+            .map_err(|e| format!("defaults out of bounds: {}", e))?;
+        Ok(config)
+    }
+
+    fn check(&self, params: &RetrievalParams) -> Result<(), String> {
+        if params.top_k == 0 || params.top_k > self.max_top_k {
+            return Err(format!("top_k must be between 1 and {}", self.max_top_k));
+        }
+        if !(self.min_similarity_floor..=1.0).contains(&params.min_similarity) {
+            return Err(format!(
+                "min_similarity must be between {} and 1",
+                self.min_similarity_floor
+            ));
+        }
+        if params.candidate_pool < params.top_k || params.candidate_pool > self.max_candidate_pool {
+            return Err(format!(
+                "candidate_pool must be between top_k ({}) and {}",
+                params.top_k, self.max_candidate_pool
+            ));
+        }
+        Ok(())
+    }
+
+    /// Apply a request's overrides to the defaults, rejecting values outside the bounds.
+    ///
+    /// A larger `top_k` without a `candidate_pool` grows the pool along with it.
+    pub fn resolve(&self, overrides: Option<&RetrievalOverrides>) -> Result<RetrievalParams, String> {
+        let Some(overrides) = overrides else {
+            return Ok(self.defaults);
+        };
+        let top_k = overrides.top_k.unwrap_or(self.defaults.top_k);
+        let params = RetrievalParams {
+            top_k,
+            min_similarity: overrides.min_similarity.unwrap_or(self.defaults.min_similarity),
+            candidate_pool: overrides
+                .candidate_pool
+                .unwrap_or_else(|| self.defaults.candidate_pool.max(top_k)),
+        };
+        self.check(&params)?;
+        Ok(params)
+    }
+}
+
+/// Document returned by `$vectorSearch`
+#[derive(Debug, Deserialize)]
+struct VectorSearchHit {
+    #[serde(rename = "_id")]
+    id: String,
+    #[serde(default)]
+    parent_id: Option<String>,
+    #[serde(default)]
+    chunk_index: u32,
+    content: String,
+    title: String,
+    category: String,
+    score: f64,
+    #[serde(default)]
+    embedding: Option<Vec<f64>>,
+    #[serde(default)]
+    embedding_model: Option<String>,
+}
+
+/// Chunk text read by the re-embedding job
+#[derive(Debug, Deserialize)]
+struct ChunkContent {
+    #[serde(rename = "_id")]
+    id: String,
+    content: String,
 }
 
 /// RAG (Retrieval-Augmented Generation) service
 pub struct RagService {
     db: AppDatabase,
-    embedding_service: EmbeddingService,
+    /// Swapped when a re-embedding job completes
+    embedding_service: RwLock<Arc<EmbeddingService>>,
+    backend: RetrievalBackend,
+    chunking: ChunkerConfig,
+    category_mode: CategoryMode,
+    retrieval: RetrievalConfig,
+    /// Similarity above which a new article is reported as a near duplicate; 0 disables the check
+    near_duplicate_threshold: f64,
+    index: VectorIndex,
+    lexical: Bm25Index,
+    reembed: ReembedTracker,
 }
 
 impl RagService {
-    pub fn new(db: AppDatabase, embedding_service: EmbeddingService) -> Self {
-        Self { db, embedding_service }
+    pub fn new(
+        db: AppDatabase,
+        embedding_service: EmbeddingService,
+        backend: RetrievalBackend,
+        chunking: ChunkerConfig,
+        category_mode: CategoryMode,
+        retrieval: RetrievalConfig,
+        near_duplicate_threshold: f64,
+    ) -> Self {
+        Self {
+            db,
+            embedding_service: RwLock::new(Arc::new(embedding_service)),
+            backend,
+            chunking,
+            category_mode,
+            retrieval,
+            near_duplicate_threshold,
+            index: VectorIndex::new(),
+            lexical: Bm25Index::new(),
+            reembed: ReembedTracker::new(),
+        }
+    }
+    
+    /// Current embedding service; queries and stored chunks must come from the same model
+    fn embedder(&self) -> Arc<EmbeddingService> {
+        self.embedding_service.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+    
+    /// Model that query embeddings and newly ingested chunks use
+    pub fn embedding_model(&self) -> String {
+        self.embedder().model().to_string()
+    }
+    
+    pub fn backend(&self) -> &RetrievalBackend {
+        &self.backend
+    }
+    
+    pub fn retrieval_config(&self) -> &RetrievalConfig {
+        &self.retrieval
+    }
+    
+    /// Prepare the configured backend: load the in-memory index or make sure the Atlas index exists.
+    /// The BM25 index is loaded for every backend.
+    pub async fn initialize(&self) -> Result<(), String> {
+        let model = self.embedding_model();
+        match self.db.embedding_model_counts().await {
+            Ok(counts) => {
+                for (other, count) in counts.iter().filter(|(m, _)| **m != model) {
+                    tracing::warn!(
+                        "{} chunks embedded with '{}' are excluded from retrieval; re-embed them with '{}' via POST /api/admin/embeddings/reembed",
+                        count,
+                        other,
+                        model
+                    );
+                }
+            }
+            Err(e) => tracing::warn!("Failed to count chunks per embedding model: {}", e),
+        }
+        if self.retrieval.hybrid.lexical_enabled() {
+            let count = self.lexical.reload(&self.db).await?;
+            tracing::info!("BM25 index loaded with {} documents", count);
+        }
+        match &self.backend {
+            RetrievalBackend::Memory => {
+                let count = self.index.reload(&self.db, &model).await?;
+                tracing::info!("Vector index loaded with {} documents", count);
+            }
+            RetrievalBackend::Atlas { index_name, dimensions, .. } => {
+                self.db
+                    .ensure_vector_search_index(index_name, *dimensions)
+                    .await
+                    .map_err(|e| format!("Failed to create Atlas vector search index: {}", e))?;
+            }
+            RetrievalBackend::Scan => {}
+        }
+        Ok(())
+    }
+    
+    /// Chunk an article, embed every chunk and store the article and its chunks.
+    ///
+    /// Content that is already stored is not stored again; the outcome then names the existing article.
+    pub async fn ingest(&self, document: NewDocument) -> Result<IngestOutcome, String> {
+        self.ingest_many(vec![document])
+            .await
+            .pop()
+            .unwrap_or_else(|| Err("Nothing to ingest".to_string()))
+    }
+    
+    /// Ingest several articles, embedding their chunks in batches.
+    ///
+    /// Returns one result per article in input order; a failing article does not stop the others.
+    pub async fn ingest_many(&self, documents: Vec<NewDocument>) -> Vec<Result<IngestOutcome, String>> {
+        let embedder = self.embedder();
+        let hashes: Vec<String> = documents.iter().map(|d| content_hash(&d.content)).collect();
+        let mut known = match self.find_by_hashes(&hashes).await {
+            Ok(known) => known,
+            Err(e) => return documents.iter().map(|_| Err(e.clone())).collect(),
+        };
+        
+        // Only content that is neither stored nor repeated earlier in this batch gets embedded
+        let mut seen = HashSet::new();
+        let pieces: Vec<Vec<String>> = documents
+            .iter()
+            .zip(&hashes)
+            .map(|(d, hash)| {
+                if known.contains_key(hash) || !seen.insert(hash.as_str()) {
+                    Vec::new()
+                } else {
+                    chunker::chunk(&d.content, self.chunking)
+                }
+            })
+            .collect();
+        
+        // Group whole articles into embedding calls, so a failed call only fails its own articles
+        let mut embeddings: Vec<Result<Vec<Vec<f64>>, String>> = Vec::with_capacity(documents.len());
+        let mut start = 0;
+        while start < pieces.len() {
+            let mut end = start + 1;
+            let mut count = pieces[start].len();
+            while end < pieces.len() && count + pieces[end].len() <= embedder.batch_size() {
+                count += pieces[end].len();
+                end += 1;
+            }
+            let texts: Vec<String> = pieces[start..end].iter().flatten().cloned().collect();
+            match embedder.generate_embeddings(&texts).await {
+                Ok(mut vectors) => {
+                    for article in &pieces[start..end] {
+                        let rest = vectors.split_off(article.len());
+                        embeddings.push(Ok(std::mem::replace(&mut vectors, rest)));
+                    }
+                }
+                Err(e) => {
+                    let error = format!("Failed to generate embedding: {}", e);
+                    embeddings.extend((start..end).map(|_| Err(error.clone())));
+                }
+            }
+            start = end;
+        }
+        
+        let mut results = Vec::with_capacity(documents.len());
+        for (((document, hash), pieces), vectors) in documents.into_iter().zip(hashes).zip(pieces).zip(embeddings) {
+            if let Some(existing) = known.get(&hash).cloned() {
+                results.push(self.resolve_duplicate(existing, document).await);
+                continue;
+            }
+            if pieces.is_empty() {
+                results.push(Err("Same content as an earlier document in this request, which failed".to_string()));
+                continue;
+            }
+            let vectors = match vectors {
+                Ok(vectors) => vectors,
+                Err(e) => {
+                    results.push(Err(e));
+                    continue;
+                }
+            };
+            
+            let near_duplicates = self.near_duplicates(&vectors, embedder.model()).await;
+            let parent_id = Uuid::new_v4().to_string();
+            let chunks = build_chunks(
+                &parent_id,
+                &document.title,
+                &document.category,
+                pieces,
+                vectors,
+                embedder.model(),
+            );
+            let parent = ParentDocument {
+                id: parent_id,
+                title: document.title.clone(),
+                category: document.category.clone(),
+                content: document.content.clone(),
+                chunk_count: chunks.len() as u32,
+                created_at: Utc::now(),
+                content_hash: Some(hash.clone()),
+            };
+            let outcome = match self.store(&parent, &chunks).await {
+                Ok(true) => {
+                    known.insert(hash, parent.clone());
+                    Ok(IngestOutcome {
+                        document: parent,
+                        status: IngestStatus::Created,
+                        near_duplicates,
+                    })
+                }
+                // Stored concurrently by another request
+                Ok(false) => match self.find_by_hashes(std::slice::from_ref(&hash)).await {
+                    Ok(mut found) => match found.remove(&hash) {
+                        Some(existing) => self.resolve_duplicate(existing, document).await,
+                        None => Err("Failed to store document: content conflict".to_string()),
+                    },
+                    Err(e) => Err(e),
+                },
+                Err(e) => Err(e),
+            };
+            results.push(outcome);
+        }
+        results
+    }
+    
+    /// Stored articles with any of `hashes`, keyed by hash
+    async fn find_by_hashes(&self, hashes: &[String]) -> Result<HashMap<String, ParentDocument>, String> {
+        let documents: Vec<ParentDocument> = self
+            .db
+            .documents_collection()
+            .find(doc! { "content_hash": { "$in": hashes } })
+            .await
+            .map_err(|e| format!("Failed to look up duplicates: {}", e))?
+            .try_collect()
+            .await
+            .map_err(|e| format!("Failed to look up duplicates: {}", e))?;
+        Ok(documents
+            .into_iter()
+            .filter_map(|d| Some((d.content_hash.clone()?, d)))
+            .collect())
+    }
+    
+    /// Stored article with the same content as `content`, if any
+    pub async fn find_duplicate(&self, content: &str) -> Result<Option<ParentDocument>, String> {
+        let hash = content_hash(content);
+        Ok(self.find_by_hashes(std::slice::from_ref(&hash)).await?.remove(&hash))
+    }
+    
+    /// Answer an ingest of content that is already stored: report the existing article,
+    /// or copy the new title and category onto it when the caller asked for an upsert
+    async fn resolve_duplicate(&self, existing: ParentDocument, document: NewDocument) -> Result<IngestOutcome, String> {
+        if !document.upsert {
+            return Ok(IngestOutcome {
+                document: existing,
+                status: IngestStatus::Duplicate,
+                near_duplicates: Vec::new(),
+            });
+        }
+        let updated = self
+            .update(&existing.id, Some(document.title), Some(document.category), None)
+            .await?
+            .ok_or_else(|| "Document was deleted while updating".to_string())?;
+        Ok(IngestOutcome {
+            document: updated,
+            status: IngestStatus::Updated,
+            near_duplicates: Vec::new(),
+        })
     }
     
-    /// Retrieve relevant documents based on query similarity
+    /// Stored articles with a chunk at least `near_duplicate_threshold` similar to one of the
+    /// first `NEAR_DUPLICATE_CHECK_CHUNKS` chunks of a new article
+    async fn near_duplicates(&self, vectors: &[Vec<f64>], model: &str) -> Vec<NearDuplicate> {
+        let mut found: Vec<NearDuplicate> = Vec::new();
+        if self.near_duplicate_threshold <= 0.0 {
+            return found;
+        }
+        for vector in vectors.iter().take(NEAR_DUPLICATE_CHECK_CHUNKS) {
+            let hits = match self.search(vector, model, 3, &[]).await {
+                Ok(hits) => hits,
+                Err(e) => {
+                    tracing::warn!("Near-duplicate check failed: {}", e);
+                    break;
+                }
+            };
+            for hit in hits.into_iter().filter(|h| h.similarity >= self.near_duplicate_threshold) {
+                match found.iter_mut().find(|d| d.id == hit.parent_id) {
+                    Some(existing) => existing.similarity = existing.similarity.max(hit.similarity),
+                    None => found.push(NearDuplicate {
+                        id: hit.parent_id,
+                        title: hit.title,
+                        similarity: hit.similarity,
+                    }),
+                }
+            }
+        }
+        found.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+        found
+    }
+    
+    /// Insert an article and its chunks, then index the chunks.
+    ///
+    /// Returns `false` without storing anything when an article with the same content exists.
+    async fn store(&self, parent: &ParentDocument, chunks: &[KnowledgeDocument]) -> Result<bool, String> {
+        match self.db.documents_collection().insert_one(parent).await {
+            Ok(_) => {}
+            Err(e) if is_duplicate_key(&e) => return Ok(false),
+            Err(e) => return Err(format!("Failed to store document: {}", e)),
+        }
+        if let Err(e) = self.db.knowledge_collection().insert_many(chunks).await {
+            // Do not leave an article behind that retrieval can never find
+            let _ = self.db.documents_collection().delete_one(doc! { "_id": &parent.id }).await;
+            let _ = self.db.knowledge_collection().delete_many(doc! { "parent_id": &parent.id }).await;
+            return Err(format!("Failed to store document: {}", e));
+        }
+        
+        for chunk in chunks {
+            self.index_document(chunk);
+        }
+        Ok(true)
+    }
+    
+    /// Apply changes to an article. A new `content` is re-chunked and re-embedded;
+    /// title and category changes are copied onto the existing chunks.
+    ///
+    /// Returns `None` if the article does not exist.
+    pub async fn update(
+        &self,
+        id: &str,
+        title: Option<String>,
+        category: Option<String>,
+        content: Option<String>,
+    ) -> Result<Option<ParentDocument>, String> {
+        let Some(original) = self
+            .db
+            .documents_collection()
+            .find_one(doc! { "_id": id })
+            .await
+            .map_err(|e| format!("Failed to load document: {}", e))?
+        else {
+            return Ok(None);
+        };
+        
+        let mut parent = original.clone();
+        if let Some(title) = title {
+            parent.title = title;
+        }
+        if let Some(category) = category {
+            parent.category = category;
+        }
+        let content_changed = content.as_ref().is_some_and(|c| *c != parent.content);
+        if let Some(content) = content {
+            parent.content = content;
+            parent.content_hash = Some(content_hash(&parent.content));
+        }
+        
+        if content_changed {
+            self.replace_chunks(&original, &mut parent).await?;
+            return Ok(Some(parent));
+        }
+        
+        self.db
+            .knowledge_collection()
+            .update_many(
+                doc! { "parent_id": id },
+                doc! { "$set": { "title": &parent.title, "category": &parent.category } },
+            )
+            .await
+            .map_err(|e| format!("Failed to update chunks: {}", e))?;
+        let chunks: Vec<KnowledgeDocument> = self
+            .db
+            .knowledge_collection()
+            .find(doc! { "parent_id": id })
+            .await
+            .map_err(|e| format!("Failed to load chunks: {}", e))?
+            .try_collect()
+            .await
+            .map_err(|e| format!("Failed to load chunks: {}", e))?;
+        for chunk in &chunks {
+            self.index_document(chunk);
+        }
+        
+        self.db
+            .documents_collection()
+            .replace_one(doc! { "_id": id }, &parent)
+            .await
+            .map_err(|e| format!("Failed to store document: {}", e))?;
+        Ok(Some(parent))
+    }
+    
+    /// Re-chunk `parent` after a content change and swap its chunks.
+    ///
+    /// The new chunks are inserted under fresh ids next to the old ones, then the article is
+    /// saved, and only then are the old chunks removed. A failing step undoes the earlier
+    /// ones, so the article keeps either its old or its new chunks, never none.
+    async fn replace_chunks(&self, original: &ParentDocument, parent: &mut ParentDocument) -> Result<(), String> {
+        let id = parent.id.clone();
+        let old_chunk_ids = self
+            .db
+            .chunk_ids(&id)
+            .await
+            .map_err(|e| format!("Failed to load chunks: {}", e))?;
+        
+        // Embed first, so a failing embeddings API leaves the old chunks in place
+        let chunks = self.embed_chunks(&id, &parent.title, &parent.category, &parent.content).await?;
+        parent.chunk_count = chunks.len() as u32;
+        let new_chunk_ids: Vec<&str> = chunks.iter().map(|chunk| chunk.id.as_str()).collect();
+        let discard_new = || async {
+            let _ = self
+                .db
+                .knowledge_collection()
+                .delete_many(doc! { "_id": { "$in": &new_chunk_ids } })
+                .await;
+        };
+        
+        if let Err(e) = self.db.knowledge_collection().insert_many(&chunks).await {
+            discard_new().await;
+            return Err(format!("Failed to replace chunks: {}", e));
+        }
+        if let Err(e) = self.db.documents_collection().replace_one(doc! { "_id": &id }, &*parent).await {
+            discard_new().await;
+            return Err(format!("Failed to store document: {}", e));
+        }
+        if let Err(e) = self
+            .db
+            .knowledge_collection()
+            .delete_many(doc! { "_id": { "$in": &old_chunk_ids } })
+            .await
+        {
+            let _ = self.db.documents_collection().replace_one(doc! { "_id": &id }, original).await;
+            discard_new().await;
+            return Err(format!("Failed to replace chunks: {}", e));
+        }
+        
+        for chunk_id in &old_chunk_ids {
+            self.unindex_document(chunk_id);
+        }
+        for chunk in &chunks {
+            self.index_document(chunk);
+        }
+        Ok(())
+    }
+    
+    /// Remove an article and all of its chunks; returns whether it existed
+    pub async fn delete(&self, id: &str) -> Result<bool, String> {
+        let chunk_ids = self
+            .db
+            .chunk_ids(id)
+            .await
+            .map_err(|e| format!("Failed to load chunks: {}", e))?;
+        self.db
+            .knowledge_collection()
+            .delete_many(doc! { "parent_id": id })
+            .await
+            .map_err(|e| format!("Failed to delete chunks: {}", e))?;
+        for chunk_id in &chunk_ids {
+            self.unindex_document(chunk_id);
+        }
+        let deleted = self
+            .db
+            .documents_collection()
+            .delete_one(doc! { "_id": id })
+            .await
+            .map_err(|e| format!("Failed to delete document: {}", e))?;
+        Ok(deleted.deleted_count > 0 || !chunk_ids.is_empty())
+    }
+    
+    /// Split an article into chunks and embed each of them
+    async fn embed_chunks(
+        &self,
+        parent_id: &str,
+        title: &str,
+        category: &str,
+        content: &str,
+    ) -> Result<Vec<KnowledgeDocument>, String> {
+        let embedder = self.embedder();
+        let pieces = chunker::chunk(content, self.chunking);
+        let vectors = embedder
+            .generate_embeddings(&pieces)
+            .await
+            .map_err(|e| format!("Failed to generate embedding: {}", e))?;
+        Ok(build_chunks(parent_id, title, category, pieces, vectors, embedder.model()))
+    }
+    
+    /// Must be called after every knowledge write so the in-memory indexes stay in sync
+    pub fn index_document(&self, document: &KnowledgeDocument) {
+        let current_model = document.embedding_model.as_deref() == Some(self.embedder().model());
+        if matches!(self.backend, RetrievalBackend::Memory) && current_model {
+            self.index.upsert(document);
+        }
+        if self.retrieval.hybrid.lexical_enabled() {
+            self.lexical.upsert(document);
+        }
+    }
+    
+    /// Drop a chunk from the in-memory indexes after it was deleted
+    pub fn unindex_document(&self, id: &str) {
+        if matches!(self.backend, RetrievalBackend::Memory) {
+            self.index.remove(id);
+        }
+        if self.retrieval.hybrid.lexical_enabled() {
+            self.lexical.remove(id);
+        }
+    }
+    
+    /// Progress of the current or last re-embedding job
+    pub fn reembed_progress(&self) -> ReembedProgress {
+        self.reembed.snapshot()
+    }
+    
+    /// Claim the job slot for a migration to `model`; returns the running job if there is one
+    pub fn begin_reembed(&self, model: &str) -> Result<ReembedProgress, ReembedProgress> {
+        self.reembed.try_start(&self.embedding_model(), model)
+    }
+    
+    /// Run a job claimed with `begin_reembed` to the end and record how it went
+    pub async fn run_reembed(&self, model: &str) {
+        let target = Arc::new(self.embedder().with_model(model));
+        let result = self.reembed_into(target).await;
+        match &result {
+            Ok(()) => {
+                tracing::info!("Knowledge base re-embedded with '{}'", model);
+                if let Some(configured) = embeddings::configured_model().filter(|c| c != model) {
+                    tracing::warn!(
+                        "EMBEDDING_MODEL is still '{}'; set it to '{}' or unset it, startup refuses the mismatch",
+                        configured,
+                        model
+                    );
+                }
+            }
+            Err(e) => tracing::error!("Re-embedding with '{}' failed: {}", model, e),
+        }
+        self.reembed.finish(result);
+    }
+    
+    async fn reembed_into(&self, target: Arc<EmbeddingService>) -> Result<(), String> {
+        let model = target.model().to_string();
+        let knowledge = self.db.knowledge_collection();
+        
+        // Stage new vectors next to the live ones, so retrieval keeps working meanwhile
+        let pending = doc! { "embedding_model": { "$ne": &model }, "next_embedding.model": { "$ne": &model } };
+        let total = knowledge
+            .count_documents(pending.clone())
+            .await
+            .map_err(|e| format!("Failed to count chunks: {}", e))?;
+        self.reembed.add_total(total);
+        let mut dimensions_seen = None;
+        loop {
+            let batch = self.chunk_contents(pending.clone(), target.batch_size()).await?;
+            if batch.is_empty() {
+                break;
+            }
+            let vectors = self.embed_contents(&target, &batch).await?;
+            for (chunk, vector) in batch.iter().zip(vectors) {
+                dimensions_seen = Some(vector.len());
+                let staged = doc! { "model": &model, "dim": vector.len() as i32, "vector": vector };
+                knowledge
+                    .update_one(doc! { "_id": &chunk.id }, doc! { "$set": { "next_embedding": staged } })
+                    .await
+                    .map_err(|e| format!("Failed to store embedding: {}", e))?;
+            }
+            self.reembed.advance(batch.len() as u64);
+        }
+        
+        // Swap the staged vectors in, then switch the model used for queries and new chunks
+        knowledge
+            .update_many(
+                doc! { "next_embedding.model": &model },
+                vec![
+                    doc! { "$set": {
+                        "embedding": "$next_embedding.vector",
+                        "embedding_model": "$next_embedding.model",
+                        "embedding_dim": "$next_embedding.dim",
+                    } },
+                    doc! { "$unset": "next_embedding" },
+                ],
+            )
+            .await
+            .map_err(|e| format!("Failed to swap embeddings: {}", e))?;
+        // Recorded so a restart keeps querying with the model the chunks now use
+        self.db
+            .set_active_embedding_model(&model)
+            .await
+            .map_err(|e| format!("Failed to record the active embedding model: {}", e))?;
+        *self.embedding_service.write().unwrap_or_else(|e| e.into_inner()) = target.clone();
+        
+        // Chunks ingested with the previous model while the job ran
+        let stale = doc! { "embedding_model": { "$ne": &model } };
+        loop {
+            let batch = self.chunk_contents(stale.clone(), target.batch_size()).await?;
+            if batch.is_empty() {
+                break;
+            }
+            self.reembed.add_total(batch.len() as u64);
+            let vectors = self.embed_contents(&target, &batch).await?;
+            for (chunk, vector) in batch.iter().zip(vectors) {
+                dimensions_seen = Some(vector.len());
+                let update = doc! { "$set": {
+                    "embedding_model": &model,
+                    "embedding_dim": vector.len() as i32,
+                    "embedding": vector,
+                } };
+                knowledge
+                    .update_one(doc! { "_id": &chunk.id }, update)
+                    .await
+                    .map_err(|e| format!("Failed to store embedding: {}", e))?;
+            }
+            self.reembed.advance(batch.len() as u64);
+        }
+        
+        match &self.backend {
+            RetrievalBackend::Memory => {
+                let count = self.index.reload(&self.db, &model).await?;
+                tracing::info!("Vector index reloaded with {} documents", count);
+            }
+            RetrievalBackend::Atlas { index_name, dimensions, .. } => {
+                if let Some(dim) = dimensions_seen.filter(|&d| d != *dimensions as usize) {
+                    tracing::warn!(
+                        "'{}' produces {} dimensions but the Atlas index '{}' expects {}; recreate the index with EMBEDDING_DIMENSIONS={}",
+                        model,
+                        dim,
+                        index_name,
+                        dimensions,
+                        dim
+                    );
+                }
+            }
+            RetrievalBackend::Scan => {}
+        }
+        Ok(())
+    }
+    
+    /// Up to `limit` chunks matching `filter`, text only
+    async fn chunk_contents(&self, filter: Document, limit: usize) -> Result<Vec<ChunkContent>, String> {
+        self.db
+            .knowledge_collection()
+            .clone_with_type::<ChunkContent>()
+            .find(filter)
+            .projection(doc! { "content": 1 })
+            .limit(limit as i64)
+            .await
+            .map_err(|e| format!("Failed to load chunks: {}", e))?
+            .try_collect()
+            .await
+            .map_err(|e| format!("Failed to load chunks: {}", e))
+    }
+    
+    async fn embed_contents(&self, embedder: &EmbeddingService, chunks: &[ChunkContent]) -> Result<Vec<Vec<f64>>, String> {
+        let texts: Vec<String> = chunks.iter().map(|c| c.content.clone()).collect();
+        let vectors = embedder
+            .generate_embeddings(&texts)
+            .await
+            .map_err(|e| format!("Failed to generate embedding: {}", e))?;
+        if vectors.len() != chunks.len() {
+            return Err("Embedding API returned the wrong number of embeddings".to_string());
+        }
+        Ok(vectors)
+    }
+    
+    /// Retrieve relevant documents based on query similarity.
+    ///
+    /// `preferred` are the knowledge categories of the chat category, see `categories`.
     pub async fn retrieve_context(
         &self,
         query: &str,
-        top_k: usize,
+        params: &RetrievalParams,
+        preferred: &[&str],
     ) -> Result<Vec<RetrievedDocument>, String> {
         // Generate embedding for the query
-        let query_embedding = self.embedding_service.generate_embedding(query).await?;
+        let embedder = self.embedder();
+        let query_embedding = embedder.generate_embedding(query).await?;
+        
+        // Strict mode filters in the backend; boost mode over-fetches and re-ranks below
+        let (filter, boost) = match self.category_mode {
+            CategoryMode::Strict => (preferred.iter().map(|c| c.to_string()).collect(), 0.0),
+            CategoryMode::Boost { weight } if !preferred.is_empty() => (Vec::new(), weight),
+            _ => (Vec::new(), 0.0),
+        };
+        let pool = params.candidate_pool.max(params.top_k);
+        let candidates = self.search(&query_embedding, embedder.model(), pool, &filter).await?;
+        
+        // Threshold the whole pool before anything is cut, so weak chunks never take a slot
+        let mut ranked: Vec<(RetrievedDocument, f64)> = candidates
+            .into_iter()
+            .filter(|doc| doc.similarity >= params.min_similarity)
+            .map(|doc| {
+                let score = doc.similarity + if preferred.contains(&doc.category.as_str()) { boost } else { 0.0 };
+                (doc, score)
+            })
+            .collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
         
-        // Fetch all documents (for local similarity search)
-        // Note: For production with MongoDB Atlas, use $vectorSearch aggregation
-        let collection = self.db.knowledge_collection();
-        let cursor = collection
-            .find(mongodb::bson::doc! {})
+        if self.retrieval.hybrid.lexical_enabled() {
+            // Weak term matches are dropped the same way, so they cannot add chunks of their own
+            let min_score = self.retrieval.hybrid.min_bm25_score;
+            let lexical = self
+                .lexical
+                .search(query, pool, &filter)
+                .into_iter()
+                .filter(|doc| doc.lexical_score.is_some_and(|score| score >= min_score))
+                .collect();
+            let vector = ranked.into_iter().map(|(doc, _)| doc).collect();
+            ranked = reciprocal_rank_fusion(vector, lexical, self.retrieval.hybrid);
+        }
+        let results = maximal_marginal_relevance(ranked, params.top_k, self.retrieval.mmr.lambda);
+        
+        tracing::debug!("Retrieved {} relevant documents for query", results.len());
+        Ok(results)
+    }
+    
+    /// Nearest chunks from the configured backend, optionally restricted to `categories`.
+    ///
+    /// Only chunks embedded with `model` are considered; vectors from different models are not comparable.
+    async fn search(
+        &self,
+        embedding: &[f64],
+        model: &str,
+        limit: usize,
+        categories: &[String],
+    ) -> Result<Vec<RetrievedDocument>, String> {
+        match &self.backend {
+            RetrievalBackend::Memory => Ok(self.search_index(embedding, model, limit, categories)),
+            RetrievalBackend::Atlas { index_name, num_candidates, .. } => {
+                match self
+                    .vector_search(index_name, *num_candidates, embedding, model, limit, categories)
+                    .await
+                {
+                    Ok(results) => Ok(results),
+                    Err(e) => {
+                        tracing::warn!("$vectorSearch failed, falling back to a local scan: {}", e);
+                        self.scan(embedding, model, limit, categories).await
+                    }
+                }
+            }
+            RetrievalBackend::Scan => self.scan(embedding, model, limit, categories).await,
+        }
+    }
+    
+    fn search_index(&self, embedding: &[f64], model: &str, limit: usize, categories: &[String]) -> Vec<RetrievedDocument> {
+        // Over-fetch when filtering, the index itself does not know about categories
+        let fetch = if categories.is_empty() { limit } else { limit * 10 };
+        self.index
+            .search(embedding, fetch)
+            .into_iter()
+            .filter(|(doc, _, _)| doc.embedding_model.as_deref() == Some(model))
+            .filter(|(doc, _, _)| categories.is_empty() || categories.contains(&doc.category))
+            .take(limit)
+            .map(|(doc, similarity, vector)| RetrievedDocument {
+                parent_id: doc.parent_id,
+                chunk_index: doc.chunk_index,
+                content: doc.content,
+                title: doc.title,
+                category: doc.category,
+                similarity,
+                lexical_score: None,
+                embedding: Some(vector),
+            })
+            .collect()
+    }
+    
+    /// Run a `$vectorSearch` aggregation on Atlas
+    async fn vector_search(
+        &self,
+        index_name: &str,
+        num_candidates: u32,
+        embedding: &[f64],
+        model: &str,
+        limit: usize,
+        categories: &[String],
+    ) -> Result<Vec<RetrievedDocument>, String> {
+        let mut stage = doc! {
+            "index": index_name,
+            "path": "embedding",
+            "queryVector": embedding,
+            // Atlas requires numCandidates >= limit
+            "numCandidates": i64::from(num_candidates).max(limit as i64),
+            "limit": limit as i64,
+        };
+        if !categories.is_empty() {
+            stage.insert("filter", doc! { "category": { "$in": categories } });
+        }
+        let pipeline = vec![
+            doc! { "$vectorSearch": stage },
+            doc! { "$project": {
+                "parent_id": 1,
+                "chunk_index": 1,
+                "content": 1,
+                "title": 1,
+                "category": 1,
+                "embedding": 1,
+                "embedding_model": 1,
+                "score": { "$meta": "vectorSearchScore" },
+            } },
+        ];
+        
+        let cursor = self
+            .db
+            .knowledge_collection()
+            .clone_with_type::<Document>()
+            .aggregate(pipeline)
+            .await
+            .map_err(|e| format!("Failed to run $vectorSearch: {}", e))?;
+        let hits: Vec<Document> = cursor
+            .try_collect()
+            .await
+            .map_err(|e| format!("Failed to collect $vectorSearch results: {}", e))?;
+        
+        let mut results = Vec::with_capacity(hits.len());
+        for hit in hits {
+            let hit: VectorSearchHit = mongodb::bson::from_document(hit)
+                .map_err(|e| format!("Unexpected $vectorSearch result: {}", e))?;
+            // The Atlas index has no model filter, so chunks of another model are dropped here
+            if hit.embedding_model.as_deref() != Some(model) {
+                continue;
+            }
+            results.push(RetrievedDocument {
+                parent_id: hit.parent_id.unwrap_or(hit.id),
+                chunk_index: hit.chunk_index,
+                content: hit.content,
+                title: hit.title,
+                category: hit.category,
+                // Atlas reports cosine as (1 + cos) / 2; convert back so thresholds mean the same everywhere
+                similarity: hit.score * 2.0 - 1.0,
+                lexical_score: None,
+                embedding: hit.embedding,
+            });
+        }
+        Ok(results)
+    }
+    
+    /// Score every document in process
+    async fn scan(
+        &self,
+        embedding: &[f64],
+        model: &str,
+        limit: usize,
+        categories: &[String],
+    ) -> Result<Vec<RetrievedDocument>, String> {
+        let mut filter = doc! { "embedding_model": model };
+        if !categories.is_empty() {
+            filter.insert("category", doc! { "$in": categories });
+        }
+        let cursor = self
+            .db
+            .knowledge_collection()
+            .find(filter)
             .await
             .map_err(|e| format!("Failed to query documents: {}", e))?;
         
@@ -49,7 +1204,7 @@ impl RagService {
         let mut scored_docs: Vec<(KnowledgeDocument, f64)> = documents
             .into_iter()
             .map(|doc| {
-                let similarity = cosine_similarity(&query_embedding, &doc.embedding);
+                let similarity = cosine_similarity(embedding, &doc.embedding);
                 (doc, similarity)
             })
             .collect();
@@ -57,22 +1212,20 @@ impl RagService {
         // Sort by similarity descending
         scored_docs.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
         
-        // Take top K results with minimum similarity threshold
-        let min_similarity = 0.3;
-        let results: Vec<RetrievedDocument> = scored_docs
+        Ok(scored_docs
             .into_iter()
-            .take(top_k)
-            .filter(|(_, sim)| *sim >= min_similarity)
+            .take(limit)
             .map(|(doc, similarity)| RetrievedDocument {
+                parent_id: doc.parent_id.unwrap_or_else(|| doc.id.clone()),
+                chunk_index: doc.chunk_index,
                 content: doc.content,
                 title: doc.title,
                 category: doc.category,
                 similarity,
+                lexical_score: None,
+                embedding: Some(doc.embedding),
             })
-            .collect();
-        
-        tracing::debug!("Retrieved {} relevant documents for query", results.len());
-        Ok(results)
+            .collect())
     }
     
     /// Augment the system prompt with retrieved context
@@ -81,16 +1234,18 @@ impl RagService {
             return base_prompt.to_string();
         }
         
-        let context_text: String = context
+        // Chunks of the same article are shown together, in article order
+        let context_text: String = group_by_parent(context)
             .iter()
             .enumerate()
-            .map(|(i, doc)| {
+            .map(|(i, chunks)| {
+                let excerpts: Vec<&str> = chunks.iter().map(|c| c.content.as_str()).collect();
                 format!(
                     "---\nDocument {} ({}): {}\n{}\n",
                     i + 1,
-                    doc.category,
-                    doc.title,
-                    doc.content
+                    chunks[0].category,
+                    chunks[0].title,
+                    excerpts.join("\n[...]\n")
                 )
             })
             .collect();
@@ -102,3 +1257,260 @@ impl RagService {
         )
     }
 }
+
+/// Pair chunk texts with their embeddings
+fn build_chunks(
+    parent_id: &str,
+    title: &str,
+    category: &str,
+    pieces: Vec<String>,
+    vectors: Vec<Vec<f64>>,
+    model: &str,
+) -> Vec<KnowledgeDocument> {
+    pieces
+        .into_iter()
+        .zip(vectors)
+        .enumerate()
+        .map(|(index, (piece, embedding))| KnowledgeDocument {
+            id: Uuid::new_v4().to_string(),
+            content: piece,
+            title: title.to_string(),
+            category: category.to_string(),
+            embedding_dim: embedding.len() as u32,
+            embedding_model: Some(model.to_string()),
+            embedding,
+            created_at: Utc::now(),
+            parent_id: Some(parent_id.to_string()),
+            chunk_index: index as u32,
+        })
+        .collect()
+}
+
+/// Merge the vector and BM25 rankings: each list adds `weight / (k + rank)` to a chunk's score.
+///
+/// Chunks found by both keep their cosine similarity and gain the BM25 score.
+/// Returns chunks with their fused score, best first.
+fn reciprocal_rank_fusion(
+    vector: Vec<RetrievedDocument>,
+    lexical: Vec<RetrievedDocument>,
+    config: HybridConfig,
+) -> Vec<(RetrievedDocument, f64)> {
+    let mut fused: Vec<(RetrievedDocument, f64)> = Vec::with_capacity(vector.len() + lexical.len());
+    for (rank, doc) in vector.into_iter().enumerate() {
+        fused.push((doc, config.vector_weight / (config.rrf_k + rank as f64 + 1.0)));
+    }
+    for (rank, doc) in lexical.into_iter().enumerate() {
+        let score = config.bm25_weight / (config.rrf_k + rank as f64 + 1.0);
+        match fused
+            .iter_mut()
+            .find(|(d, _)| d.parent_id == doc.parent_id && d.chunk_index == doc.chunk_index)
+        {
+            Some((existing, total)) => {
+                existing.lexical_score = doc.lexical_score;
+                *total += score;
+            }
+            None => fused.push((doc, score)),
+        }
+    }
+    // Stable sort, so ties keep the vector order
+    fused.sort_by(|a, b| b.1.total_cmp(&a.1));
+    fused
+}
+
+/// Pick `k` chunks from `ranked` (best first) by maximal marginal relevance: each step takes the chunk
+/// maximizing `lambda * relevance - (1 - lambda) * redundancy` with the chunks already picked.
+///
+/// Relevance is the ranking score scaled to 0..1. Redundancy is the cosine similarity of the
+/// embeddings, or the word overlap for chunks found only by BM25.
+fn maximal_marginal_relevance(ranked: Vec<(RetrievedDocument, f64)>, k: usize, lambda: f64) -> Vec<RetrievedDocument> {
+    if lambda >= 1.0 || ranked.len() <= 1 {
+        return ranked.into_iter().take(k).map(|(doc, _)| doc).collect();
+    }
+    
+    let best = ranked[0].1;
+    let worst = ranked[ranked.len() - 1].1;
+    let relevance: Vec<f64> = ranked
+        .iter()
+        .map(|(_, score)| if best > worst { (score - worst) / (best - worst) } else { 1.0 })
+        .collect();
+    let terms: Vec<HashSet<String>> = ranked
+        .iter()
+        .map(|(doc, _)| bm25::tokenize(&doc.content).into_iter().collect())
+        .collect();
+    let redundancy = |a: usize, b: usize| match (&ranked[a].0.embedding, &ranked[b].0.embedding) {
+        (Some(x), Some(y)) => cosine_similarity(x, y),
+        _ => {
+            let union = terms[a].union(&terms[b]).count();
+            if union == 0 {
+                0.0
+            } else {
+                terms[a].intersection(&terms[b]).count() as f64 / union as f64
+            }
+        }
+    };
+    
+    let mut selected: Vec<usize> = Vec::with_capacity(k);
+    let mut remaining: Vec<usize> = (0..ranked.len()).collect();
+    while selected.len() < k && !remaining.is_empty() {
+        let mmr = |i: usize| {
+            let max_redundancy = selected.iter().map(|&j| redundancy(i, j)).fold(0.0, f64::max);
+            lambda * relevance[i] - (1.0 - lambda) * max_redundancy
+        };
+        let (position, _) = remaining
+            .iter()
+            .enumerate()
+            .map(|(position, &i)| (position, mmr(i)))
+            .fold((0, f64::MIN), |best, candidate| if candidate.1 > best.1 { candidate } else { best });
+        selected.push(remaining.remove(position));
+    }
+    
+    let mut ranked: Vec<Option<RetrievedDocument>> = ranked.into_iter().map(|(doc, _)| Some(doc)).collect();
+    selected.into_iter().filter_map(|i| ranked[i].take()).collect()
+}
+
+/// Group chunks by article, keeping the order in which articles were first retrieved
+pub fn group_by_parent(context: &[RetrievedDocument]) -> Vec<Vec<&RetrievedDocument>> {
+    let mut groups: Vec<Vec<&RetrievedDocument>> = Vec::new();
+    for doc in context {
+        match groups.iter_mut().find(|g| g[0].parent_id == doc.parent_id) {
+            Some(group) => group.push(doc),
+            None => groups.push(vec![doc]),
+        }
+    }
+    for group in &mut groups {
+        group.sort_by_key(|doc| doc.chunk_index);
+    }
+    groups
+}
+
+/// One source title per retrieved article
+pub fn source_titles(context: &[RetrievedDocument]) -> Vec<String> {
+    group_by_parent(context)
+        .iter()
+        .map(|chunks| chunks[0].title.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{CircuitBreaker, ResilientClient, RetryPolicy};
+    use std::time::Duration;
+
+    /// Service whose MongoDB and embeddings API are both unreachable
+    async fn offline_service() -> RagService {
+        let db = AppDatabase::connect("mongodb://127.0.0.1:1/?serverSelectionTimeoutMS=100", "test")
+            .await
+            .expect("client builds");
+        let http = ResilientClient::new(
+            RetryPolicy {
+                timeout: Duration::from_millis(1),
+                max_retries: 0,
+                base_delay: Duration::ZERO,
+                max_delay: Duration::ZERO,
+            },
+            Arc::new(CircuitBreaker::new("embeddings", 0, Duration::ZERO)),
+        );
+        RagService::new(
+            db,
+            EmbeddingService::new(String::new(), http).with_model("m1"),
+            RetrievalBackend::Memory,
+            ChunkerConfig::from_env(),
+            CategoryMode::Off,
+            RetrievalConfig::from_env().expect("default retrieval settings"),
+            0.95,
+        )
+    }
+
+    #[tokio::test]
+    async fn a_failed_reembed_keeps_the_current_model() {
+        let rag = offline_service().await;
+        rag.begin_reembed("m2").unwrap();
+        assert!(rag.begin_reembed("m3").is_err());
+
+        rag.run_reembed("m2").await;
+        let progress = rag.reembed_progress();
+        assert_eq!(progress.state, crate::reembed::JobState::Failed);
+        assert!(progress.error.is_some_and(|e| e.contains("Failed to count chunks")));
+        assert_eq!(rag.embedding_model(), "m1");
+    }
+
+    fn retrieved(parent: &str, embedding: Option<Vec<f64>>) -> RetrievedDocument {
+        RetrievedDocument {
+            parent_id: parent.to_string(),
+            chunk_index: 0,
+            content: format!("isi artikel {}", parent),
+            title: parent.to_string(),
+            category: "general".to_string(),
+            similarity: 0.0,
+            lexical_score: None,
+            embedding,
+        }
+    }
+
+    fn ids(docs: &[RetrievedDocument]) -> Vec<&str> {
+        docs.iter().map(|d| d.parent_id.as_str()).collect()
+    }
+
+    #[test]
+    fn rrf_rewards_chunks_found_by_both_rankings() {
+        let mut lexical_b = retrieved("b", None);
+        lexical_b.lexical_score = Some(3.5);
+        let fused = reciprocal_rank_fusion(
+            vec![retrieved("a", None), retrieved("b", None)],
+            vec![lexical_b, retrieved("c", None)],
+            HybridConfig::default(),
+        );
+
+        let order: Vec<&str> = fused.iter().map(|(d, _)| d.parent_id.as_str()).collect();
+        assert_eq!(order, vec!["b", "a", "c"]);
+        assert!((fused[0].1 - (1.0 / 62.0 + 1.0 / 61.0)).abs() < 1e-12);
+        assert_eq!(fused[0].0.lexical_score, Some(3.5));
+    }
+
+    #[test]
+    fn rrf_weights_scale_each_ranking() {
+        let config = HybridConfig {
+            vector_weight: 0.0,
+            ..HybridConfig::default()
+        };
+        let fused = reciprocal_rank_fusion(vec![retrieved("a", None)], vec![retrieved("c", None)], config);
+        let order: Vec<&str> = fused.iter().map(|(d, _)| d.parent_id.as_str()).collect();
+        assert_eq!(order, vec!["c", "a"]);
+    }
+
+    #[test]
+    fn mmr_skips_near_duplicates() {
+        let ranked = vec![
+            (retrieved("a", Some(vec![1.0, 0.0])), 1.0),
+            (retrieved("a-copy", Some(vec![1.0, 0.0])), 0.9),
+            (retrieved("c", Some(vec![0.0, 1.0])), 0.5),
+        ];
+        assert_eq!(ids(&maximal_marginal_relevance(ranked.clone(), 2, 0.5)), vec!["a", "c"]);
+        // Lambda 1 is plain relevance order
+        assert_eq!(ids(&maximal_marginal_relevance(ranked, 2, 1.0)), vec!["a", "a-copy"]);
+    }
+
+    #[test]
+    fn mmr_uses_word_overlap_without_embeddings() {
+        let with_content = |parent: &str, content: &str| RetrievedDocument {
+            content: content.to_string(),
+            ..retrieved(parent, None)
+        };
+        let ranked = vec![
+            (with_content("a", "latihan napas dalam"), 1.0),
+            (with_content("a-copy", "latihan napas dalam"), 0.9),
+            (with_content("z", "jadwal tidur teratur"), 0.5),
+        ];
+        assert_eq!(ids(&maximal_marginal_relevance(ranked, 2, 0.5)), vec!["a", "z"]);
+    }
+
+    #[test]
+    fn sources_list_each_article_once() {
+        let mut second_chunk = retrieved("a", None);
+        second_chunk.chunk_index = 1;
+        let context = vec![retrieved("b", None), second_chunk, retrieved("a", None)];
+        assert_eq!(source_titles(&context), vec!["b", "a"]);
+        assert_eq!(group_by_parent(&context)[1][0].chunk_index, 0);
+    }
+}
@@ -0,0 +1,334 @@
+//! Request throttling.
+//!
+//! Every client gets a token bucket per budget (chat and ingest are separate), keyed
+//! by its API key once the key is verified or, otherwise, by its IP address, so made-up
+//! keys cannot buy fresh buckets. On top of that a global
+//! semaphore caps how many upstream LLM calls run at once, so a burst from many
+//! clients queues briefly instead of exhausting the provider budget.
+//!
+//! Crisis messages are exempt from the chat budget so they always reach the hotlines.
+
+use crate::{auth, crisis, AppState, ErrorResponse};
+use axum::body::Body;
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::Response;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Most buckets kept per limiter; beyond it fully refilled buckets are dropped first,
+/// then the least recently used ones
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// Largest chat body read to look for a crisis phrase once the budget is spent
+/// (axum's default request body limit)
+const CHAT_BODY_LIMIT: usize = 2 * 1024 * 1024;
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token bucket limiter with one bucket per client
+pub struct TokenBucketLimiter {
+    /// Largest burst a client can send at once
+    capacity: f64,
+    /// Tokens added per second; zero disables the limiter
+    refill_per_sec: f64,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl TokenBucketLimiter {
+    pub fn new(per_minute: u32, burst: u32) -> Self {
+        Self {
+            capacity: f64::from(burst.max(1)),
+            refill_per_sec: f64::from(per_minute) / 60.0,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Read `<PREFIX>_PER_MINUTE` and `<PREFIX>_BURST`, falling back to the given defaults
+    pub fn from_env(prefix: &str, per_minute: u32, burst: u32) -> Self {
+        let read = |suffix: &str, default: u32| {
+            std::env::var(format!("{}_{}", prefix, suffix))
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        };
+        Self::new(read("PER_MINUTE", per_minute), read("BURST", burst))
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.refill_per_sec > 0.0
+    }
+
+    /// Take one token for `client`, or return how long until one is available
+    pub fn try_acquire(&self, client: &str) -> Result<(), Duration> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if buckets.len() >= MAX_TRACKED_CLIENTS && !buckets.contains_key(client) {
+            let (capacity, rate) = (self.capacity, self.refill_per_sec);
+            buckets.retain(|_, b| b.tokens + now.duration_since(b.updated).as_secs_f64() * rate < capacity);
+            while buckets.len() >= MAX_TRACKED_CLIENTS {
+                let Some(oldest) = buckets.iter().min_by_key(|(_, b)| b.updated).map(|(k, _)| k.clone()) else {
+                    break;
+                };
+                buckets.remove(&oldest);
+            }
+        }
+
+        let bucket = buckets.entry(client.to_string()).or_insert(Bucket {
+            tokens: self.capacity,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.refill_per_sec))
+        }
+    }
+}
+
+/// Per-client budgets
+pub struct RateLimits {
+    pub chat: TokenBucketLimiter,
+    pub ingest: TokenBucketLimiter,
+    /// Take the client IP from `X-Forwarded-For` / `X-Real-IP` (set by the bundled nginx);
+    /// off unless `TRUST_PROXY_HEADERS` is set, since direct callers can forge them
+    pub trust_proxy_headers: bool,
+}
+
+impl RateLimits {
+    pub fn from_env() -> Self {
+        Self {
+            chat: TokenBucketLimiter::from_env("RATE_LIMIT_CHAT", 20, 5),
+            ingest: TokenBucketLimiter::from_env("RATE_LIMIT_INGEST", 60, 20),
+            trust_proxy_headers: std::env::var("TRUST_PROXY_HEADERS")
+                .map(|v| v == "true" || v == "1")
+                .unwrap_or(false),
+        }
+    }
+}
+
+/// Caps concurrent upstream LLM calls across all clients
+pub struct UpstreamLimiter {
+    permits: Arc<Semaphore>,
+    /// How long a request may wait for a free slot before giving up
+    queue_timeout: Duration,
+}
+
+impl UpstreamLimiter {
+    pub fn new(max_concurrent: usize, queue_timeout: Duration) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(max_concurrent.max(1))),
+            queue_timeout,
+        }
+    }
+
+    /// Read `LLM_MAX_CONCURRENCY` (default 8) and `LLM_QUEUE_TIMEOUT_SECS` (default 10)
+    pub fn from_env() -> Self {
+        let max_concurrent = std::env::var("LLM_MAX_CONCURRENCY")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(8);
+        let queue_timeout = std::env::var("LLM_QUEUE_TIMEOUT_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(10);
+        Self::new(max_concurrent, Duration::from_secs(queue_timeout))
+    }
+
+    /// Wait for a slot; the permit is released when dropped.
+    ///
+    /// On timeout returns the delay to suggest in `Retry-After`.
+    pub async fn acquire(&self) -> Result<OwnedSemaphorePermit, Duration> {
+        match tokio::time::timeout(self.queue_timeout, self.permits.clone().acquire_owned()).await {
+            Ok(Ok(permit)) => Ok(permit),
+            _ => Err(self.queue_timeout.max(Duration::from_secs(1))),
+        }
+    }
+}
+
+/// Identify an unauthenticated caller by its IP address
+fn client_ip(headers: &HeaderMap, peer: Option<SocketAddr>, trust_proxy_headers: bool) -> String {
+    let forwarded = trust_proxy_headers
+        .then(|| {
+            // nginx appends the address it saw, so the last entry is the one we can trust
+            headers
+                .get("x-forwarded-for")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.rsplit(',').next())
+                .or_else(|| headers.get("x-real-ip").and_then(|v| v.to_str().ok()))
+                .map(|ip| ip.trim().to_string())
+                .filter(|ip| !ip.is_empty())
+        })
+        .flatten();
+
+    match forwarded.or_else(|| peer.map(|addr| addr.ip().to_string())) {
+        Some(ip) => format!("ip:{}", ip),
+        None => "ip:unknown".to_string(),
+    }
+}
+
+/// Identify the caller: the id of its API key when the key is valid, otherwise its IP.
+///
+/// A verified key is stored in the request so the `Authorized` extractor does not look
+/// it up again. Invalid keys fall back to the IP and are rejected by the handler.
+async fn client_key(state: &AppState, request: &mut Request) -> String {
+    if state.config.auth_enabled {
+        let presented = auth::presented_key(request.headers())
+            .filter(|k| !k.is_empty())
+            .map(str::to_string);
+        if let Some(key) = presented {
+            match auth::lookup(state, &key).await {
+                Ok(Some(record)) => {
+                    let client = format!("key:{}", record.id);
+                    request.extensions_mut().insert(auth::VerifiedKey(record));
+                    return client;
+                }
+                Ok(None) => {}
+                Err(e) => tracing::warn!("Failed to look up API key for rate limiting: {}", e),
+            }
+        }
+    }
+
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| *addr);
+    client_ip(request.headers(), peer, state.rate_limits.trust_proxy_headers)
+}
+
+/// 429 response with a `Retry-After` header in whole seconds
+pub fn too_many_requests(retry_after: Duration, message: &str) -> Response {
+    let mut response = ErrorResponse::respond(StatusCode::TOO_MANY_REQUESTS, message);
+    let seconds = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+    response
+}
+
+async fn enforce(state: &AppState, limiter: &TokenBucketLimiter, mut request: Request, next: Next) -> Response {
+    let client = client_key(state, &mut request).await;
+    match limiter.try_acquire(&client) {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => {
+            tracing::warn!("Rate limit exceeded for {} on {}", client, request.uri().path());
+            too_many_requests(retry_after, "Too many requests, please slow down")
+        }
+    }
+}
+
+/// Whether a chat request body carries a crisis phrase in its `message`
+fn is_crisis_request(body: &[u8]) -> bool {
+    serde_json::from_slice::<serde_json::Value>(body)
+        .ok()
+        .and_then(|v| v.get("message").and_then(|m| m.as_str()).map(crisis::detect))
+        .flatten()
+        .is_some()
+}
+
+/// Middleware applying the chat budget.
+///
+/// Crisis messages are let through once the budget is spent, so they still get the
+/// hotlines instead of a 429.
+pub async fn limit_chat(State(state): State<Arc<AppState>>, mut request: Request, next: Next) -> Response {
+    let client = client_key(&state, &mut request).await;
+    let Err(retry_after) = state.rate_limits.chat.try_acquire(&client) else {
+        return next.run(request).await;
+    };
+
+    let (parts, body) = request.into_parts();
+    match axum::body::to_bytes(body, CHAT_BODY_LIMIT).await {
+        Ok(body) if is_crisis_request(&body) => {
+            tracing::warn!("Rate limit exceeded for {} but the message is a crisis, letting it through", client);
+            next.run(Request::from_parts(parts, Body::from(body))).await
+        }
+        _ => {
+            tracing::warn!("Rate limit exceeded for {} on {}", client, parts.uri.path());
+            too_many_requests(retry_after, "Too many requests, please slow down")
+        }
+    }
+}
+
+/// Middleware applying the ingest budget
+pub async fn limit_ingest(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    enforce(&state, &state.rate_limits.ingest, request, next).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn burst_is_allowed_then_throttled_per_client() {
+        let limiter = TokenBucketLimiter::new(60, 3);
+        for _ in 0..3 {
+            assert!(limiter.try_acquire("ip:a").is_ok());
+        }
+        let retry_after = limiter.try_acquire("ip:a").unwrap_err();
+        assert!(retry_after > Duration::ZERO && retry_after <= Duration::from_secs(1));
+        // Other clients have their own bucket
+        assert!(limiter.try_acquire("ip:b").is_ok());
+    }
+
+    #[test]
+    fn tokens_refill_over_time() {
+        // 6000 per minute is one token every 10ms
+        let limiter = TokenBucketLimiter::new(6000, 1);
+        assert!(limiter.try_acquire("ip:a").is_ok());
+        assert!(limiter.try_acquire("ip:a").is_err());
+        std::thread::sleep(Duration::from_millis(15));
+        assert!(limiter.try_acquire("ip:a").is_ok());
+    }
+
+    #[test]
+    fn zero_rate_disables_the_limiter() {
+        let limiter = TokenBucketLimiter::new(0, 1);
+        for _ in 0..100 {
+            assert!(limiter.try_acquire("ip:a").is_ok());
+        }
+    }
+
+    #[test]
+    fn tracked_clients_stay_under_the_cap() {
+        let limiter = TokenBucketLimiter::new(1, 5);
+        for i in 0..MAX_TRACKED_CLIENTS + 50 {
+            // Every bucket is partly drained, so none can be dropped as fully refilled
+            limiter.try_acquire(&format!("ip:{}", i)).unwrap();
+        }
+        let buckets = limiter.buckets.lock().unwrap();
+        assert!(buckets.len() <= MAX_TRACKED_CLIENTS);
+        assert!(buckets.contains_key(&format!("ip:{}", MAX_TRACKED_CLIENTS + 49)));
+    }
+
+    #[test]
+    fn crisis_requests_are_recognised_from_the_body() {
+        assert!(is_crisis_request(br#"{"message": "aku pengen bunuh diri"}"#));
+        assert!(!is_crisis_request(br#"{"message": "aku capek kerja"}"#));
+        assert!(!is_crisis_request(b"not json"));
+    }
+
+    #[test]
+    fn client_ip_prefers_the_last_forwarded_address() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", HeaderValue::from_static("10.0.0.1, 203.0.113.7"));
+        let peer = Some(SocketAddr::from(([127, 0, 0, 1], 4000)));
+        assert_eq!(client_ip(&headers, peer, true), "ip:203.0.113.7");
+        assert_eq!(client_ip(&headers, peer, false), "ip:127.0.0.1");
+    }
+}
@@ -0,0 +1,343 @@
+//! PII redaction for outbound calls.
+//!
+//! Phone numbers, NIK numbers, emails, street addresses and personal names are
+//! replaced with placeholders such as `[PHONE_1]` before user text is sent to the
+//! chat provider or the embeddings API. The vault remembers the originals so the
+//! reply shown to the user can optionally be restored.
+
+use regex::Regex;
+use std::sync::LazyLock;
+
+/// Kind of personal data a placeholder stands for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PiiKind {
+    Email,
+    Nik,
+    Phone,
+    Address,
+    Name,
+}
+
+impl PiiKind {
+    fn label(self) -> &'static str {
+        match self {
+            PiiKind::Email => "EMAIL",
+            PiiKind::Nik => "NIK",
+            PiiKind::Phone => "PHONE",
+            PiiKind::Address => "ADDRESS",
+            PiiKind::Name => "NAME",
+        }
+    }
+}
+
+/// A detector and the capture group holding the personal data
+struct Detector {
+    kind: PiiKind,
+    pattern: Regex,
+    group: usize,
+}
+
+/// Words a self-introduction can be followed by that are not names ("my name is not
+/// important", "nama aku gak penting"). Vaulting one would replace it across the whole
+/// conversation and flip the meaning of every negation.
+const NOT_NAMES: &[&str] = &[
+    // Negations
+    "not", "no", "never", "gak", "ga", "gk", "nggak", "ngga", "enggak", "engga", "tidak", "tdk", "tak", "bukan",
+    "belum", "jangan",
+    // Common continuations
+    "a", "the", "just", "really", "very", "kind", "secret", "private", "unknown", "important", "irrelevant",
+    "nothing", "none", "rahasia", "penting", "siapa", "apa", "sih", "juga", "ya", "itu", "ini", "adalah", "udah",
+    "sudah", "masih", "cuma", "hanya", "sangat", "terlalu",
+];
+
+/// Whether a name match is an actual name rather than a word from `NOT_NAMES`
+fn is_plausible_name(candidate: &str) -> bool {
+    candidate
+        .split_whitespace()
+        .next()
+        .is_some_and(|first| !NOT_NAMES.contains(&first.to_lowercase().as_str()))
+}
+
+/// Detectors run in order; earlier kinds win when matches overlap (a NIK is never read as a phone)
+static DETECTORS: LazyLock<Vec<Detector>> = LazyLock::new(|| {
+    vec![
+        Detector {
+            kind: PiiKind::Email,
+            pattern: Regex::new(r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}").unwrap(),
+            group: 0,
+        },
+        Detector {
+            kind: PiiKind::Nik,
+            pattern: Regex::new(r"\b\d{6}[ .-]?\d{6}[ .-]?\d{4}\b").unwrap(),
+            group: 0,
+        },
+        Detector {
+            kind: PiiKind::Phone,
+            // Mobile numbers (08xx / +628xx / 628xx) and landlines with an area code
+            pattern: Regex::new(
+                r"(?:\+62[ .-]?|\b62|\b0)8\d{1,2}[ .-]?\d{3,4}[ .-]?\d{3,5}\b|(?:\+62[ .-]?|\b0)\(?\d{2,3}\)?[ .-]\d{3,4}[ .-]?\d{3,5}\b",
+            )
+            .unwrap(),
+            group: 0,
+        },
+        Detector {
+            kind: PiiKind::Address,
+            // A street keyword followed by capitalized words and an optional house number
+            pattern: Regex::new(
+                r"\b(?:[Jj]l\.?|[Jj]ln\.?|[Jj]alan|[Gg]g\.|[Gg]ang|[Kk]omplek|[Pp]erumahan|[Pp]erum\.)\s+[A-Z0-9][\w'-]*(?:\s+(?:(?:[Nn]o|[Nn]omor)\.?\s*\d+[A-Za-z]?|[A-Z0-9][\w'-]*))*(?:,?\s*RT\.?\s*\d{1,3}\s*/\s*RW\.?\s*\d{1,3})?|\bRT\.?\s*\d{1,3}\s*/\s*RW\.?\s*\d{1,3}\b",
+            )
+            .unwrap(),
+            group: 0,
+        },
+        Detector {
+            kind: PiiKind::Name,
+            // Explicit self-introductions, where the name itself may be lowercase
+            pattern: Regex::new(
+                r"(?i:\b(?:nama\s+(?:saya|aku|gue|gw|ku)(?:\s+adalah)?|namaku|my\s+name\s+is)\s+)([A-Za-z][a-z]+(?:\s+[A-Z][a-z]+){0,2})",
+            )
+            .unwrap(),
+            group: 1,
+        },
+        Detector {
+            kind: PiiKind::Name,
+            // Looser introductions only count when the name is capitalized
+            pattern: Regex::new(
+                r"(?i:\b(?:panggil\s+(?:saya|aku|gue|gw)|call\s+me|i'm\s+called)\s+)([A-Z][a-z]+(?:\s+[A-Z][a-z]+)?)",
+            )
+            .unwrap(),
+            group: 1,
+        },
+        Detector {
+            kind: PiiKind::Name,
+            // Honorifics followed by a capitalized name ("Bu Ani", "Mas Joko")
+            pattern: Regex::new(
+                r"\b(?:Pak|pak|Bapak|bapak|Bu|bu|Ibu|ibu|Mas|mas|Mbak|mbak|Kak|kak|Bang|bang|Mr\.?|Mrs\.?|Ms\.?)\s+([A-Z][a-z]+(?:\s+[A-Z][a-z]+)?)",
+            )
+            .unwrap(),
+            group: 1,
+        },
+    ]
+});
+
+/// Personal data found in a conversation and the placeholders that replaced it
+#[derive(Debug, Default, Clone)]
+pub struct PiiVault {
+    /// `(placeholder, original)` pairs in the order they were found
+    entries: Vec<(String, String)>,
+}
+
+impl PiiVault {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn placeholder_for(&mut self, kind: PiiKind, original: &str) -> String {
+        if let Some((placeholder, _)) = self.entries.iter().find(|(_, o)| o == original) {
+            return placeholder.clone();
+        }
+        let prefix = format!("[{}_", kind.label());
+        let next = self.entries.iter().filter(|(p, _)| p.starts_with(&prefix)).count() + 1;
+        let placeholder = format!("{}{}]", prefix, next);
+        self.entries.push((placeholder.clone(), original.to_string()));
+        placeholder
+    }
+
+    /// Replace personal data in `text` with placeholders, remembering the originals.
+    ///
+    /// Values already in the vault are replaced wherever they appear, so a name
+    /// introduced in one message is also hidden in the others.
+    pub fn redact(&mut self, text: &str) -> String {
+        let mut spans: Vec<(usize, usize, PiiKind)> = Vec::new();
+        for detector in DETECTORS.iter() {
+            for caps in detector.pattern.captures_iter(text) {
+                let Some(m) = caps.get(detector.group) else {
+                    continue;
+                };
+                if detector.kind == PiiKind::Name && !is_plausible_name(m.as_str()) {
+                    continue;
+                }
+                let overlaps = spans.iter().any(|(s, e, _)| m.start() < *e && *s < m.end());
+                if !overlaps {
+                    spans.push((m.start(), m.end(), detector.kind));
+                }
+            }
+        }
+        spans.sort_by_key(|(start, _, _)| *start);
+
+        let mut redacted = String::with_capacity(text.len());
+        let mut cursor = 0;
+        for (start, end, kind) in spans {
+            redacted.push_str(&text[cursor..start]);
+            redacted.push_str(&self.placeholder_for(kind, &text[start..end]));
+            cursor = end;
+        }
+        redacted.push_str(&text[cursor..]);
+
+        // Hide values that were detected elsewhere in the conversation, longest first
+        let mut known = self.entries.clone();
+        known.sort_by_key(|(_, original)| std::cmp::Reverse(original.len()));
+        for (placeholder, original) in known {
+            let boundary = |c: Option<char>| if c.is_some_and(char::is_alphanumeric) { r"\b" } else { "" };
+            let pattern = format!(
+                "{}{}{}",
+                boundary(original.chars().next()),
+                regex::escape(&original),
+                boundary(original.chars().last())
+            );
+            if let Ok(re) = Regex::new(&pattern) {
+                redacted = re.replace_all(&redacted, placeholder.as_str()).into_owned();
+            }
+        }
+        redacted
+    }
+
+    /// Redact several texts that belong to the same conversation.
+    ///
+    /// Runs twice so a value first seen in a later text is also hidden in the earlier ones.
+    pub fn redact_all(&mut self, texts: &mut [String]) {
+        for _ in 0..2 {
+            for text in texts.iter_mut() {
+                *text = self.redact(text);
+            }
+        }
+    }
+
+    /// Put the original values back into a reply
+    pub fn restore(&self, text: &str) -> String {
+        let mut restored = text.to_string();
+        for (placeholder, original) in &self.entries {
+            restored = restored.replace(placeholder.as_str(), original);
+        }
+        restored
+    }
+
+    /// Incremental restorer for streamed replies
+    pub fn restorer(&self) -> StreamRestorer<'_> {
+        StreamRestorer {
+            vault: self,
+            pending: String::new(),
+        }
+    }
+}
+
+/// Restores placeholders in a stream of deltas, holding back text while a
+/// placeholder may still be split across chunks
+pub struct StreamRestorer<'a> {
+    vault: &'a PiiVault,
+    pending: String,
+}
+
+impl StreamRestorer<'_> {
+    /// Longest placeholder we could be waiting for, e.g. `[ADDRESS_12]`
+    const MAX_PLACEHOLDER_LEN: usize = 16;
+
+    /// Feed a delta and get back the text that is safe to emit
+    pub fn push(&mut self, delta: &str) -> String {
+        self.pending.push_str(delta);
+        let hold_from = match self.pending.rfind('[') {
+            Some(open)
+                if !self.pending[open..].contains(']')
+                    && self.pending.len() - open < Self::MAX_PLACEHOLDER_LEN =>
+            {
+                open
+            }
+            _ => self.pending.len(),
+        };
+        let ready: String = self.pending.drain(..hold_from).collect();
+        self.vault.restore(&ready)
+    }
+
+    /// Emit whatever is still held back at the end of the stream
+    pub fn finish(&mut self) -> String {
+        let rest = std::mem::take(&mut self.pending);
+        self.vault.restore(&rest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn personal_data_is_replaced_and_restored() {
+        let mut vault = PiiVault::new();
+        let text = "Nama saya Budi, email budi@mail.com, hp 0812-3456-7890, tinggal di Jl. Merdeka No. 5";
+        let redacted = vault.redact(text);
+        assert_eq!(
+            redacted,
+            "Nama saya [NAME_1], email [EMAIL_1], hp [PHONE_1], tinggal di [ADDRESS_1]"
+        );
+        assert_eq!(vault.restore(&redacted), text);
+    }
+
+    #[test]
+    fn nik_numbers_are_not_read_as_phones() {
+        let mut vault = PiiVault::new();
+        assert_eq!(vault.redact("NIK saya 3174012345678901"), "NIK saya [NIK_1]");
+    }
+
+    #[test]
+    fn names_are_hidden_across_the_conversation() {
+        let mut vault = PiiVault::new();
+        let mut texts = vec!["Budi lagi sedih".to_string(), "nama aku Budi".to_string()];
+        vault.redact_all(&mut texts);
+        assert_eq!(texts, vec!["[NAME_1] lagi sedih", "nama aku [NAME_1]"]);
+        assert_eq!(vault.len(), 1);
+    }
+
+    #[test]
+    fn lowercase_self_introductions_are_still_names() {
+        let mut vault = PiiVault::new();
+        assert_eq!(vault.redact("nama aku budi, aku capek"), "nama aku [NAME_1], aku capek");
+    }
+
+    #[test]
+    fn negations_after_an_introduction_are_not_names() {
+        let mut vault = PiiVault::new();
+        let mut texts = vec!["nama aku gak penting".to_string(), "aku gak mau cerita".to_string()];
+        vault.redact_all(&mut texts);
+        assert_eq!(texts, vec!["nama aku gak penting", "aku gak mau cerita"]);
+        assert!(vault.is_empty());
+    }
+
+    #[test]
+    fn english_refusals_to_give_a_name_are_not_names() {
+        let mut vault = PiiVault::new();
+        let text = "my name is not important, I do not want to talk about it";
+        assert_eq!(vault.redact(text), text);
+        assert!(vault.is_empty());
+    }
+
+    #[test]
+    fn stream_restorer_handles_placeholders_split_across_deltas() {
+        let mut vault = PiiVault::new();
+        vault.redact("email budi@mail.com");
+        let mut restorer = vault.restorer();
+
+        let mut out = restorer.push("Hai [EMA");
+        assert_eq!(out, "Hai ");
+        out.push_str(&restorer.push("IL_"));
+        out.push_str(&restorer.push("1], apa kabar"));
+        out.push_str(&restorer.finish());
+        assert_eq!(out, "Hai budi@mail.com, apa kabar");
+    }
+
+    #[test]
+    fn stream_restorer_releases_brackets_that_are_not_placeholders() {
+        let vault = PiiVault::new();
+        let mut restorer = vault.restorer();
+        assert_eq!(restorer.push("catatan [penting"), "catatan ");
+        assert_eq!(restorer.push("] ya"), "[penting] ya");
+        assert_eq!(restorer.push("[ini bukan placeholder sama sekali"), "[ini bukan placeholder sama sekali");
+        assert_eq!(restorer.push(" [X"), " ");
+        assert_eq!(restorer.finish(), "[X");
+    }
+}
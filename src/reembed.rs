@@ -0,0 +1,165 @@
+//! Progress of the background job that moves the knowledge base to another embedding model.
+//!
+//! The job itself lives in `RagService::run_reembed`: chunks are re-embedded into a
+//! `next_embedding` field while retrieval keeps using the current vectors, and once every
+//! chunk has one the new vectors replace the old ones, the model is recorded in the
+//! `settings` collection (startup reads it back) and the service switches models.
+//! A failed run can be started again; chunks that already carry a vector for the target
+//! model are skipped.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::sync::Mutex;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    /// No job has run since startup
+    Idle,
+    Running,
+    Completed,
+    Failed,
+}
+
+/// Snapshot of the current or last re-embedding job
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ReembedProgress {
+    pub state: JobState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target_model: Option<String>,
+    /// Chunks that needed a new embedding when the job started
+    pub total: u64,
+    /// Chunks embedded with the target model so far
+    pub processed: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Shared job state; at most one job runs at a time
+pub struct ReembedTracker {
+    progress: Mutex<ReembedProgress>,
+}
+
+impl ReembedTracker {
+    pub fn new() -> Self {
+        Self {
+            progress: Mutex::new(ReembedProgress {
+                state: JobState::Idle,
+                source_model: None,
+                target_model: None,
+                total: 0,
+                processed: 0,
+                started_at: None,
+                finished_at: None,
+                error: None,
+            }),
+        }
+    }
+
+    fn update(&self, f: impl FnOnce(&mut ReembedProgress)) {
+        f(&mut self.progress.lock().unwrap_or_else(|e| e.into_inner()));
+    }
+
+    pub fn snapshot(&self) -> ReembedProgress {
+        self.progress.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Claim the job slot, or return the running job's progress
+    pub fn try_start(&self, source_model: &str, target_model: &str) -> Result<ReembedProgress, ReembedProgress> {
+        let mut progress = self.progress.lock().unwrap_or_else(|e| e.into_inner());
+        if progress.state == JobState::Running {
+            return Err(progress.clone());
+        }
+        *progress = ReembedProgress {
+            state: JobState::Running,
+            source_model: Some(source_model.to_string()),
+            target_model: Some(target_model.to_string()),
+            total: 0,
+            processed: 0,
+            started_at: Some(Utc::now()),
+            finished_at: None,
+            error: None,
+        };
+        Ok(progress.clone())
+    }
+
+    pub fn add_total(&self, chunks: u64) {
+        self.update(|p| p.total += chunks);
+    }
+
+    pub fn advance(&self, chunks: u64) {
+        self.update(|p| p.processed += chunks);
+    }
+
+    /// Record how the job ended
+    pub fn finish(&self, result: Result<(), String>) {
+        self.update(|p| {
+            p.finished_at = Some(Utc::now());
+            match result {
+                Ok(()) => p.state = JobState::Completed,
+                Err(e) => {
+                    p.state = JobState::Failed;
+                    p.error = Some(e);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn progress_counts_chunks_and_completes() {
+        let tracker = ReembedTracker::new();
+        assert_eq!(tracker.snapshot().state, JobState::Idle);
+
+        let started = tracker.try_start("m1", "m2").unwrap();
+        assert_eq!(started.state, JobState::Running);
+        assert_eq!(started.source_model.as_deref(), Some("m1"));
+        assert_eq!(started.target_model.as_deref(), Some("m2"));
+        tracker.add_total(10);
+        tracker.advance(4);
+        tracker.add_total(2);
+        tracker.advance(8);
+        tracker.finish(Ok(()));
+
+        let done = tracker.snapshot();
+        assert_eq!(done.state, JobState::Completed);
+        assert_eq!((done.processed, done.total), (12, 12));
+        assert!(done.finished_at.is_some());
+        assert!(done.error.is_none());
+    }
+
+    #[test]
+    fn only_one_job_runs_at_a_time() {
+        let tracker = ReembedTracker::new();
+        tracker.try_start("m1", "m2").unwrap();
+        tracker.advance(3);
+        let running = tracker.try_start("m1", "m3").unwrap_err();
+        assert_eq!(running.target_model.as_deref(), Some("m2"));
+        assert_eq!(running.processed, 3);
+    }
+
+    #[test]
+    fn a_failed_job_keeps_its_error_and_can_be_restarted() {
+        let tracker = ReembedTracker::new();
+        tracker.try_start("m1", "m2").unwrap();
+        tracker.finish(Err("Embedding API error".to_string()));
+        let failed = tracker.snapshot();
+        assert_eq!(failed.state, JobState::Failed);
+        assert_eq!(failed.error.as_deref(), Some("Embedding API error"));
+
+        let restarted = tracker.try_start("m1", "m2").unwrap();
+        assert_eq!(restarted.processed, 0);
+        assert!(restarted.error.is_none());
+    }
+}
@@ -0,0 +1,446 @@
+//! In-memory approximate nearest neighbour index over knowledge embeddings.
+//!
+//! A small HNSW graph (Malkov & Yashunin) built at startup from the `knowledge`
+//! collection and updated whenever a document is written, so retrieval never has
+//! to pull every embedding out of MongoDB. Vectors are stored unit-normalized as
+//! `f32`, which turns cosine similarity into a plain dot product.
+
+use crate::db::{AppDatabase, KnowledgeDocument};
+use futures::stream::TryStreamExt;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::sync::RwLock;
+
+/// Links per node on the upper layers
+const M: usize = 16;
+/// Links per node on the bottom layer
+const M0: usize = 2 * M;
+/// Candidate list size while inserting
+const EF_CONSTRUCTION: usize = 100;
+/// Minimum candidate list size while searching
+const EF_SEARCH: usize = 64;
+/// Rebuild once this many tombstones have piled up (and they outnumber live nodes)
+const REBUILD_THRESHOLD: usize = 64;
+
+/// Document fields kept next to the vector, enough to build the prompt without MongoDB
+#[derive(Debug, Clone)]
+pub struct IndexedDocument {
+    pub id: String,
+    pub parent_id: String,
+    pub chunk_index: u32,
+    pub title: String,
+    pub category: String,
+    pub content: String,
+    pub embedding_model: Option<String>,
+}
+
+struct Node {
+    document: IndexedDocument,
+    vector: Vec<f32>,
+    /// Neighbour slots per layer, `links[0]` is the bottom layer
+    links: Vec<Vec<usize>>,
+    deleted: bool,
+}
+
+/// Similarity paired with a node slot, ordered by similarity
+#[derive(Clone, Copy, PartialEq)]
+struct Scored(f32, usize);
+
+impl Eq for Scored {}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scored {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0).then(self.1.cmp(&other.1))
+    }
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+/// Unit-length copy of `v`, or `None` for a zero vector
+fn normalize(v: &[f64]) -> Option<Vec<f32>> {
+    let norm = v.iter().map(|x| x * x).sum::<f64>().sqrt();
+    (norm > 0.0).then(|| v.iter().map(|x| (x / norm) as f32).collect())
+}
+
+struct Hnsw {
+    nodes: Vec<Node>,
+    /// Live slot for each document id
+    slots: HashMap<String, usize>,
+    entry: Option<usize>,
+    max_level: usize,
+    dimensions: Option<usize>,
+    deleted: usize,
+    /// SplitMix64 state for level assignment
+    seed: u64,
+}
+
+impl Hnsw {
+    fn new() -> Self {
+        Self {
+            nodes: Vec::new(),
+            slots: HashMap::new(),
+            entry: None,
+            max_level: 0,
+            dimensions: None,
+            deleted: 0,
+            seed: 0x9E37_79B9_7F4A_7C15,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Draw a layer from the usual exponential distribution with `mL = 1 / ln(M)`
+    fn random_level(&mut self) -> usize {
+        self.seed = self.seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.seed;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        let uniform = ((z >> 11) as f64 + 1.0) / (1u64 << 53) as f64;
+        (-uniform.ln() / (M as f64).ln()) as usize
+    }
+
+    /// Best-first search on one layer, returns up to `ef` nodes sorted by similarity
+    fn search_layer(&self, query: &[f32], entry: usize, ef: usize, level: usize) -> Vec<Scored> {
+        let mut visited = HashSet::from([entry]);
+        let first = Scored(dot(query, &self.nodes[entry].vector), entry);
+        let mut candidates = BinaryHeap::from([first]);
+        let mut results = BinaryHeap::from([Reverse(first)]);
+
+        while let Some(Scored(similarity, slot)) = candidates.pop() {
+            let worst = results.peek().map_or(f32::MIN, |Reverse(s)| s.0);
+            if similarity < worst && results.len() >= ef {
+                break;
+            }
+            for &neighbour in &self.nodes[slot].links[level] {
+                if !visited.insert(neighbour) {
+                    continue;
+                }
+                let scored = Scored(dot(query, &self.nodes[neighbour].vector), neighbour);
+                let worst = results.peek().map_or(f32::MIN, |Reverse(s)| s.0);
+                if results.len() < ef || scored.0 > worst {
+                    candidates.push(scored);
+                    results.push(Reverse(scored));
+                    if results.len() > ef {
+                        results.pop();
+                    }
+                }
+            }
+        }
+
+        let mut found: Vec<Scored> = results.into_iter().map(|Reverse(s)| s).collect();
+        found.sort_by(|a, b| b.cmp(a));
+        found
+    }
+
+    /// Walk down from the top layer to `level`, keeping only the closest node
+    fn descend(&self, query: &[f32], mut entry: usize, level: usize) -> usize {
+        for layer in (level..=self.max_level).rev() {
+            entry = self.search_layer(query, entry, 1, layer)[0].1;
+        }
+        entry
+    }
+
+    /// Keep only the `max` closest links of `slot` on `level`
+    fn prune(&mut self, slot: usize, level: usize, max: usize) {
+        let vector = &self.nodes[slot].vector;
+        let mut scored: Vec<Scored> = self.nodes[slot].links[level]
+            .iter()
+            .map(|&n| Scored(dot(vector, &self.nodes[n].vector), n))
+            .collect();
+        scored.sort_by(|a, b| b.cmp(a));
+        scored.truncate(max);
+        self.nodes[slot].links[level] = scored.into_iter().map(|s| s.1).collect();
+    }
+
+    fn insert(&mut self, document: IndexedDocument, vector: Vec<f32>) {
+        if let Some(dimensions) = self.dimensions {
+            if dimensions != vector.len() {
+                tracing::warn!(
+                    "Skipping document {} in vector index: {} dimensions, index uses {}",
+                    document.id,
+                    vector.len(),
+                    dimensions
+                );
+                return;
+            }
+        }
+        self.remove(&document.id);
+        self.dimensions = Some(vector.len());
+
+        let slot = self.nodes.len();
+        let level = self.random_level();
+        self.slots.insert(document.id.clone(), slot);
+        self.nodes.push(Node {
+            document,
+            vector,
+            links: vec![Vec::new(); level + 1],
+            deleted: false,
+        });
+
+        let Some(entry) = self.entry else {
+            self.entry = Some(slot);
+            self.max_level = level;
+            return;
+        };
+
+        let query = self.nodes[slot].vector.clone();
+        let mut entry = if level < self.max_level {
+            self.descend(&query, entry, level + 1)
+        } else {
+            entry
+        };
+
+        for layer in (0..=level.min(self.max_level)).rev() {
+            let candidates = self.search_layer(&query, entry, EF_CONSTRUCTION, layer);
+            let max_links = if layer == 0 { M0 } else { M };
+            let neighbours: Vec<usize> = candidates.iter().take(M).map(|s| s.1).collect();
+            for &neighbour in &neighbours {
+                self.nodes[neighbour].links[layer].push(slot);
+                if self.nodes[neighbour].links[layer].len() > max_links {
+                    self.prune(neighbour, layer, max_links);
+                }
+            }
+            self.nodes[slot].links[layer] = neighbours;
+            entry = candidates[0].1;
+        }
+
+        if level > self.max_level {
+            self.max_level = level;
+            self.entry = Some(slot);
+        }
+    }
+
+    /// Tombstone a document; the node stays in the graph for navigation until the next rebuild
+    fn remove(&mut self, id: &str) -> bool {
+        let Some(slot) = self.slots.remove(id) else {
+            return false;
+        };
+        self.nodes[slot].deleted = true;
+        self.deleted += 1;
+        if self.deleted >= REBUILD_THRESHOLD && self.deleted > self.slots.len() {
+            self.rebuild();
+        }
+        true
+    }
+
+    fn rebuild(&mut self) {
+        let live: Vec<Node> = std::mem::take(&mut self.nodes)
+            .into_iter()
+            .filter(|node| !node.deleted)
+            .collect();
+        let seed = self.seed;
+        *self = Self::new();
+        self.seed = seed;
+        for node in live {
+            self.insert(node.document, node.vector);
+        }
+    }
+
+    fn search(&self, query: &[f32], k: usize) -> Vec<(IndexedDocument, f64, Vec<f64>)> {
+        let Some(entry) = self.entry else {
+            return Vec::new();
+        };
+        if self.dimensions != Some(query.len()) {
+            tracing::warn!("Query embedding has {} dimensions, vector index uses {:?}", query.len(), self.dimensions);
+            return Vec::new();
+        }
+
+        let entry = if self.max_level > 0 {
+            self.descend(query, entry, 1)
+        } else {
+            entry
+        };
+        // Tombstones take up room in the candidate list, so widen it accordingly
+        let ef = EF_SEARCH.max(k).saturating_add(self.deleted.min(EF_SEARCH));
+        self.search_layer(query, entry, ef, 0)
+            .into_iter()
+            .filter(|s| !self.nodes[s.1].deleted)
+            .take(k)
+            .map(|s| {
+                let node = &self.nodes[s.1];
+                let vector = node.vector.iter().map(|&x| f64::from(x)).collect();
+                (node.document.clone(), f64::from(s.0), vector)
+            })
+            .collect()
+    }
+}
+
+/// Thread-safe vector index shared through `AppState`
+pub struct VectorIndex {
+    graph: RwLock<Hnsw>,
+}
+
+impl VectorIndex {
+    pub fn new() -> Self {
+        Self {
+            graph: RwLock::new(Hnsw::new()),
+        }
+    }
+
+    /// Rebuild the index from the chunks of the `knowledge` collection embedded with `model`,
+    /// returns the number of documents loaded
+    pub async fn reload(&self, db: &AppDatabase, model: &str) -> Result<usize, String> {
+        let mut cursor = db
+            .knowledge_collection()
+            .find(mongodb::bson::doc! { "embedding_model": model })
+            .await
+            .map_err(|e| format!("Failed to query documents: {}", e))?;
+
+        // Build off to the side so searches keep working during a reload
+        let mut graph = Hnsw::new();
+        while let Some(document) = cursor
+            .try_next()
+            .await
+            .map_err(|e| format!("Failed to read documents: {}", e))?
+        {
+            if let Some((indexed, vector)) = Self::prepare(&document) {
+                graph.insert(indexed, vector);
+            }
+        }
+
+        let loaded = graph.len();
+        *self.graph.write().unwrap_or_else(|e| e.into_inner()) = graph;
+        Ok(loaded)
+    }
+
+    fn prepare(document: &KnowledgeDocument) -> Option<(IndexedDocument, Vec<f32>)> {
+        let Some(vector) = normalize(&document.embedding) else {
+            tracing::warn!("Skipping document {} in vector index: empty embedding", document.id);
+            return None;
+        };
+        let indexed = IndexedDocument {
+            id: document.id.clone(),
+            parent_id: document.parent_id.clone().unwrap_or_else(|| document.id.clone()),
+            chunk_index: document.chunk_index,
+            title: document.title.clone(),
+            category: document.category.clone(),
+            content: document.content.clone(),
+            embedding_model: document.embedding_model.clone(),
+        };
+        Some((indexed, vector))
+    }
+
+    /// Add a document, replacing any previous version with the same id
+    pub fn upsert(&self, document: &KnowledgeDocument) {
+        if let Some((indexed, vector)) = Self::prepare(document) {
+            self.graph.write().unwrap_or_else(|e| e.into_inner()).insert(indexed, vector);
+        }
+    }
+
+    /// Drop a document from the index
+    pub fn remove(&self, id: &str) -> bool {
+        self.graph.write().unwrap_or_else(|e| e.into_inner()).remove(id)
+    }
+
+    /// The `k` documents most similar to `embedding`, best first, with their cosine similarity
+    /// and unit-length vector
+    pub fn search(&self, embedding: &[f64], k: usize) -> Vec<(IndexedDocument, f64, Vec<f64>)> {
+        let Some(query) = normalize(embedding) else {
+            return Vec::new();
+        };
+        self.graph.read().unwrap_or_else(|e| e.into_inner()).search(&query, k)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    const DIMENSIONS: usize = 16;
+
+    /// Deterministic pseudo-random vectors, so failures are reproducible
+    fn vectors(count: usize) -> Vec<Vec<f64>> {
+        let mut state: u64 = 42;
+        (0..count)
+            .map(|_| {
+                (0..DIMENSIONS)
+                    .map(|_| {
+                        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                        (state >> 33) as f64 / (1u64 << 31) as f64 - 0.5
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn chunk(id: usize, embedding: Vec<f64>) -> KnowledgeDocument {
+        KnowledgeDocument {
+            id: format!("doc-{}", id),
+            content: String::new(),
+            title: String::new(),
+            category: "general".to_string(),
+            embedding_dim: embedding.len() as u32,
+            embedding,
+            created_at: Utc::now(),
+            parent_id: None,
+            chunk_index: 0,
+            embedding_model: Some("test".to_string()),
+        }
+    }
+
+    fn cosine(a: &[f64], b: &[f64]) -> f64 {
+        let dot: f64 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+        let norm = |v: &[f64]| v.iter().map(|x| x * x).sum::<f64>().sqrt();
+        dot / (norm(a) * norm(b))
+    }
+
+    #[test]
+    fn search_matches_brute_force_on_a_small_set() {
+        let vectors = vectors(300);
+        let index = VectorIndex::new();
+        for (i, vector) in vectors.iter().enumerate() {
+            index.upsert(&chunk(i, vector.clone()));
+        }
+
+        let query = &vectors[7];
+        let results = index.search(query, 5);
+        assert_eq!(results.len(), 5);
+        assert_eq!(results[0].0.id, "doc-7");
+        assert!((results[0].1 - 1.0).abs() < 1e-5);
+        assert!(results.windows(2).all(|w| w[0].1 >= w[1].1));
+
+        let mut exact: Vec<(usize, f64)> = vectors.iter().enumerate().map(|(i, v)| (i, cosine(query, v))).collect();
+        exact.sort_by(|a, b| b.1.total_cmp(&a.1));
+        let found: HashSet<String> = results.iter().map(|(doc, _, _)| doc.id.clone()).collect();
+        let recalled = exact[..5].iter().filter(|(i, _)| found.contains(&format!("doc-{}", i))).count();
+        assert!(recalled >= 4, "recall {}/5", recalled);
+    }
+
+    #[test]
+    fn removed_and_replaced_documents_are_not_returned_stale() {
+        let vectors = vectors(50);
+        let index = VectorIndex::new();
+        for (i, vector) in vectors.iter().enumerate() {
+            index.upsert(&chunk(i, vector.clone()));
+        }
+
+        assert!(index.remove("doc-3"));
+        assert!(!index.remove("doc-3"));
+        assert!(index.search(&vectors[3], 10).iter().all(|(doc, _, _)| doc.id != "doc-3"));
+
+        // Re-inserting an id moves it to its new vector
+        index.upsert(&chunk(4, vectors[9].iter().map(|x| -x).collect()));
+        let negated: Vec<f64> = vectors[9].iter().map(|x| -x).collect();
+        assert_eq!(index.search(&negated, 1)[0].0.id, "doc-4");
+    }
+
+    #[test]
+    fn empty_vectors_are_skipped() {
+        let index = VectorIndex::new();
+        index.upsert(&chunk(0, Vec::new()));
+        assert!(index.search(&[1.0; DIMENSIONS], 3).is_empty());
+        assert!(index.search(&[], 3).is_empty());
+    }
+}